@@ -0,0 +1,54 @@
+--
+-- PostgreSQL database dump
+--
+
+SET statement_timeout = 0;
+SET client_encoding = 'UTF8';
+
+CREATE TABLE public.albums (
+    id uuid DEFAULT public.uuid_generate_v4() NOT NULL,
+    "ownerId" uuid NOT NULL,
+    "albumName" character varying DEFAULT 'Untitled Album'::character varying NOT NULL,
+    "createdAt" timestamp with time zone DEFAULT now() NOT NULL,
+    "deletedAt" timestamp with time zone
+);
+
+--
+-- Data for Name: albums; Type: TABLE DATA; Schema: public; Owner: postgres
+--
+
+COPY public.albums (id, "ownerId", "albumName", "createdAt", "deletedAt") FROM stdin;
+a1	u1	Summer	2024-07-01 10:00:00+00	\N
+a2	u1	Tabs\tand \\ backslashes	2024-07-02 10:00:00+00	\N
+a3	u1	Deleted album	2024-07-03 10:00:00+00	2024-08-01 10:00:00+00
+\.
+
+
+--
+-- Data for Name: albums_assets_assets; Type: TABLE DATA; Schema: public; Owner: postgres
+--
+
+COPY public.albums_assets_assets ("albumsId", "assetsId", "createdAt") FROM stdin;
+a1	x1	2024-07-01 10:00:00+00
+a1	x2	2024-07-01 10:00:00+00
+a2	x3	2024-07-02 10:00:00+00
+a2	x4	2024-07-02 10:00:00+00
+a3	x1	2024-07-03 10:00:00+00
+\.
+
+
+--
+-- Data for Name: assets; Type: TABLE DATA; Schema: public; Owner: postgres
+--
+
+COPY public.assets (id, "deviceAssetId", "ownerId", "originalPath", "deletedAt", checksum) FROM stdin;
+x1	IMG_0001.jpg-123	u1	/usr/src/app/upload/library/admin/2024/summer/IMG_0001.jpg	\N	\\x0a1b
+x2	IMG_0002.jpg-456	u1	/usr/src/app/upload/library/admin/2024/summer/IMG_0002.jpg	\N	\\x0a1c
+x3	scan.png-789	u1	/mnt/external/scans/scan.png	\N	\\x0a1d
+x4	trashed.jpg-000	u1	/usr/src/app/upload/library/admin/2024/tabs/trashed.jpg	2024-09-01 10:00:00+00	\\x0a1e
+\.
+
+
+--
+-- PostgreSQL database dump complete
+--
@@ -0,0 +1,17 @@
+--
+-- PostgreSQL database dump
+--
+
+COPY public.album (id, "ownerId", "albumName", "deletedAt") FROM stdin;
+b1	u1	Winter	\N
+\.
+
+COPY public.album_asset ("albumId", "assetId", "createdAt") FROM stdin;
+b1	y1	2025-01-01 10:00:00+00
+b1	y2	2025-01-01 10:00:00+00
+\.
+
+COPY public.asset (id, "ownerId", "originalPath", "deletedAt") FROM stdin;
+y1	u1	/data/library/admin/2025/winter/snow.heic	\N
+y2	u1	/data/library/admin/2025/winter/line\nbreak.jpg	\N
+\.
@@ -0,0 +1,279 @@
+use crate::prelude::*;
+use crate::progress;
+use signal_hook::consts::{SIGINT, SIGTERM};
+use signal_hook::iterator::Signals;
+use std::sync::atomic::{AtomicI32, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+type Listener = Box<dyn Fn() + Send>;
+
+/// Shared flag telling every part of a run to stop scheduling new work.
+///
+/// Cancellation is cooperative and has two stages. After the first signal the run is cancelled:
+/// the running directory is allowed to finish, but `traverse` doesn't start the next one. A
+/// second signal aborts the run: the executor terminates its running child. `main` exits with
+/// the first signal's exit code once the summary has been written.
+#[derive(Clone, Default)]
+pub struct CancellationToken {
+    inner: Arc<Inner>,
+}
+
+#[derive(Default)]
+struct Inner {
+    /// Signal number that cancelled the run, 0 while running.
+    signal: AtomicI32,
+    /// Number of signals received so far.
+    count: AtomicUsize,
+    next_listener_id: AtomicUsize,
+    listeners: Mutex<Vec<(usize, Listener)>>,
+}
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Cancel the token when SIGINT or SIGTERM is received. With `announce` every stage is also
+    /// shown on stderr, whatever the log level, so the terminal tells what the run is doing.
+    pub fn install_signal_handlers(&self, announce: bool) -> Result<()> {
+        let mut signals =
+            Signals::new([SIGINT, SIGTERM]).context("Failed to register signal handlers")?;
+
+        let token = self.clone();
+        std::thread::spawn(move || {
+            for signal in signals.forever() {
+                token.cancel(signal);
+                if let Some(message) = announce
+                    .then(|| stage_message(token.inner.count.load(Ordering::SeqCst)))
+                    .flatten()
+                {
+                    progress::eprint_line(message);
+                }
+            }
+        });
+
+        Ok(())
+    }
+
+    /// Record a signal: the first one cancels the run, the second one aborts it.
+    pub fn cancel(&self, signal: i32) {
+        let _ = self
+            .inner
+            .signal
+            .compare_exchange(0, signal, Ordering::SeqCst, Ordering::SeqCst);
+
+        if let Some(message) = stage_message(self.inner.count.fetch_add(1, Ordering::SeqCst) + 1) {
+            info!("{}", message);
+        }
+
+        for (_, listener) in self.inner.listeners.lock().unwrap().iter() {
+            listener();
+        }
+    }
+
+    /// Raise the number of cancellations to `count` without a signal, notifying the listeners
+    /// if that changed anything.
+    fn raise_to(&self, count: usize) {
+        if self.inner.count.fetch_max(count, Ordering::SeqCst) >= count {
+            return;
+        }
+        for (_, listener) in self.inner.listeners.lock().unwrap().iter() {
+            listener();
+        }
+    }
+
+    /// Cancel the run, or abort it when it is already cancelled, like a signal that leaves the
+    /// exit code alone. For stopping the run from the dashboard.
+    pub fn escalate(&self) {
+        self.raise_to(self.inner.count.load(Ordering::SeqCst) + 1);
+    }
+
+    /// Abort the work in progress without a signal.
+    pub fn abort(&self) {
+        self.raise_to(2);
+    }
+
+    /// A token for a single directory: cancelled and aborted along with this one until the
+    /// guard is dropped, and aborted on its own to skip just that directory.
+    pub fn child(&self) -> (Self, CancelGuard) {
+        let child = Self::new();
+        let follower = child.clone();
+        let parent = Arc::downgrade(&self.inner);
+        let guard = self.on_cancel(move || {
+            if let Some(parent) = parent.upgrade() {
+                follower.raise_to(parent.count.load(Ordering::SeqCst));
+            }
+        });
+        (child, guard)
+    }
+
+    /// No new work should be started.
+    pub fn is_cancelled(&self) -> bool {
+        self.inner.count.load(Ordering::SeqCst) >= 1
+    }
+
+    /// Work in progress should be terminated as well.
+    pub fn is_aborted(&self) -> bool {
+        self.inner.count.load(Ordering::SeqCst) >= 2
+    }
+
+    /// Conventional shell exit code for the signal that cancelled the run.
+    pub fn exit_code(&self) -> Option<u8> {
+        match self.inner.signal.load(Ordering::SeqCst) {
+            0 => None,
+            signal => Some(128u8.saturating_add(signal as u8)),
+        }
+    }
+
+    /// Call `listener` on cancellation until the returned guard is dropped. The listener is called
+    /// right away when the token is already cancelled. Listeners must not block.
+    pub fn on_cancel(&self, listener: impl Fn() + Send + 'static) -> CancelGuard {
+        let id = self.inner.next_listener_id.fetch_add(1, Ordering::SeqCst);
+        self.inner
+            .listeners
+            .lock()
+            .unwrap()
+            .push((id, Box::new(listener)));
+
+        if self.is_cancelled() {
+            if let Some((_, listener)) = self
+                .inner
+                .listeners
+                .lock()
+                .unwrap()
+                .iter()
+                .find(|(listener_id, _)| *listener_id == id)
+            {
+                listener();
+            }
+        }
+
+        CancelGuard {
+            token: self.clone(),
+            id,
+        }
+    }
+}
+
+/// Unregisters a cancellation listener when dropped.
+pub struct CancelGuard {
+    token: CancellationToken,
+    id: usize,
+}
+
+impl Drop for CancelGuard {
+    fn drop(&mut self) {
+        self.token
+            .inner
+            .listeners
+            .lock()
+            .unwrap()
+            .retain(|(id, _)| *id != self.id);
+    }
+}
+
+/// What the run does after its `count`th signal.
+fn stage_message(count: usize) -> Option<&'static str> {
+    match count {
+        1 => Some(
+            "Signal received: finishing the current directory, then stopping. Press Ctrl+C again to abort it",
+        ),
+        2 => Some("Second signal received: aborting the current directory"),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use std::sync::atomic::AtomicBool;
+
+    #[test]
+    fn test_cancel_sets_exit_code() {
+        let token = CancellationToken::new();
+        assert!(!token.is_cancelled());
+        assert_eq!(token.exit_code(), None);
+
+        token.cancel(SIGTERM);
+        token.cancel(SIGINT);
+
+        assert!(token.is_cancelled());
+        assert_eq!(token.exit_code(), Some(143));
+    }
+
+    #[test]
+    fn test_second_signal_aborts() {
+        let token = CancellationToken::new();
+
+        token.cancel(SIGINT);
+        assert!(token.is_cancelled());
+        assert!(!token.is_aborted());
+
+        token.cancel(SIGINT);
+        assert!(token.is_aborted());
+        assert_eq!(token.exit_code(), Some(130));
+    }
+
+    #[test]
+    fn test_on_cancel_notifies_until_guard_dropped() {
+        let token = CancellationToken::new();
+        let calls = Arc::new(AtomicUsize::new(0));
+
+        let counter = calls.clone();
+        let guard = token.on_cancel(move || {
+            counter.fetch_add(1, Ordering::SeqCst);
+        });
+        token.cancel(SIGINT);
+        token.cancel(SIGINT);
+        drop(guard);
+        token.cancel(SIGINT);
+
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn test_child_follows_parent() {
+        let token = CancellationToken::new();
+        let (child, guard) = token.child();
+
+        child.abort();
+        assert!(child.is_aborted());
+        assert!(!token.is_cancelled());
+
+        let (child, _guard) = token.child();
+        token.cancel(SIGINT);
+        assert!(child.is_cancelled());
+        assert!(!child.is_aborted());
+        token.escalate();
+        assert!(child.is_aborted());
+        assert_eq!(token.exit_code(), Some(130));
+
+        drop(guard);
+        let (late_child, _guard) = token.child();
+        assert!(late_child.is_aborted());
+    }
+
+    #[test]
+    fn test_escalate_leaves_exit_code() {
+        let token = CancellationToken::new();
+        token.escalate();
+        assert!(token.is_cancelled());
+        assert!(!token.is_aborted());
+        token.escalate();
+        assert!(token.is_aborted());
+        assert_eq!(token.exit_code(), None);
+    }
+
+    #[test]
+    fn test_on_cancel_after_cancellation_notifies_immediately() {
+        let token = CancellationToken::new();
+        token.cancel(SIGINT);
+
+        let called = Arc::new(AtomicBool::new(false));
+        let flag = called.clone();
+        let _guard = token.on_cancel(move || flag.store(true, Ordering::SeqCst));
+
+        assert!(called.load(Ordering::SeqCst));
+    }
+}
@@ -0,0 +1,331 @@
+use crate::execute::ExecuteError;
+use crate::upload_stats::UploadStats;
+
+/// Messages of a missing login, a rejected API key or a key without the needed permissions.
+const AUTH_PATTERNS: &[&str] = &[
+    "No auth file exists",
+    "Please login first",
+    "Invalid API key",
+    "Invalid user token",
+    "Missing required permission",
+    "\"statusCode\":401",
+    "\"statusCode\":403",
+];
+/// Prefixes of Node's TLS error codes, matched only against the code of an error so that file
+/// names like `BIRTH_CERT_2020.jpg` don't look like a TLS failure.
+const TLS_CODE_PREFIXES: &[&str] = &[
+    "CERT_",
+    "DEPTH_ZERO_SELF_SIGNED_CERT",
+    "SELF_SIGNED_CERT_IN_CHAIN",
+    "UNABLE_TO_VERIFY_LEAF_SIGNATURE",
+    "UNABLE_TO_GET_ISSUER_CERT",
+    "ERR_TLS_",
+    "ERR_SSL_",
+];
+/// Node's network error codes.
+const UNREACHABLE_PATTERNS: &[&str] = &[
+    "ECONNREFUSED",
+    "ENOTFOUND",
+    "EHOSTUNREACH",
+    "ENETUNREACH",
+    "ECONNRESET",
+    "ETIMEDOUT",
+    "EAI_AGAIN",
+    "UND_ERR_CONNECT_TIMEOUT",
+];
+const DISK_FULL_PATTERNS: &[&str] = &["ENOSPC", "no space left on device"];
+const SERVER_ERROR_PATTERNS: &[&str] = &[
+    "Internal Server Error",
+    "Bad Gateway",
+    "Service Unavailable",
+    "Gateway Timeout",
+];
+const UNSUPPORTED_FILE_PATTERNS: &[&str] = &["Unsupported file type", "Unsupported media type"];
+/// Lines of a Node error the CLI prints besides its `Error:` line: the error it was caused by
+/// and Node's error code.
+const ERROR_DETAIL_PREFIXES: &[&str] = &["[cause]:", "code: '"];
+
+/// Classify the result of an `immich upload` run from its exit code, the last lines of its
+/// output and `stats`, parsed from all of the output.
+///
+/// The numbers of uploaded and failed files come from `stats`, as the summary may have scrolled
+/// out of `output` when many files failed. The CLI lists every file it failed to upload as
+/// `- <path>: <error>`. Only the error part of those lines is looked at, so paths containing
+/// words like "auth" can't cause a misfire; they decide between an unsupported file and a
+/// partial success. Failed files are reported whatever the exit code, as the CLI may exit with
+/// 0 after failing some of them. When the CLI failed, its error lines are checked for failures
+/// affecting the whole run, most specific first. Other output, like progress lines naming the
+/// files, is never looked at.
+pub fn classify(
+    exit_code: i32,
+    output: &[String],
+    stats: &UploadStats,
+) -> Result<(), ExecuteError> {
+    let mut file_failures = Vec::new();
+
+    for line in output {
+        let line = line.trim_end();
+        if let Some(failure) = line.strip_prefix("- ") {
+            let message = failure
+                .rsplit_once(": ")
+                .map_or(failure, |(_, message)| message);
+            file_failures.push((line, message));
+            continue;
+        }
+
+        if exit_code == 0 || !is_error_line(line) {
+            continue;
+        }
+
+        let message = || line.trim().to_string();
+        if matches_any(line, AUTH_PATTERNS) {
+            return Err(ExecuteError::AuthFailed(message()));
+        }
+        if error_code(line).is_some_and(|code| {
+            TLS_CODE_PREFIXES
+                .iter()
+                .any(|prefix| code.starts_with(prefix))
+        }) {
+            return Err(ExecuteError::Tls(message()));
+        }
+        if matches_any(line, UNREACHABLE_PATTERNS) {
+            return Err(ExecuteError::ServerUnreachable(message()));
+        }
+        if matches_any(line, DISK_FULL_PATTERNS) {
+            return Err(ExecuteError::ServerDiskFull(message()));
+        }
+        if matches_any(line, SERVER_ERROR_PATTERNS) || has_server_error_status(line) {
+            return Err(ExecuteError::ServerError(message()));
+        }
+    }
+
+    // The count of the CLI's summary, or the files it listed if it printed none
+    let failed = stats.failed.max(file_failures.len());
+    if failed > 0 {
+        let summary = format!(
+            "{} of {} files failed to upload",
+            failed,
+            failed + stats.uploaded
+        );
+        if !file_failures.is_empty()
+            && file_failures
+                .iter()
+                .all(|(_, message)| matches_any(message, UNSUPPORTED_FILE_PATTERNS))
+        {
+            return Err(ExecuteError::UnsupportedFile(format!(
+                "{}: unsupported file type",
+                summary
+            )));
+        }
+        return Err(ExecuteError::PartialSuccess(summary));
+    }
+
+    if exit_code != 0 {
+        let last_line = output
+            .iter()
+            .rev()
+            .find(|line| !line.trim().is_empty())
+            .map_or("no output", |line| line.trim());
+        return Err(ExecuteError::Other(anyhow::anyhow!(
+            "Exit code {}: {}",
+            exit_code,
+            last_line
+        )));
+    }
+
+    Ok(())
+}
+
+/// An `Error:` line (`TypeError:`, ...), a detail of the error, or a JSON error body.
+fn is_error_line(line: &str) -> bool {
+    let line = line.trim_start();
+    line.split_whitespace()
+        .next()
+        .is_some_and(|word| word.ends_with("Error:"))
+        || ERROR_DETAIL_PREFIXES
+            .iter()
+            .any(|prefix| line.starts_with(prefix))
+        || (line.starts_with('{') && line.contains("\"statusCode\":"))
+}
+
+/// The code of a Node error from its `code: '<code>'` line.
+fn error_code(line: &str) -> Option<&str> {
+    let code = line.trim().strip_prefix("code: '")?;
+    code.split_once('\'').map(|(code, _)| code)
+}
+
+fn matches_any(line: &str, patterns: &[&str]) -> bool {
+    patterns.iter().any(|pattern| line.contains(pattern))
+}
+
+/// `"statusCode":5xx` in a JSON error body.
+fn has_server_error_status(line: &str) -> bool {
+    line.match_indices("\"statusCode\":").any(|(index, key)| {
+        let status = &line[index + key.len()..];
+        status.len() >= 3
+            && status.starts_with('5')
+            && status[..3].chars().all(|c| c.is_ascii_digit())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use rstest::rstest;
+
+    /// `classify` with the statistics of the whole output, as the executor passes them.
+    fn classify_output(exit_code: i32, output: &[String]) -> Result<(), ExecuteError> {
+        let mut stats = UploadStats::default();
+        for line in output {
+            stats.parse_line(line);
+        }
+        classify(exit_code, output, &stats)
+    }
+
+    fn fixture(name: &str) -> Vec<String> {
+        let path = format!(
+            "{}/fixtures/cli_output/{}.txt",
+            env!("CARGO_MANIFEST_DIR"),
+            name
+        );
+        std::fs::read_to_string(&path)
+            .unwrap_or_else(|e| panic!("Failed to read {path}: {e}"))
+            .lines()
+            .map(str::to_string)
+            .collect()
+    }
+
+    /// Name of the `ExecuteError` variant, or "Ok".
+    fn variant(result: &Result<(), ExecuteError>) -> &'static str {
+        match result {
+            Ok(()) => "Ok",
+            Err(ExecuteError::AuthFailed(_)) => "AuthFailed",
+            Err(ExecuteError::Tls(_)) => "Tls",
+            Err(ExecuteError::ServerUnreachable(_)) => "ServerUnreachable",
+            Err(ExecuteError::ServerDiskFull(_)) => "ServerDiskFull",
+            Err(ExecuteError::ServerError(_)) => "ServerError",
+            Err(ExecuteError::UnsupportedFile(_)) => "UnsupportedFile",
+            Err(ExecuteError::PartialSuccess(_)) => "PartialSuccess",
+            Err(ExecuteError::Other(_)) => "Other",
+            Err(_) => "unexpected",
+        }
+    }
+
+    #[rstest]
+    #[case("success", 0, "Ok")]
+    #[case("auth_not_logged_in", 1, "AuthFailed")]
+    #[case("auth_invalid_key", 1, "AuthFailed")]
+    #[case("unreachable_refused", 1, "ServerUnreachable")]
+    #[case("unreachable_dns", 1, "ServerUnreachable")]
+    #[case("tls_self_signed", 1, "Tls")]
+    #[case("tls_expired", 1, "Tls")]
+    #[case("server_bad_gateway", 1, "ServerError")]
+    #[case("server_internal_error", 1, "ServerError")]
+    #[case("server_disk_full", 1, "ServerDiskFull")]
+    #[case("unsupported_file", 1, "UnsupportedFile")]
+    #[case("partial_success", 1, "PartialSuccess")]
+    #[case("partial_success", 0, "PartialSuccess")]
+    #[case("missing_directory", 1, "Other")]
+    #[case("success_error_tokens", 0, "Ok")]
+    #[case("success_error_tokens", 1, "Other")]
+    fn test_classify_fixture(#[case] name: &str, #[case] exit_code: i32, #[case] expected: &str) {
+        assert_eq!(
+            variant(&classify_output(exit_code, &fixture(name))),
+            expected
+        );
+    }
+
+    #[test]
+    fn test_classify_partial_success_counts_files() {
+        match classify_output(1, &fixture("partial_success")) {
+            Err(ExecuteError::PartialSuccess(message)) => {
+                assert_eq!(message, "2 of 12 files failed to upload")
+            }
+            other => panic!("Expected a partial success, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_classify_counts_failures_beyond_the_tail() {
+        // The summary scrolled out of the tail, only the last failures are left
+        let tail: Vec<String> = (100..300)
+            .map(|i| format!("- /photos/IMG_{i:04}.jpg: Error: socket hang up"))
+            .collect();
+        let stats = UploadStats {
+            new: 310,
+            uploaded: 10,
+            failed: 300,
+            ..UploadStats::default()
+        };
+
+        match classify(1, &tail, &stats) {
+            Err(ExecuteError::PartialSuccess(message)) => {
+                assert_eq!(message, "300 of 310 files failed to upload")
+            }
+            other => panic!("Expected a partial success, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_classify_file_names_are_no_tls_codes() {
+        let output = [
+            "Error: ENOENT: no such file or directory, open '/photos/BIRTH_CERT_2020.jpg'",
+            "  [cause]: Error: ERR_TLS_CERT_ALTNAME_INVALID.png is gone",
+        ]
+        .map(String::from);
+        assert_eq!(variant(&classify_output(1, &output)), "Other");
+    }
+
+    #[rstest]
+    #[case("    code: 'CERT_HAS_EXPIRED'", Some("CERT_HAS_EXPIRED"))]
+    #[case("code: 'ECONNREFUSED',", Some("ECONNREFUSED"))]
+    #[case("code: 'CERT_", None)]
+    #[case("Error: code: 'CERT_HAS_EXPIRED'", None)]
+    fn test_error_code(#[case] line: &str, #[case] expected: Option<&str>) {
+        assert_eq!(error_code(line), expected);
+    }
+
+    #[test]
+    fn test_classify_failures_of_a_successful_exit() {
+        let output = [
+            "Found 2 new files and 0 duplicates",
+            "Failed to upload 2 assets:",
+            "- /photos/IMG_0001.jpg: Error: socket hang up",
+            "- /photos/IMG_0002.jpg: Error: socket hang up",
+        ]
+        .map(String::from);
+
+        match classify_output(0, &output) {
+            Err(ExecuteError::PartialSuccess(message)) => {
+                assert_eq!(message, "2 of 2 files failed to upload")
+            }
+            other => panic!("Expected a partial success, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_classify_failure_without_output() {
+        assert_eq!(variant(&classify_output(2, &[])), "Other");
+    }
+
+    #[rstest]
+    #[case("Error: connect ECONNREFUSED 192.168.1.20:2283", true)]
+    #[case("TypeError: fetch failed", true)]
+    #[case("  [cause]: Error: self-signed certificate", true)]
+    #[case("    code: 'CERT_HAS_EXPIRED'", true)]
+    #[case(r#"{"message":"Bad Gateway","statusCode":502}"#, true)]
+    #[case("Progress | 100% || 4/4 || Current file [BIRTH_CERT.jpg]", false)]
+    #[case("Uploading /photos/Error: notes/IMG_0001.jpg", false)]
+    fn test_is_error_line(#[case] line: &str, #[case] expected: bool) {
+        assert_eq!(is_error_line(line), expected);
+    }
+
+    #[rstest]
+    #[case(r#"{"statusCode":503}"#, true)]
+    #[case(r#"{"statusCode":404}"#, false)]
+    #[case(r#"{"statusCode":5"#, false)]
+    fn test_has_server_error_status(#[case] line: &str, #[case] expected: bool) {
+        assert_eq!(has_server_error_status(line), expected);
+    }
+}
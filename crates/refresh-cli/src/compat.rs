@@ -0,0 +1,176 @@
+use crate::prelude::*;
+use serde::Deserialize;
+use std::fmt;
+
+/// Oldest CLI with the `immich upload [paths...]` command line. The legacy CLI before it took the
+/// server and key as flags and has no `-A`.
+const MIN_CLI_VERSION: Version = Version::new(2, 0, 0);
+/// First CLI with `-H` (`--include-hidden`) and `-c` (`--concurrency`).
+const HIDDEN_AND_CONCURRENCY_SINCE: Version = Version::new(2, 0, 6);
+/// The server renamed its API routes (`/asset` to `/assets`, ...) in this version; CLIs before
+/// [`CLI_FOR_RENAMED_API`] only speak the old routes, later ones only the new routes.
+const RENAMED_API_SERVER: Version = Version::new(1, 106, 0);
+const CLI_FOR_RENAMED_API: Version = Version::new(2, 2, 0);
+
+/// A `major.minor.patch` version of the Immich CLI or server.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize)]
+pub struct Version {
+    pub major: u32,
+    pub minor: u32,
+    pub patch: u32,
+}
+
+impl Version {
+    pub const fn new(major: u32, minor: u32, patch: u32) -> Self {
+        Self {
+            major,
+            minor,
+            patch,
+        }
+    }
+
+    /// Parse `2.2.3`, `v2.2.3` or `2.2.3-beta.1`; pre-release and build suffixes are ignored.
+    pub fn parse(version: &str) -> Option<Self> {
+        let version = version.trim();
+        let version = version.strip_prefix('v').unwrap_or(version);
+        let core = version.split(['-', '+']).next()?;
+        let mut parts = core.split('.').map(|part| part.parse().ok());
+        let version = Self::new(parts.next()??, parts.next()??, parts.next()??);
+        parts.next().is_none().then_some(version)
+    }
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)
+    }
+}
+
+/// Which optional `immich upload` flags the installed CLI understands.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CliFlags {
+    /// `-H`
+    pub include_hidden: bool,
+    /// `-c <n>`
+    pub concurrency: bool,
+}
+
+impl Default for CliFlags {
+    /// The flags of current CLIs.
+    fn default() -> Self {
+        Self {
+            include_hidden: true,
+            concurrency: true,
+        }
+    }
+}
+
+/// The flag set for the CLI reporting `cli_version`, after checking it can talk to a server of
+/// `server_version`. A version that can't be parsed (e.g. from a wrapper script) gets the flags of
+/// current CLIs.
+pub fn cli_flags(cli_version: &str, server_version: Option<Version>) -> Result<CliFlags> {
+    let Some(cli) = Version::parse(cli_version) else {
+        info!(
+            "Unknown Immich CLI version '{}', assuming it takes the current upload flags",
+            cli_version
+        );
+        return Ok(CliFlags::default());
+    };
+
+    if cli < MIN_CLI_VERSION {
+        bail!(
+            "Immich CLI {} is not supported: the legacy CLI has a different command line. Install version {} or later with 'npm install -g @immich/cli'",
+            cli,
+            MIN_CLI_VERSION
+        );
+    }
+    if let Some(server) = server_version {
+        if server >= RENAMED_API_SERVER && cli < CLI_FOR_RENAMED_API {
+            bail!(
+                "Immich CLI {} can't talk to server {}: servers from {} on need CLI {} or later. Update it with 'npm install -g @immich/cli'",
+                cli,
+                server,
+                RENAMED_API_SERVER,
+                CLI_FOR_RENAMED_API
+            );
+        }
+        if server < RENAMED_API_SERVER && cli >= CLI_FOR_RENAMED_API {
+            bail!(
+                "Immich CLI {} can't talk to server {}: it needs server {} or later. Update the server or install an older CLI, e.g. 'npm install -g @immich/cli@2.1'",
+                cli,
+                server,
+                RENAMED_API_SERVER
+            );
+        }
+    }
+
+    if cli < HIDDEN_AND_CONCURRENCY_SINCE {
+        info!(
+            "Immich CLI {} has no -H and -c flags: hidden files are skipped and the CLI's own concurrency is used",
+            cli
+        );
+        return Ok(CliFlags {
+            include_hidden: false,
+            concurrency: false,
+        });
+    }
+    Ok(CliFlags::default())
+}
+
+/// The API calls this tool makes itself need the routes introduced with [`RENAMED_API_SERVER`].
+pub fn check_server_version(server_version: Version) -> Result<()> {
+    if server_version < RENAMED_API_SERVER {
+        bail!(
+            "Immich server {} is not supported, relink and reconcile need {} or later",
+            server_version,
+            RENAMED_API_SERVER
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use rstest::rstest;
+
+    #[rstest]
+    #[case("2.2.3", Some(Version::new(2, 2, 3)))]
+    #[case("v1.106.4\n", Some(Version::new(1, 106, 4)))]
+    #[case("2.3.0-beta.1", Some(Version::new(2, 3, 0)))]
+    #[case("2.2", None)]
+    #[case("2.2.3.4", None)]
+    #[case("", None)]
+    #[case("immich 2.2.3", None)]
+    fn test_version_parse(#[case] version: &str, #[case] expected: Option<Version>) {
+        assert_eq!(Version::parse(version), expected);
+    }
+
+    #[rstest]
+    #[case("2.2.3", None, Some((true, true)))]
+    #[case("2.2.3", Some(Version::new(1, 118, 2)), Some((true, true)))]
+    #[case("2.0.3", None, Some((false, false)))]
+    #[case("2.1.0", Some(Version::new(1, 105, 1)), Some((true, true)))]
+    #[case("wrapper", Some(Version::new(1, 118, 2)), Some((true, true)))]
+    #[case("0.41.0", None, None)]
+    #[case("2.1.0", Some(Version::new(1, 118, 2)), None)]
+    #[case("2.2.3", Some(Version::new(1, 105, 1)), None)]
+    fn test_cli_flags(
+        #[case] cli_version: &str,
+        #[case] server_version: Option<Version>,
+        #[case] expected: Option<(bool, bool)>,
+    ) {
+        let flags = cli_flags(cli_version, server_version).ok();
+        assert_eq!(
+            flags.map(|flags| (flags.include_hidden, flags.concurrency)),
+            expected
+        );
+    }
+
+    #[test]
+    fn test_check_server_version() {
+        assert!(check_server_version(Version::new(1, 106, 0)).is_ok());
+        assert!(check_server_version(Version::new(1, 105, 1)).is_err());
+    }
+}
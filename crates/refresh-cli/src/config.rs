@@ -0,0 +1,969 @@
+use crate::prelude::*;
+use crate::users::UserRoute;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+const DEFAULT_CLI_COMMAND: &str = "immich";
+const DEFAULT_CONCURRENCY: u32 = 24;
+const DEFAULT_CONTAINER_IMAGE: &str = "ghcr.io/immich-app/immich-cli:latest";
+/// `container_runtime` value that runs the CLI directly, for profiles overriding a container
+/// default.
+const NATIVE_RUNTIME: &str = "native";
+/// Upload flags that delete or modify local files. They are only passed on when explicitly
+/// allowed.
+const DESTRUCTIVE_UPLOAD_FLAGS: &[&str] = &["--delete", "--delete-duplicates"];
+/// Upload flags immich-refresh sets itself: the album, what is scanned, the dry run and the
+/// server. Passing them again would upload somewhere else than planned, or never exit with
+/// `--watch`.
+const RESERVED_UPLOAD_FLAGS: &[&str] = &[
+    "-A",
+    "--album-name",
+    "-a",
+    "--album",
+    "-H",
+    "--include-hidden",
+    "-r",
+    "--recursive",
+    "-c",
+    "--concurrency",
+    "-n",
+    "--dry-run",
+    "--watch",
+    "-u",
+    "--url",
+    "-k",
+    "--key",
+    "-d",
+    "--config-directory",
+];
+const DEFAULT_MAX_LOG_FILES: usize = 30;
+const DEFAULT_MAX_RUNS: usize = 100;
+const DEFAULT_SYSLOG_ADDRESS: &str = "/dev/log";
+/// Shown instead of secrets in debug output.
+const REDACTED: &str = "<redacted>";
+
+/// The optional `config.toml`: defaults for every run plus named profiles that override them.
+///
+/// ```toml
+/// [cli]
+/// concurrency = 8
+///
+/// [profiles.nas.cli]
+/// path = "/opt/immich-cli/bin/immich"
+/// extra_args = ["--skip-hash"]
+///
+/// [profiles.nas.server]
+/// url = "http://nas.lan:2283"
+/// api_key_command = ["pass", "show", "immich/nas"]
+/// ```
+#[derive(Debug, Default, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub cli: CliOverrides,
+    pub server: ServerOverrides,
+    pub profiles: BTreeMap<String, Profile>,
+    /// Immich accounts that top-level directories are uploaded to instead of the run's account.
+    pub users: BTreeMap<String, User>,
+    pub log: LogSettings,
+}
+
+/// What is logged, the log file, its format, when it is rotated and how many rotated files and
+/// runs are kept, and the system logs that also get every event.
+///
+/// ```toml
+/// [log]
+/// level = "info"
+/// echo_cli_output = false
+/// file = "/var/log/immich-refresh/run.log"
+/// file_in_dry_run = true
+/// format = "json"
+/// rotation = "daily"
+/// max_size = "50MB"
+/// max_files = 30
+/// max_age_days = 90
+/// compress = true
+/// max_runs = 100
+/// journald = true
+///
+/// [log.modules]
+/// execute = "debug"
+///
+/// [log.syslog]
+/// address = "logs.lan:514"
+/// facility = "local3"
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LogSettings {
+    /// `error`, `warn`, `info`, `debug` or `trace`; `info` when not set.
+    pub level: Option<Box<str>>,
+    /// Levels for single modules, e.g. `execute = "debug"`, overriding `level`.
+    pub modules: BTreeMap<String, Box<str>>,
+    /// Print the Immich CLI's output to the terminal. It is logged either way.
+    pub echo_cli_output: bool,
+    /// Log file instead of `run.log` in the state directory.
+    pub file: Option<Box<str>>,
+    /// Also write the log file in dry runs, which otherwise only log to stdout.
+    pub file_in_dry_run: bool,
+    pub format: LogFormat,
+    pub rotation: Rotation,
+    /// Rotate once the file would grow beyond this size, e.g. `"50MB"`.
+    #[serde(deserialize_with = "deserialize_size")]
+    pub max_size: Option<u64>,
+    /// Rotated files to keep; older ones are deleted. `0` keeps all of them.
+    pub max_files: usize,
+    /// Delete rotated files older than this.
+    pub max_age_days: Option<u64>,
+    /// Gzip rotated files.
+    pub compress: bool,
+    /// Runs whose own log file and record are kept for `history`. `0` keeps all of them.
+    pub max_runs: usize,
+    /// Send events to the systemd journal with their span fields, e.g. `ALBUM=`.
+    pub journald: bool,
+    /// Send events to syslog in RFC 5424 format.
+    pub syslog: Option<SyslogSettings>,
+}
+
+impl Default for LogSettings {
+    fn default() -> Self {
+        Self {
+            level: None,
+            modules: BTreeMap::new(),
+            echo_cli_output: true,
+            file: None,
+            file_in_dry_run: false,
+            format: LogFormat::Text,
+            rotation: Rotation::Daily,
+            max_size: None,
+            max_files: DEFAULT_MAX_LOG_FILES,
+            max_age_days: None,
+            compress: true,
+            max_runs: DEFAULT_MAX_RUNS,
+            journald: false,
+            syslog: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SyslogSettings {
+    /// A Unix socket path like `/dev/log`, or `host:port` for UDP.
+    pub address: Box<str>,
+    pub facility: Facility,
+}
+
+impl Default for SyslogSettings {
+    fn default() -> Self {
+        Self {
+            address: DEFAULT_SYSLOG_ADDRESS.into(),
+            facility: Facility::User,
+        }
+    }
+}
+
+/// Syslog facilities a user program may log as.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Facility {
+    #[default]
+    User = 1,
+    Daemon = 3,
+    Local0 = 16,
+    Local1 = 17,
+    Local2 = 18,
+    Local3 = 19,
+    Local4 = 20,
+    Local5 = 21,
+    Local6 = 22,
+    Local7 = 23,
+}
+
+/// Format of the lines written to stdout and the log file.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// One compact line per event, for people.
+    #[default]
+    Text,
+    /// One JSON object per event with the fields of its spans, for log aggregation.
+    Json,
+}
+
+impl LogFormat {
+    pub fn parse(format: &str) -> Result<Self> {
+        match format {
+            "text" => Ok(Self::Text),
+            "json" => Ok(Self::Json),
+            _ => bail!("Unknown log format {}, expected text or json", format),
+        }
+    }
+}
+
+/// Time-based rotation of the log file, in UTC.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Rotation {
+    Never,
+    Hourly,
+    #[default]
+    Daily,
+}
+
+fn deserialize_size<'de, D: serde::Deserializer<'de>>(
+    deserializer: D,
+) -> std::result::Result<Option<u64>, D::Error> {
+    let size = Box::<str>::deserialize(deserializer)?;
+    parse_size(&size).map(Some).ok_or_else(|| {
+        serde::de::Error::custom(format!(
+            "invalid size {:?}, expected e.g. \"500KB\", \"50MB\" or \"1GiB\"",
+            size
+        ))
+    })
+}
+
+/// `50MB`, `512 KiB`, `1GB` or plain bytes.
+fn parse_size(size: &str) -> Option<u64> {
+    let size = size.trim();
+    let split = size
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(size.len());
+    let (value, unit) = size.split_at(split);
+    let multiplier: u64 = match unit.trim() {
+        "" | "B" => 1,
+        "KB" | "kB" => 1_000,
+        "MB" => 1_000_000,
+        "GB" => 1_000_000_000,
+        "KiB" => 1 << 10,
+        "MiB" => 1 << 20,
+        "GiB" => 1 << 30,
+        _ => return None,
+    };
+    value
+        .parse::<u64>()
+        .ok()
+        .filter(|value| *value > 0)?
+        .checked_mul(multiplier)
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Profile {
+    pub cli: CliOverrides,
+    pub server: ServerOverrides,
+}
+
+/// An Immich account and the directories that go to it.
+///
+/// ```toml
+/// [users.alice]
+/// directories = ["alice", "shared/alice-*"]
+///
+/// [users.alice.server]
+/// api_key_env = "IMMICH_API_KEY_ALICE"
+/// ```
+#[derive(Debug, Default, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct User {
+    /// Patterns matched against the child directory, or the child and grandchild when they
+    /// contain a `/`.
+    pub directories: Vec<Box<str>>,
+    /// The user's API key and, when the user is on a different server, its URL.
+    pub server: ServerOverrides,
+}
+
+/// A user from the config file with the server settings resolved for a run.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UserAccount {
+    pub route: UserRoute,
+    pub server: ServerSettings,
+}
+
+/// Immich CLI settings given in the config file or on the command line. Unset fields keep the
+/// value from the layer below.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CliOverrides {
+    pub path: Option<Box<str>>,
+    pub concurrency: Option<u32>,
+    pub include_hidden: Option<bool>,
+    pub recursive: Option<bool>,
+    /// Appended to the arguments of the layers below rather than replacing them.
+    pub extra_args: Vec<Box<str>>,
+    pub allow_destructive: Option<bool>,
+    /// `docker`, `podman` (or a path to either) to run the CLI from its container image.
+    pub container_runtime: Option<Box<str>>,
+    pub container_image: Option<Box<str>>,
+    /// Extra options for `<runtime> run`, e.g. `--network host`. Appended like `extra_args`.
+    pub container_args: Vec<Box<str>>,
+}
+
+/// The server and where its API key comes from. At most one `api_key*` field may be set per
+/// table; a profile setting one replaces the source of the `[server]` table.
+#[derive(Default, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerOverrides {
+    pub url: Option<Box<str>>,
+    /// The key itself. Better kept out of the config file with one of the other sources.
+    pub api_key: Option<Box<str>>,
+    /// Name of an environment variable holding the key.
+    pub api_key_env: Option<Box<str>>,
+    /// File whose contents are the key; `~/` is expanded.
+    pub api_key_file: Option<Box<str>>,
+    /// Account the key is stored under in the Secret Service keyring.
+    pub api_key_keyring: Option<Box<str>>,
+    /// Command printing the key, e.g. a password manager.
+    pub api_key_command: Option<Vec<Box<str>>>,
+}
+
+impl std::fmt::Debug for ServerOverrides {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ServerOverrides")
+            .field("url", &self.url)
+            .field("api_key", &self.api_key.as_ref().map(|_| REDACTED))
+            .field("api_key_env", &self.api_key_env)
+            .field("api_key_file", &self.api_key_file)
+            .field("api_key_keyring", &self.api_key_keyring)
+            .field("api_key_command", &self.api_key_command)
+            .finish()
+    }
+}
+
+/// Where the API key for the run is read from.
+#[derive(Clone, PartialEq, Eq)]
+pub enum ApiKeySource {
+    Inline(Box<str>),
+    Env(Box<str>),
+    File(Box<str>),
+    Keyring(Box<str>),
+    Command(Vec<Box<str>>),
+}
+
+impl std::fmt::Debug for ApiKeySource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Inline(_) => f.write_str(REDACTED),
+            Self::Env(name) => f.debug_tuple("Env").field(name).finish(),
+            Self::File(path) => f.debug_tuple("File").field(path).finish(),
+            Self::Keyring(account) => f.debug_tuple("Keyring").field(account).finish(),
+            Self::Command(command) => f.debug_tuple("Command").field(command).finish(),
+        }
+    }
+}
+
+/// The server credentials configured for a run. Both unset means the credentials of
+/// `immich login` are used.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct ServerSettings {
+    pub url: Option<Box<str>>,
+    pub api_key: Option<ApiKeySource>,
+}
+
+impl ServerOverrides {
+    fn api_key_source(&self) -> Result<Option<ApiKeySource>> {
+        let sources = [
+            self.api_key.clone().map(ApiKeySource::Inline),
+            self.api_key_env.clone().map(ApiKeySource::Env),
+            self.api_key_file.clone().map(ApiKeySource::File),
+            self.api_key_keyring.clone().map(ApiKeySource::Keyring),
+            self.api_key_command.clone().map(ApiKeySource::Command),
+        ];
+        let mut sources = sources.into_iter().flatten();
+        let source = sources.next();
+        if sources.next().is_some() {
+            bail!("Only one of api_key, api_key_env, api_key_file, api_key_keyring and api_key_command can be set");
+        }
+        Ok(source)
+    }
+}
+
+/// How the Immich CLI is invoked for an upload.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CliSettings {
+    pub command: Box<str>,
+    pub concurrency: u32,
+    pub include_hidden: bool,
+    pub recursive: bool,
+    /// Passed to `immich upload` before the album and paths.
+    pub extra_args: Vec<Box<str>>,
+    /// Run the CLI in a container instead of `command`.
+    pub container: Option<ContainerSettings>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ContainerSettings {
+    pub runtime: Box<str>,
+    pub image: Box<str>,
+    /// Passed to `<runtime> run` before the image.
+    pub run_args: Vec<Box<str>>,
+}
+
+impl Default for CliSettings {
+    fn default() -> Self {
+        Self {
+            command: DEFAULT_CLI_COMMAND.into(),
+            concurrency: DEFAULT_CONCURRENCY,
+            include_hidden: true,
+            recursive: true,
+            extra_args: Vec::new(),
+            container: None,
+        }
+    }
+}
+
+/// `$XDG_CONFIG_HOME/immich-refresh`, falling back to `~/.config`.
+pub fn config_dir() -> Result<PathBuf> {
+    let config_home = match env::var("XDG_CONFIG_HOME") {
+        Ok(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => {
+            let home_dir = env::var("HOME").context("Failed to get HOME environment variable")?;
+            Path::new(&home_dir).join(".config")
+        }
+    };
+
+    Ok(config_home.join("immich-refresh"))
+}
+
+fn default_config_file() -> Result<PathBuf> {
+    Ok(config_dir()?.join("config.toml"))
+}
+
+/// Where logs and run state go: systemd's `$STATE_DIRECTORY`, `$XDG_STATE_HOME/immich-refresh`,
+/// or `~/.local/state/immich-refresh`.
+pub fn state_dir() -> Result<PathBuf> {
+    let var = |name| env::var(name).ok().filter(|value| !value.is_empty());
+    state_dir_from(var("STATE_DIRECTORY"), var("XDG_STATE_HOME"), var("HOME")).context(
+        "Failed to find the state directory: none of STATE_DIRECTORY, XDG_STATE_HOME or HOME is set",
+    )
+}
+
+fn state_dir_from(
+    state_directory: Option<String>,
+    state_home: Option<String>,
+    home_dir: Option<String>,
+) -> Option<PathBuf> {
+    // systemd sets the directories of `StateDirectory=` separated by colons
+    if let Some(dir) = state_directory
+        .as_deref()
+        .and_then(|dirs| dirs.split(':').next())
+    {
+        return Some(PathBuf::from(dir));
+    }
+    let state_home = match (state_home, home_dir) {
+        (Some(state_home), _) => PathBuf::from(state_home),
+        (None, Some(home_dir)) => Path::new(&home_dir).join(".local").join("state"),
+        (None, None) => return None,
+    };
+    Some(state_home.join("immich-refresh"))
+}
+
+impl Config {
+    /// Load `path`, or the default config file when no path is given. Only a missing default
+    /// file is allowed.
+    pub fn load(path: Option<&str>) -> Result<Self> {
+        let path = match path {
+            Some(path) => PathBuf::from(path),
+            // Without HOME, e.g. in a systemd unit, there is no default file either
+            None => match default_config_file() {
+                Ok(path) if path.exists() => path,
+                _ => return Ok(Self::default()),
+            },
+        };
+
+        let contents = fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read config file: {}", path.display()))?;
+        let config = Self::parse(&contents)
+            .with_context(|| format!("Failed to parse config file: {}", path.display()))?;
+        debug!("Loaded config file: {}", path.display());
+
+        Ok(config)
+    }
+
+    pub fn parse(contents: &str) -> Result<Self> {
+        Ok(toml::from_str(contents)?)
+    }
+
+    /// Resolve the CLI settings for a run: built-in defaults, then the `[cli]` table, then the
+    /// selected profile, then the command line.
+    pub fn cli_settings(&self, profile: Option<&str>, run: &CliOverrides) -> Result<CliSettings> {
+        let mut layers = vec![&self.cli];
+        if let Some(profile) = self.profile(profile)? {
+            layers.push(&profile.cli);
+        }
+        layers.push(run);
+
+        let mut settings = CliSettings::default();
+        let mut allow_destructive = false;
+        let mut runtime: Option<&str> = None;
+        let mut image: &str = DEFAULT_CONTAINER_IMAGE;
+        let mut run_args = Vec::new();
+        for layer in layers {
+            if let Some(path) = &layer.path {
+                settings.command = path.clone();
+            }
+            if let Some(concurrency) = layer.concurrency {
+                settings.concurrency = concurrency;
+            }
+            if let Some(include_hidden) = layer.include_hidden {
+                settings.include_hidden = include_hidden;
+            }
+            if let Some(recursive) = layer.recursive {
+                settings.recursive = recursive;
+            }
+            settings.extra_args.extend(layer.extra_args.iter().cloned());
+            if let Some(allow) = layer.allow_destructive {
+                allow_destructive = allow;
+            }
+            if let Some(container_runtime) = &layer.container_runtime {
+                runtime = Some(container_runtime.as_ref()).filter(|r| *r != NATIVE_RUNTIME);
+            }
+            if let Some(container_image) = &layer.container_image {
+                image = container_image;
+            }
+            run_args.extend(layer.container_args.iter().cloned());
+        }
+        settings.container = runtime.map(|runtime| ContainerSettings {
+            runtime: runtime.into(),
+            image: image.into(),
+            run_args,
+        });
+
+        if settings.concurrency == 0 {
+            bail!("CLI concurrency must be at least 1");
+        }
+        if let Some(flag) = settings.extra_args.iter().find(|arg| is_reserved(arg)) {
+            bail!(
+                "Upload flag {} is set by immich-refresh itself and can't be passed as an extra argument",
+                flag
+            );
+        }
+        if !allow_destructive {
+            if let Some(flag) = settings.extra_args.iter().find(|arg| is_destructive(arg)) {
+                bail!(
+                    "Upload flag {} deletes local files; set allow_destructive = true or pass --allow-destructive to use it",
+                    flag
+                );
+            }
+        }
+
+        Ok(settings)
+    }
+
+    /// Resolve the server settings for a run: the `[server]` table, then the selected profile.
+    pub fn server_settings(&self, profile: Option<&str>) -> Result<ServerSettings> {
+        let mut layers = vec![("[server]".to_string(), &self.server)];
+        if let (Some(name), Some(profile)) = (profile, self.profile(profile)?) {
+            layers.push((format!("[profiles.{}.server]", name), &profile.server));
+        }
+
+        let mut settings = ServerSettings::default();
+        for (table, layer) in layers {
+            if let Some(url) = &layer.url {
+                settings.url = Some(url.clone());
+            }
+            if let Some(source) = layer.api_key_source().context(table)? {
+                settings.api_key = Some(source);
+            }
+        }
+
+        Ok(settings)
+    }
+
+    /// Resolve the accounts of all users. Their server URL defaults to the run's; the API key
+    /// must be their own.
+    pub fn user_accounts(&self, profile: Option<&str>) -> Result<Vec<UserAccount>> {
+        let run_server = self.server_settings(profile)?;
+
+        self.users
+            .iter()
+            .map(|(name, user)| {
+                if user.directories.is_empty() {
+                    bail!("User {} has no directories", name);
+                }
+                let api_key = user
+                    .server
+                    .api_key_source()
+                    .with_context(|| format!("[users.{}.server]", name))?
+                    .ok_or_else(|| {
+                        anyhow::anyhow!(
+                            "User {} has no credentials: set one of api_key_env, api_key_file, api_key_keyring, api_key_command or api_key in [users.{}.server]",
+                            name,
+                            name
+                        )
+                    })?;
+
+                Ok(UserAccount {
+                    route: UserRoute {
+                        name: name.as_str().into(),
+                        patterns: user.directories.clone(),
+                    },
+                    server: ServerSettings {
+                        url: user.server.url.clone().or_else(|| run_server.url.clone()),
+                        api_key: Some(api_key),
+                    },
+                })
+            })
+            .collect()
+    }
+
+    fn profile(&self, name: Option<&str>) -> Result<Option<&Profile>> {
+        name.map(|name| {
+            self.profiles
+                .get(name)
+                .ok_or_else(|| anyhow::anyhow!("Unknown profile: {}", name))
+        })
+        .transpose()
+    }
+}
+
+/// `-c4` or `--concurrency=4` as well as the bare flag. Combined short flags only count by
+/// their first letter.
+fn is_reserved(arg: &str) -> bool {
+    let flag = if arg.starts_with("--") {
+        arg.split_once('=').map_or(arg, |(flag, _)| flag)
+    } else {
+        arg.get(..2).unwrap_or(arg)
+    };
+    RESERVED_UPLOAD_FLAGS.contains(&flag)
+}
+
+fn is_destructive(arg: &str) -> bool {
+    let flag = arg.split_once('=').map_or(arg, |(flag, _)| flag);
+    DESTRUCTIVE_UPLOAD_FLAGS.contains(&flag)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use rstest::rstest;
+    use tempfile::TempDir;
+
+    const CONFIG: &str = r#"
+[cli]
+concurrency = 8
+extra_args = ["--skip-hash"]
+
+[profiles.nas.cli]
+path = "/opt/immich-cli/bin/immich"
+include_hidden = false
+extra_args = ["--ignore", "**/*.tmp"]
+
+[profiles.container.cli]
+container_runtime = "podman"
+container_args = ["--network", "host"]
+
+[profiles.cleanup.cli]
+extra_args = ["--delete"]
+allow_destructive = true
+
+[server]
+url = "https://photos.example.com"
+api_key_file = "~/.config/immich-refresh/photos.key"
+
+[profiles.nas.server]
+url = "http://nas.lan:2283"
+api_key_command = ["pass", "show", "immich/nas"]
+"#;
+
+    #[test]
+    fn test_cli_settings_defaults() {
+        let settings = Config::default()
+            .cli_settings(None, &CliOverrides::default())
+            .unwrap();
+        assert_eq!(settings, CliSettings::default());
+    }
+
+    #[test]
+    fn test_cli_settings_layers() {
+        let config = Config::parse(CONFIG).unwrap();
+        let run = CliOverrides {
+            concurrency: Some(2),
+            extra_args: vec!["--json-output".into()],
+            ..Default::default()
+        };
+
+        let settings = config.cli_settings(Some("nas"), &run).unwrap();
+
+        assert_eq!(
+            settings,
+            CliSettings {
+                command: "/opt/immich-cli/bin/immich".into(),
+                concurrency: 2,
+                include_hidden: false,
+                recursive: true,
+                extra_args: vec![
+                    "--skip-hash".into(),
+                    "--ignore".into(),
+                    "**/*.tmp".into(),
+                    "--json-output".into(),
+                ],
+                container: None,
+            }
+        );
+    }
+
+    #[rstest]
+    #[case(None, None, None)]
+    #[case(Some("container"), None, Some("podman"))]
+    #[case(Some("container"), Some("docker"), Some("docker"))]
+    #[case(Some("container"), Some("native"), None)]
+    fn test_cli_settings_container_runtime(
+        #[case] profile: Option<&str>,
+        #[case] run_runtime: Option<&str>,
+        #[case] expected_runtime: Option<&str>,
+    ) {
+        let config = Config::parse(CONFIG).unwrap();
+        let run = CliOverrides {
+            container_runtime: run_runtime.map(Into::into),
+            ..Default::default()
+        };
+
+        let settings = config.cli_settings(profile, &run).unwrap();
+
+        assert_eq!(
+            settings.container.as_ref().map(|c| c.runtime.as_ref()),
+            expected_runtime
+        );
+        if let Some(container) = settings.container {
+            assert_eq!(container.image.as_ref(), DEFAULT_CONTAINER_IMAGE);
+            assert_eq!(container.run_args, vec!["--network".into(), "host".into()]);
+        }
+    }
+
+    #[test]
+    fn test_cli_settings_unknown_profile() {
+        let config = Config::parse(CONFIG).unwrap();
+        assert!(config
+            .cli_settings(Some("missing"), &CliOverrides::default())
+            .is_err());
+    }
+
+    #[rstest]
+    #[case(None, vec!["--delete"], false)]
+    #[case(None, vec!["--delete-duplicates=true"], false)]
+    #[case(Some("cleanup"), vec![], true)]
+    #[case(None, vec!["--skip-hash"], true)]
+    fn test_cli_settings_destructive_flags(
+        #[case] profile: Option<&str>,
+        #[case] extra_args: Vec<&str>,
+        #[case] allowed: bool,
+    ) {
+        let config = Config::parse(CONFIG).unwrap();
+        let run = CliOverrides {
+            extra_args: extra_args.into_iter().map(Into::into).collect(),
+            ..Default::default()
+        };
+
+        assert_eq!(config.cli_settings(profile, &run).is_ok(), allowed);
+    }
+
+    #[rstest]
+    #[case(vec!["-A", "Other"], false)]
+    #[case(vec!["--album-name=Other"], false)]
+    #[case(vec!["-c4"], false)]
+    #[case(vec!["--watch"], false)]
+    #[case(vec!["--key", "other-key"], false)]
+    #[case(vec!["-h"], true)]
+    #[case(vec!["--ignore", "**/*.tmp"], true)]
+    #[case(vec!["--json-output"], true)]
+    fn test_cli_settings_reserved_flags(#[case] extra_args: Vec<&str>, #[case] allowed: bool) {
+        let config = Config::parse(CONFIG).unwrap();
+        let run = CliOverrides {
+            extra_args: extra_args.into_iter().map(Into::into).collect(),
+            allow_destructive: Some(true),
+            ..Default::default()
+        };
+
+        assert_eq!(config.cli_settings(None, &run).is_ok(), allowed);
+    }
+
+    #[rstest]
+    #[case(None, "https://photos.example.com", ApiKeySource::File("~/.config/immich-refresh/photos.key".into()))]
+    #[case(Some("nas"), "http://nas.lan:2283", ApiKeySource::Command(vec!["pass".into(), "show".into(), "immich/nas".into()]))]
+    #[case(Some("cleanup"), "https://photos.example.com", ApiKeySource::File("~/.config/immich-refresh/photos.key".into()))]
+    fn test_server_settings_layers(
+        #[case] profile: Option<&str>,
+        #[case] url: &str,
+        #[case] api_key: ApiKeySource,
+    ) {
+        let config = Config::parse(CONFIG).unwrap();
+
+        assert_eq!(
+            config.server_settings(profile).unwrap(),
+            ServerSettings {
+                url: Some(url.into()),
+                api_key: Some(api_key),
+            }
+        );
+    }
+
+    #[test]
+    fn test_server_settings_reject_several_key_sources() {
+        let config =
+            Config::parse("[server]\napi_key_env = \"IMMICH_KEY\"\napi_key_file = \"key\"\n")
+                .unwrap();
+        assert!(config.server_settings(None).is_err());
+    }
+
+    #[test]
+    fn test_server_overrides_debug_redacts_key() {
+        let config = Config::parse("[server]\napi_key = \"s3cret\"\n").unwrap();
+        assert!(!format!("{config:?}").contains("s3cret"));
+    }
+
+    #[test]
+    fn test_user_accounts() {
+        let config = Config::parse(&format!(
+            "{CONFIG}\n[users.alice]\ndirectories = [\"alice\"]\n\n[users.alice.server]\napi_key_env = \"IMMICH_API_KEY_ALICE\"\n"
+        ))
+        .unwrap();
+
+        let accounts = config.user_accounts(Some("nas")).unwrap();
+
+        assert_eq!(
+            accounts,
+            vec![UserAccount {
+                route: UserRoute {
+                    name: "alice".into(),
+                    patterns: vec!["alice".into()],
+                },
+                server: ServerSettings {
+                    url: Some("http://nas.lan:2283".into()),
+                    api_key: Some(ApiKeySource::Env("IMMICH_API_KEY_ALICE".into())),
+                },
+            }]
+        );
+    }
+
+    #[rstest]
+    #[case("[users.bob]\ndirectories = [\"bob\"]\n")]
+    #[case("[users.bob]\n[users.bob.server]\napi_key_env = \"KEY\"\n")]
+    fn test_user_accounts_refuses_incomplete_users(#[case] config: &str) {
+        let config = Config::parse(config).unwrap();
+        assert!(config.user_accounts(None).is_err());
+    }
+
+    #[test]
+    fn test_log_settings() {
+        let config = Config::parse(
+            "[log]\nformat = \"json\"\nrotation = \"hourly\"\nmax_size = \"50MB\"\nmax_age_days = 90\ncompress = false\n",
+        )
+        .unwrap();
+
+        assert_eq!(
+            config.log,
+            LogSettings {
+                format: LogFormat::Json,
+                rotation: Rotation::Hourly,
+                max_size: Some(50_000_000),
+                max_files: DEFAULT_MAX_LOG_FILES,
+                max_age_days: Some(90),
+                compress: false,
+                max_runs: DEFAULT_MAX_RUNS,
+                ..LogSettings::default()
+            }
+        );
+        assert!(Config::parse("[log]\nmax_size = \"lots\"\n").is_err());
+        assert!(Config::parse("[log]\nrotation = \"weekly\"\n").is_err());
+        assert!(Config::parse("[log]\nformat = \"xml\"\n").is_err());
+    }
+
+    #[rstest]
+    #[case(
+        Some("/var/lib/immich-refresh"),
+        Some("/state"),
+        Some("/home/u"),
+        "/var/lib/immich-refresh"
+    )]
+    #[case(Some("/var/lib/a:/var/lib/b"), None, None, "/var/lib/a")]
+    #[case(None, Some("/state"), Some("/home/u"), "/state/immich-refresh")]
+    #[case(None, None, Some("/home/u"), "/home/u/.local/state/immich-refresh")]
+    fn test_state_dir_from(
+        #[case] state_directory: Option<&str>,
+        #[case] state_home: Option<&str>,
+        #[case] home_dir: Option<&str>,
+        #[case] expected: &str,
+    ) {
+        assert_eq!(
+            state_dir_from(
+                state_directory.map(Into::into),
+                state_home.map(Into::into),
+                home_dir.map(Into::into)
+            ),
+            Some(PathBuf::from(expected))
+        );
+    }
+
+    #[test]
+    fn test_state_dir_from_without_home() {
+        assert_eq!(state_dir_from(None, None, None), None);
+    }
+
+    #[test]
+    fn test_log_levels() {
+        let config = Config::parse(
+            "[log]\nlevel = \"warn\"\necho_cli_output = false\n\n[log.modules]\nexecute = \"debug\"\n",
+        )
+        .unwrap();
+
+        assert_eq!(config.log.level.as_deref(), Some("warn"));
+        assert_eq!(
+            config.log.modules,
+            BTreeMap::from([("execute".to_string(), "debug".into())])
+        );
+        assert!(!config.log.echo_cli_output);
+        assert!(Config::default().log.echo_cli_output);
+    }
+
+    #[test]
+    fn test_log_sinks() {
+        let config = Config::parse("[log]\njournald = true\n\n[log.syslog]\n").unwrap();
+        assert!(config.log.journald);
+        assert_eq!(config.log.syslog, Some(SyslogSettings::default()));
+
+        let config =
+            Config::parse("[log.syslog]\naddress = \"logs.lan:514\"\nfacility = \"local3\"\n")
+                .unwrap();
+        assert_eq!(
+            config.log.syslog,
+            Some(SyslogSettings {
+                address: "logs.lan:514".into(),
+                facility: Facility::Local3,
+            })
+        );
+        assert!(Config::parse("[log.syslog]\nfacility = \"kern\"\n").is_err());
+        assert_eq!(Config::default().log.syslog, None);
+    }
+
+    #[rstest]
+    #[case("512", Some(512))]
+    #[case("500KB", Some(500_000))]
+    #[case("50 MB", Some(50_000_000))]
+    #[case("1GiB", Some(1 << 30))]
+    #[case("0MB", None)]
+    #[case("MB", None)]
+    #[case("5 parsecs", None)]
+    fn test_parse_size(#[case] size: &str, #[case] expected: Option<u64>) {
+        assert_eq!(parse_size(size), expected);
+    }
+
+    #[test]
+    fn test_parse_rejects_unknown_keys() {
+        assert!(Config::parse("[cli]\nconcurency = 8\n").is_err());
+    }
+
+    #[test]
+    fn test_load_explicit_file() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("config.toml");
+        fs::write(&path, CONFIG).unwrap();
+
+        let config = Config::load(Some(&path.to_string_lossy())).unwrap();
+        assert_eq!(config.cli.concurrency, Some(8));
+
+        let missing = temp_dir.path().join("missing.toml");
+        assert!(Config::load(Some(&missing.to_string_lossy())).is_err());
+    }
+}
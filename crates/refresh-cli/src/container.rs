@@ -0,0 +1,181 @@
+use crate::config::ContainerSettings;
+use crate::prelude::*;
+use std::collections::BTreeSet;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Host paths are mounted below this directory at their own absolute path, so every album
+/// directory has the same path inside the container on every run.
+const MOUNT_ROOT: &str = "/import";
+/// Environment variables the Immich CLI reads its credentials from. Only the names appear on the
+/// command line; the values are inherited from the runtime client's environment.
+pub const CREDENTIAL_VARIABLES: [&str; 2] = ["IMMICH_INSTANCE_URL", "IMMICH_API_KEY"];
+
+static CONTAINER_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+/// A container name unique to this process, used to remove the container when its client had to
+/// be killed.
+pub fn container_name() -> String {
+    format!(
+        "immich-refresh-{}-{}",
+        std::process::id(),
+        CONTAINER_COUNT.fetch_add(1, Ordering::SeqCst)
+    )
+}
+
+/// Where `host_path` appears inside the container.
+fn container_path(host_path: &Path) -> PathBuf {
+    Path::new(MOUNT_ROOT).join(host_path.strip_prefix("/").unwrap_or(host_path))
+}
+
+/// Read-only bind mounts for the upload paths and the paths to pass to the CLI inside the
+/// container. Directories are mounted themselves, files through their parent directory.
+pub fn mounts(paths: &[Box<str>]) -> Result<(Vec<String>, Vec<Box<str>>)> {
+    let mut mounted = BTreeSet::new();
+    let mut container_paths = Vec::with_capacity(paths.len());
+
+    for path in paths {
+        let host_path = std::path::absolute(path.as_ref())
+            .with_context(|| format!("Failed to resolve path: {}", path))?;
+        if host_path.to_string_lossy().contains(':') {
+            bail!(
+                "Path contains ':' and can't be mounted into a container: {}",
+                host_path.display()
+            );
+        }
+
+        let mount = if host_path.is_dir() {
+            host_path.clone()
+        } else {
+            host_path
+                .parent()
+                .ok_or_else(|| anyhow::anyhow!("Invalid path: {}", host_path.display()))?
+                .to_path_buf()
+        };
+        mounted.insert(mount);
+        container_paths.push(
+            container_path(&host_path)
+                .to_string_lossy()
+                .into_owned()
+                .into_boxed_str(),
+        );
+    }
+
+    let mount_args = mounted
+        .iter()
+        .flat_map(|mount| {
+            [
+                "-v".to_string(),
+                format!("{}:{}:ro", mount.display(), container_path(mount).display()),
+            ]
+        })
+        .collect();
+
+    Ok((mount_args, container_paths))
+}
+
+/// Arguments for `<runtime>` that run `immich <upload_args>` from the CLI image.
+pub fn run_args(
+    container: &ContainerSettings,
+    name: &str,
+    mount_args: Vec<String>,
+    upload_args: Vec<String>,
+) -> Vec<String> {
+    // --init: node as PID 1 would ignore the SIGTERM forwarded by the client
+    let mut args = vec![
+        "run".to_string(),
+        "--rm".to_string(),
+        "--init".to_string(),
+        "--name".to_string(),
+        name.to_string(),
+    ];
+    for variable in CREDENTIAL_VARIABLES {
+        args.push("-e".to_string());
+        args.push(variable.to_string());
+    }
+    args.extend(mount_args);
+    args.extend(container.run_args.iter().map(|arg| arg.to_string()));
+    args.push(container.image.to_string());
+    args.extend(upload_args);
+    args
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_mounts_directories_and_files() {
+        let temp_dir = TempDir::new().unwrap();
+        let album = temp_dir.path().join("2024/summer");
+        fs::create_dir_all(&album).unwrap();
+        fs::write(album.join("a.jpg"), "a").unwrap();
+        fs::write(album.join("b.jpg"), "b").unwrap();
+        let album = album.to_string_lossy().into_owned();
+
+        let (mount_args, container_paths) = mounts(&[
+            format!("{album}/a.jpg").into(),
+            format!("{album}/b.jpg").into(),
+            album.clone().into(),
+        ])
+        .unwrap();
+
+        assert_eq!(
+            mount_args,
+            vec!["-v".to_string(), format!("{album}:/import{album}:ro")]
+        );
+        assert_eq!(
+            container_paths,
+            vec![
+                format!("/import{album}/a.jpg").into(),
+                format!("/import{album}/b.jpg").into(),
+                format!("/import{album}").into(),
+            ] as Vec<Box<str>>
+        );
+    }
+
+    #[test]
+    fn test_mounts_rejects_colons() {
+        assert!(mounts(&["/photos/12:00".into()]).is_err());
+    }
+
+    #[test]
+    fn test_run_args() {
+        let container = ContainerSettings {
+            runtime: "podman".into(),
+            image: "ghcr.io/immich-app/immich-cli:latest".into(),
+            run_args: vec!["--network".into(), "host".into()],
+        };
+
+        let args = run_args(
+            &container,
+            "immich-refresh-1-0",
+            vec!["-v".into(), "/p:/import/p:ro".into()],
+            vec!["upload".into(), "/import/p".into()],
+        );
+
+        assert_eq!(
+            args,
+            vec![
+                "run",
+                "--rm",
+                "--init",
+                "--name",
+                "immich-refresh-1-0",
+                "-e",
+                "IMMICH_INSTANCE_URL",
+                "-e",
+                "IMMICH_API_KEY",
+                "-v",
+                "/p:/import/p:ro",
+                "--network",
+                "host",
+                "ghcr.io/immich-app/immich-cli:latest",
+                "upload",
+                "/import/p"
+            ]
+        );
+    }
+}
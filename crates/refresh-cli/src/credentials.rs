@@ -0,0 +1,242 @@
+use crate::config::{self, ApiKeySource, ServerSettings};
+use crate::immich_api::{self, ServerCredentials};
+use crate::prelude::*;
+use std::io::Write;
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+
+/// Secret Service attribute all keys of this tool are stored under, next to `account`.
+const KEYRING_SERVICE: &str = "immich-refresh";
+/// Name of the CLI config directory used without a profile.
+const DEFAULT_PROFILE_DIR: &str = "default";
+
+/// The credentials configured in `settings`, or `None` when the config file leaves them to
+/// `immich login`. A configured key without a URL takes the URL from `IMMICH_INSTANCE_URL`.
+pub fn load(settings: &ServerSettings) -> Result<Option<ServerCredentials>> {
+    let Some(source) = &settings.api_key else {
+        if settings.url.is_some() {
+            bail!("server.url is set but no API key: set one of api_key_env, api_key_file, api_key_keyring, api_key_command or api_key");
+        }
+        return Ok(None);
+    };
+
+    let url = match &settings.url {
+        Some(url) => url.to_string(),
+        None => env::var("IMMICH_INSTANCE_URL").context(
+            "An API key is configured but no server: set server.url or IMMICH_INSTANCE_URL",
+        )?,
+    };
+    let api_key = read_api_key(source)?;
+    if api_key.is_empty() {
+        bail!("The API key from {} is empty", describe(source));
+    }
+    debug!("Read the API key from {}", describe(source));
+
+    Ok(Some(ServerCredentials {
+        url: immich_api::normalize_api_url(&url),
+        api_key: api_key.into_boxed_str(),
+    }))
+}
+
+/// Where a key came from, for messages. Never includes the key itself.
+fn describe(source: &ApiKeySource) -> String {
+    match source {
+        ApiKeySource::Inline(_) => "the config file".to_string(),
+        ApiKeySource::Env(name) => format!("environment variable {}", name),
+        ApiKeySource::File(path) => format!("file {}", path),
+        ApiKeySource::Keyring(account) => format!("keyring account {}", account),
+        ApiKeySource::Command(command) => format!("command '{}'", command.join(" ")),
+    }
+}
+
+fn read_api_key(source: &ApiKeySource) -> Result<String> {
+    match source {
+        ApiKeySource::Inline(key) => Ok(key.trim().to_string()),
+        ApiKeySource::Env(name) => env::var(name.as_ref())
+            .map(|key| key.trim().to_string())
+            .with_context(|| format!("Environment variable {} with the API key is not set", name)),
+        ApiKeySource::File(path) => {
+            let path = expand_home(path)?;
+            fs::read_to_string(&path)
+                .map(|key| key.trim().to_string())
+                .with_context(|| format!("Failed to read the API key from {}", path.display()))
+        }
+        ApiKeySource::Keyring(account) => {
+            let command = [
+                "secret-tool",
+                "lookup",
+                "service",
+                KEYRING_SERVICE,
+                "account",
+                account,
+            ];
+            run_key_command(&command).with_context(|| {
+                format!(
+                    "Failed to read the API key from the keyring. Store it with: secret-tool store --label='Immich API key' service {} account {}",
+                    KEYRING_SERVICE, account
+                )
+            })
+        }
+        ApiKeySource::Command(command) => {
+            let command: Vec<&str> = command.iter().map(AsRef::as_ref).collect();
+            run_key_command(&command)
+        }
+    }
+}
+
+/// The first line a command prints. Its stderr goes to the terminal, so password managers can
+/// prompt.
+fn run_key_command(command: &[&str]) -> Result<String> {
+    let Some((program, args)) = command.split_first() else {
+        bail!("api_key_command is empty");
+    };
+    let output = Command::new(program)
+        .args(args)
+        .stdin(Stdio::inherit())
+        .stderr(Stdio::inherit())
+        .output()
+        .with_context(|| format!("Failed to run '{}'", program))?;
+    if !output.status.success() {
+        bail!("'{}' failed with {}", command.join(" "), output.status);
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_string())
+}
+
+fn expand_home(path: &str) -> Result<PathBuf> {
+    match path.strip_prefix("~/") {
+        Some(relative) => {
+            let home_dir = env::var("HOME").context("Failed to get HOME environment variable")?;
+            Ok(Path::new(&home_dir).join(relative))
+        }
+        None => Ok(PathBuf::from(path)),
+    }
+}
+
+/// Write `credentials` to a CLI config directory of its own for `profile`, or for `user` of the
+/// config file, and return it. Pointing the CLI at it with `IMMICH_CONFIG_DIR` keeps profiles and
+/// users for different accounts apart and leaves the user's own `immich login` alone.
+pub fn provision_cli_config(
+    profile: Option<&str>,
+    user: Option<&str>,
+    credentials: &ServerCredentials,
+) -> Result<PathBuf> {
+    let name = match (user, profile) {
+        (Some(user), _) => format!("user-{}", user),
+        (None, Some(profile)) => profile.to_string(),
+        (None, None) => DEFAULT_PROFILE_DIR.to_string(),
+    };
+    let dir = config::config_dir()?
+        .join("cli")
+        .join(name.replace(['/', '\\'], "_"));
+    provision_cli_config_in(&dir, credentials)?;
+    Ok(dir)
+}
+
+fn provision_cli_config_in(dir: &Path, credentials: &ServerCredentials) -> Result<()> {
+    fs::create_dir_all(dir)
+        .with_context(|| format!("Failed to create CLI config directory {}", dir.display()))?;
+    fs::set_permissions(dir, fs::Permissions::from_mode(0o700))?;
+
+    let auth_file = dir.join("auth.yml");
+    let mut file = fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(&auth_file)
+        .with_context(|| format!("Failed to write {}", auth_file.display()))?;
+    // The mode only applies to new files
+    file.set_permissions(fs::Permissions::from_mode(0o600))?;
+    writeln!(file, "url: {}", credentials.url)?;
+    writeln!(file, "key: {}", credentials.api_key)?;
+
+    debug!("Provisioned Immich CLI config in {}", dir.display());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use rstest::rstest;
+    use tempfile::TempDir;
+
+    fn settings(url: Option<&str>, api_key: Option<ApiKeySource>) -> ServerSettings {
+        ServerSettings {
+            url: url.map(Into::into),
+            api_key,
+        }
+    }
+
+    #[test]
+    fn test_load_without_configured_key() {
+        assert_eq!(load(&settings(None, None)).unwrap(), None);
+        assert!(load(&settings(Some("http://nas.lan:2283"), None)).is_err());
+    }
+
+    #[rstest]
+    #[case(ApiKeySource::Inline(" inline-key ".into()), "inline-key")]
+    #[case(ApiKeySource::Command(vec!["echo".into(), "command-key".into()]), "command-key")]
+    #[case(ApiKeySource::Command(vec!["printf".into(), "first\\nsecond".into()]), "first")]
+    fn test_load_api_key(#[case] source: ApiKeySource, #[case] expected: &str) {
+        let credentials = load(&settings(Some("http://nas.lan:2283"), Some(source)))
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(
+            credentials,
+            ServerCredentials {
+                url: "http://nas.lan:2283/api".into(),
+                api_key: expected.into(),
+            }
+        );
+    }
+
+    #[test]
+    fn test_load_api_key_file() {
+        let temp_dir = TempDir::new().unwrap();
+        let key_file = temp_dir.path().join("nas.key");
+        fs::write(&key_file, "file-key\n").unwrap();
+        let source = ApiKeySource::File(key_file.to_string_lossy().into());
+
+        let credentials = load(&settings(Some("http://nas.lan:2283/api"), Some(source)))
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(credentials.api_key.as_ref(), "file-key");
+    }
+
+    #[rstest]
+    #[case(ApiKeySource::File("/nonexistent/immich.key".into()))]
+    #[case(ApiKeySource::Command(vec!["false".into()]))]
+    #[case(ApiKeySource::Command(vec!["true".into()]))]
+    #[case(ApiKeySource::Env("IMMICH_REFRESH_TEST_UNSET_KEY".into()))]
+    fn test_load_api_key_failure(#[case] source: ApiKeySource) {
+        assert!(load(&settings(Some("http://nas.lan:2283"), Some(source))).is_err());
+    }
+
+    #[test]
+    fn test_provision_cli_config() {
+        let temp_dir = TempDir::new().unwrap();
+        let dir = temp_dir.path().join("cli/nas");
+        let credentials = ServerCredentials {
+            url: "http://nas.lan:2283/api".into(),
+            api_key: "abc123".into(),
+        };
+
+        provision_cli_config_in(&dir, &credentials).unwrap();
+
+        let auth_file = dir.join("auth.yml");
+        let contents = fs::read_to_string(&auth_file).unwrap();
+        assert_eq!(immich_api::parse_auth_file(&contents).unwrap(), credentials);
+        let mode = fs::metadata(&auth_file).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+    }
+}
@@ -0,0 +1,608 @@
+use crate::cancel::CancellationToken;
+use crate::execute::{Execute, OutputSink};
+use crate::prelude::*;
+use crate::report::{Outcome, RunReport};
+use crate::traverse::{run_jobs, Job, Schedule};
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Modifier, Style};
+use ratatui::text::Line;
+use ratatui::widgets::{Block, List, ListItem, ListState, Paragraph};
+use ratatui::{DefaultTerminal, Frame};
+use std::collections::VecDeque;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::thread;
+use std::time::Duration;
+use tracing::Span;
+
+/// Lines of CLI output kept for the running job.
+const OUTPUT_LINES: usize = 500;
+/// How long the dashboard waits for a key before it redraws.
+const TICK: Duration = Duration::from_millis(100);
+/// How often a paused or idle worker looks at the cancellation token again.
+const WAIT_INTERVAL: Duration = Duration::from_millis(200);
+const KEYS: &str = "p pause  s skip  r retry  ↑↓ select  q quit";
+
+/// What the worker tells the dashboard.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Update {
+    Started(usize),
+    Output(String),
+    Finished(usize, Outcome),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum JobState {
+    Pending,
+    Running,
+    Done(Outcome),
+}
+
+/// Jobs waiting to run, changed by the keys of the dashboard.
+#[derive(Default)]
+struct Queue {
+    pending: VecDeque<usize>,
+    paused: bool,
+    /// No job is started once set.
+    quit: bool,
+    /// The token of the running job, to skip it.
+    running: Option<CancellationToken>,
+}
+
+/// The schedule of a dashboard run, shared between the dashboard and the worker running the
+/// jobs. Once every job ran, the worker waits for failed ones to be retried until the user quits.
+struct Controls {
+    queue: Mutex<Queue>,
+    changed: Condvar,
+    updates: Sender<Update>,
+}
+
+impl Controls {
+    fn new(jobs: usize, updates: Sender<Update>) -> Self {
+        Self {
+            queue: Mutex::new(Queue {
+                pending: (0..jobs).collect(),
+                ..Queue::default()
+            }),
+            changed: Condvar::new(),
+            updates,
+        }
+    }
+
+    fn queue(&self) -> MutexGuard<'_, Queue> {
+        self.queue.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Change the queue and wake the worker up.
+    fn change<T>(&self, change: impl FnOnce(&mut Queue) -> T) -> T {
+        let result = change(&mut self.queue());
+        self.changed.notify_all();
+        result
+    }
+}
+
+impl Schedule for Controls {
+    fn next(&self, cancel: &CancellationToken) -> Option<usize> {
+        let mut queue = self.queue();
+        loop {
+            if cancel.is_cancelled() || queue.quit {
+                return None;
+            }
+            if !queue.paused {
+                if let Some(index) = queue.pending.pop_front() {
+                    return Some(index);
+                }
+            }
+            queue = self
+                .changed
+                .wait_timeout(queue, WAIT_INTERVAL)
+                .unwrap_or_else(|e| e.into_inner())
+                .0;
+        }
+    }
+
+    fn started(&self, index: usize, cancel: &CancellationToken) {
+        self.queue().running = Some(cancel.clone());
+        let _ = self.updates.send(Update::Started(index));
+    }
+
+    fn finished(&self, index: usize, outcome: Outcome) {
+        self.queue().running = None;
+        let _ = self.updates.send(Update::Finished(index, outcome));
+    }
+}
+
+/// What the dashboard shows.
+struct View {
+    /// Group and name of every job.
+    jobs: Vec<(Box<str>, Box<str>)>,
+    states: Vec<JobState>,
+    running: Option<usize>,
+    output: VecDeque<String>,
+    /// Position in the failures pane.
+    selected: usize,
+    paused: bool,
+    quitting: bool,
+}
+
+impl View {
+    fn new<E>(jobs: &[Job<'_, E>]) -> Self {
+        Self {
+            jobs: jobs
+                .iter()
+                .map(|job| (job.group.clone(), job.name.clone()))
+                .collect(),
+            states: vec![JobState::Pending; jobs.len()],
+            running: None,
+            output: VecDeque::new(),
+            selected: 0,
+            paused: false,
+            quitting: false,
+        }
+    }
+
+    fn apply(&mut self, update: Update) {
+        match update {
+            Update::Started(index) => {
+                self.states[index] = JobState::Running;
+                self.running = Some(index);
+                self.output.clear();
+            }
+            Update::Output(line) => {
+                if self.output.len() == OUTPUT_LINES {
+                    self.output.pop_front();
+                }
+                self.output.push_back(line);
+            }
+            Update::Finished(index, outcome) => {
+                self.states[index] = JobState::Done(outcome);
+                self.running = None;
+            }
+        }
+        self.selected = self.selected.min(self.failures().len().saturating_sub(1));
+    }
+
+    /// Jobs that didn't complete, including skipped ones.
+    fn failures(&self) -> Vec<usize> {
+        self.states
+            .iter()
+            .enumerate()
+            .filter(|(_, state)| matches!(state, JobState::Done(outcome) if *outcome != Outcome::Completed))
+            .map(|(index, _)| index)
+            .collect()
+    }
+
+    fn label(&self, index: usize) -> String {
+        let (group, name) = &self.jobs[index];
+        format!("{}/{}", group, name)
+    }
+
+    fn handle_key(&mut self, key: KeyEvent, controls: &Controls, cancel: &CancellationToken) {
+        match key.code {
+            KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                self.quit(controls, cancel)
+            }
+            KeyCode::Char('q') => self.quit(controls, cancel),
+            KeyCode::Char('p') => {
+                self.paused = controls.change(|queue| {
+                    queue.paused = !queue.paused;
+                    queue.paused
+                });
+                info!(
+                    "{} from the dashboard",
+                    if self.paused { "Paused" } else { "Resumed" }
+                );
+            }
+            KeyCode::Char('s') => {
+                if let (Some(running), Some(token)) = (self.running, &controls.queue().running) {
+                    info!("Skipping {} from the dashboard", self.label(running));
+                    token.abort();
+                }
+            }
+            KeyCode::Char('r') if !self.quitting => {
+                if let Some(&index) = self.failures().get(self.selected) {
+                    info!("Retrying {} from the dashboard", self.label(index));
+                    self.states[index] = JobState::Pending;
+                    controls.change(|queue| queue.pending.push_back(index));
+                    self.selected = self.selected.min(self.failures().len().saturating_sub(1));
+                }
+            }
+            KeyCode::Up | KeyCode::Char('k') => self.selected = self.selected.saturating_sub(1),
+            KeyCode::Down | KeyCode::Char('j') => {
+                self.selected = (self.selected + 1).min(self.failures().len().saturating_sub(1));
+            }
+            _ => {}
+        }
+    }
+
+    /// The first time, start no more jobs and let the running one finish; the second time,
+    /// abort it as well.
+    fn quit(&mut self, controls: &Controls, cancel: &CancellationToken) {
+        let work_left = controls.change(|queue| {
+            queue.quit = true;
+            !queue.pending.is_empty() || queue.running.is_some()
+        });
+        // Leaving once everything ran ends the run normally
+        if work_left || self.quitting {
+            cancel.escalate();
+        }
+        if !self.quitting {
+            info!("Quitting from the dashboard");
+        }
+        self.quitting = true;
+    }
+
+    fn status(&self) -> String {
+        let done = self
+            .states
+            .iter()
+            .filter(|state| matches!(state, JobState::Done(_)))
+            .count();
+        let mut status = format!(
+            "{}/{} done, {} failed",
+            done,
+            self.jobs.len(),
+            self.failures().len()
+        );
+        if self.quitting {
+            status.push_str(" | QUITTING, q again to abort");
+        } else if self.paused {
+            status.push_str(" | PAUSED");
+        } else if self.running.is_none() && done == self.jobs.len() {
+            status.push_str(" | all jobs ran");
+        }
+        format!("{} | {}", status, KEYS)
+    }
+
+    fn draw(&self, frame: &mut Frame) {
+        let [main, status] =
+            Layout::vertical([Constraint::Min(0), Constraint::Length(1)]).areas(frame.area());
+        let [plan, right] =
+            Layout::horizontal([Constraint::Percentage(40), Constraint::Percentage(60)])
+                .areas(main);
+        let [output, failures] =
+            Layout::vertical([Constraint::Min(0), Constraint::Length(8)]).areas(right);
+
+        // The plan as a tree of groups and their jobs, scrolled to the running job
+        let mut items = Vec::new();
+        let mut running_item = None;
+        let mut group = None;
+        for (index, (job_group, name)) in self.jobs.iter().enumerate() {
+            if group != Some(job_group) {
+                group = Some(job_group);
+                items.push(
+                    ListItem::new(job_group.to_string())
+                        .style(Style::new().add_modifier(Modifier::BOLD)),
+                );
+            }
+            if self.running == Some(index) {
+                running_item = Some(items.len());
+            }
+            let symbol = match self.states[index] {
+                JobState::Pending => " ",
+                JobState::Running => ">",
+                JobState::Done(Outcome::Completed) => "+",
+                JobState::Done(Outcome::Failed) => "x",
+                JobState::Done(Outcome::TimedOut) => "t",
+                JobState::Done(Outcome::Interrupted) => "-",
+            };
+            items.push(ListItem::new(format!("  {} {}", symbol, name)));
+        }
+        let mut plan_state = ListState::default().with_selected(running_item);
+        frame.render_stateful_widget(
+            List::new(items)
+                .block(Block::bordered().title("Plan"))
+                .highlight_style(Style::new().add_modifier(Modifier::REVERSED)),
+            plan,
+            &mut plan_state,
+        );
+
+        let title = match self.running {
+            Some(index) => format!("Running: {}", self.label(index)),
+            None if self.paused => "Paused".to_string(),
+            None => "Idle".to_string(),
+        };
+        let visible = output.height.saturating_sub(2) as usize;
+        let lines: Vec<Line> = self
+            .output
+            .iter()
+            .skip(self.output.len().saturating_sub(visible))
+            .map(|line| Line::raw(line.as_str()))
+            .collect();
+        frame.render_widget(
+            Paragraph::new(lines).block(Block::bordered().title(title)),
+            output,
+        );
+
+        let items: Vec<ListItem> = self
+            .failures()
+            .into_iter()
+            .map(|index| {
+                let outcome = match self.states[index] {
+                    JobState::Done(Outcome::TimedOut) => "timed out",
+                    JobState::Done(Outcome::Interrupted) => "skipped",
+                    _ => "failed",
+                };
+                ListItem::new(format!("{} ({})", self.label(index), outcome))
+            })
+            .collect();
+        let mut failures_state =
+            ListState::default().with_selected((!items.is_empty()).then_some(self.selected));
+        frame.render_stateful_widget(
+            List::new(items)
+                .block(Block::bordered().title("Failures"))
+                .highlight_style(Style::new().add_modifier(Modifier::REVERSED)),
+            failures,
+            &mut failures_state,
+        );
+
+        frame.render_widget(Paragraph::new(self.status()), status);
+    }
+}
+
+/// A full-screen view of a run: the plan, the running job with its output, and the failures,
+/// with keys to pause, skip, retry and quit.
+pub struct Dashboard {
+    updates: Sender<Update>,
+    updates_rx: Receiver<Update>,
+}
+
+impl Dashboard {
+    pub fn new() -> Self {
+        let (updates, updates_rx) = mpsc::channel();
+        Self {
+            updates,
+            updates_rx,
+        }
+    }
+
+    /// Shows the output of the executor it is given to as the output of the running job.
+    pub fn output_sink(&self) -> OutputSink {
+        let updates = self.updates.clone();
+        Arc::new(move |_stream, line: &str| {
+            let _ = updates.send(Update::Output(line.to_string()));
+        })
+    }
+
+    /// Run `jobs` in the background while showing the dashboard, until the user quits or the
+    /// run is cancelled.
+    pub fn run<E: Execute + Sync>(
+        self,
+        jobs: &[Job<'_, E>],
+        cancel: &CancellationToken,
+    ) -> Result<RunReport> {
+        let controls = Controls::new(jobs.len(), self.updates.clone());
+        let mut view = View::new(jobs);
+        let mut terminal = ratatui::try_init().context("Failed to set up the terminal")?;
+
+        let (shown, report) = thread::scope(|scope| {
+            // Log lines of the jobs belong to the run like every other one
+            let span = Span::current();
+            let worker_controls = &controls;
+            let worker = scope.spawn(move || {
+                let _span = span.enter();
+                run_jobs(jobs, worker_controls, cancel)
+            });
+            let shown = self.show(&mut terminal, &mut view, &controls, cancel, &worker);
+            if shown.is_err() {
+                // Without a dashboard the run can't be controlled anymore
+                view.quit(&controls, cancel);
+                view.quit(&controls, cancel);
+            }
+            let report = worker
+                .join()
+                .unwrap_or_else(|_| Err(anyhow::anyhow!("The run stopped unexpectedly")));
+            (shown, report)
+        });
+
+        ratatui::restore();
+        shown.context("Failed to draw the dashboard")?;
+        let report = report?;
+        println!("{}", report.summary());
+        Ok(report)
+    }
+
+    fn show(
+        &self,
+        terminal: &mut DefaultTerminal,
+        view: &mut View,
+        controls: &Controls,
+        cancel: &CancellationToken,
+        worker: &thread::ScopedJoinHandle<'_, Result<RunReport>>,
+    ) -> std::io::Result<()> {
+        while !worker.is_finished() {
+            while let Ok(update) = self.updates_rx.try_recv() {
+                view.apply(update);
+            }
+            terminal.draw(|frame| view.draw(frame))?;
+            if event::poll(TICK)? {
+                if let Event::Key(key) = event::read()? {
+                    if key.kind == KeyEventKind::Press {
+                        view.handle_key(key, controls, cancel);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::execute::{ExecuteArgs, MockExecute};
+    use crate::progress;
+    use crate::upload_stats::UploadStats;
+    use pretty_assertions::assert_eq;
+    use ratatui::backend::TestBackend;
+    use ratatui::Terminal;
+
+    fn jobs(executor: &MockExecute) -> Vec<Job<'_, MockExecute>> {
+        ["summer", "winter", "spring"]
+            .iter()
+            .map(|name| Job {
+                group: "2024".into(),
+                name: (*name).into(),
+                source: format!("/photos/2024/{}", name),
+                user: None,
+                executor,
+                execute_args: ExecuteArgs {
+                    paths: vec![format!("/photos/2024/{}", name).into()],
+                    album_name: (*name).into(),
+                    dry_run: false,
+                },
+                size: progress::Size::default(),
+            })
+            .collect()
+    }
+
+    fn key(code: KeyCode) -> KeyEvent {
+        KeyEvent::new(code, KeyModifiers::NONE)
+    }
+
+    #[test]
+    fn test_view_tracks_jobs() {
+        let executor = MockExecute::new();
+        let jobs = jobs(&executor);
+        let mut view = View::new(&jobs);
+
+        view.apply(Update::Started(0));
+        view.apply(Update::Output("Found 2 new files".to_string()));
+        assert_eq!(view.running, Some(0));
+        assert_eq!(view.output, ["Found 2 new files"]);
+        view.apply(Update::Finished(0, Outcome::Failed));
+        view.apply(Update::Started(1));
+        view.apply(Update::Finished(1, Outcome::Interrupted));
+
+        assert_eq!(view.running, None);
+        assert_eq!(view.failures(), [0, 1]);
+        assert_eq!(view.states[2], JobState::Pending);
+    }
+
+    #[test]
+    fn test_keys_control_the_queue() {
+        let executor = MockExecute::new();
+        let jobs = jobs(&executor);
+        let mut view = View::new(&jobs);
+        let (updates, _updates_rx) = mpsc::channel();
+        let controls = Controls::new(jobs.len(), updates);
+        let cancel = CancellationToken::new();
+
+        view.handle_key(key(KeyCode::Char('p')), &controls, &cancel);
+        assert!(view.paused);
+        assert!(controls.queue().paused);
+
+        controls.queue().pending.clear();
+        view.apply(Update::Finished(0, Outcome::Completed));
+        view.apply(Update::Finished(1, Outcome::Failed));
+        view.apply(Update::Finished(2, Outcome::TimedOut));
+        view.handle_key(key(KeyCode::Down), &controls, &cancel);
+        view.handle_key(key(KeyCode::Char('r')), &controls, &cancel);
+        assert_eq!(controls.queue().pending, [2]);
+        assert_eq!(view.states[2], JobState::Pending);
+        assert_eq!(view.selected, 0);
+
+        let (job_cancel, _guard) = cancel.child();
+        controls.started(0, &job_cancel);
+        view.apply(Update::Started(0));
+        view.handle_key(key(KeyCode::Char('s')), &controls, &cancel);
+        assert!(job_cancel.is_aborted());
+        assert!(!cancel.is_cancelled());
+
+        view.handle_key(key(KeyCode::Char('q')), &controls, &cancel);
+        assert!(controls.queue().quit);
+        assert!(cancel.is_cancelled());
+        assert!(!cancel.is_aborted());
+        view.handle_key(key(KeyCode::Char('q')), &controls, &cancel);
+        assert!(cancel.is_aborted());
+    }
+
+    #[test]
+    fn test_quit_after_everything_ran_is_not_a_cancellation() {
+        let executor = MockExecute::new();
+        let jobs = jobs(&executor);
+        let mut view = View::new(&jobs);
+        let (updates, _updates_rx) = mpsc::channel();
+        let controls = Controls::new(0, updates);
+        let cancel = CancellationToken::new();
+
+        view.handle_key(key(KeyCode::Char('q')), &controls, &cancel);
+
+        assert!(!cancel.is_cancelled());
+        assert_eq!(controls.next(&cancel), None);
+    }
+
+    #[test]
+    fn test_run_jobs_with_retry() {
+        let mut executor = MockExecute::new();
+        let mut calls = 0;
+        executor
+            .expect_execute()
+            .times(4)
+            .returning(move |args, _| {
+                calls += 1;
+                match (args.album_name.as_ref(), calls) {
+                    ("winter", 2) => {
+                        Err(crate::execute::ExecuteError::Other(anyhow::anyhow!("disk")).into())
+                    }
+                    _ => Ok(UploadStats::default()),
+                }
+            });
+        let jobs = jobs(&executor);
+        let (updates, updates_rx) = mpsc::channel();
+        let controls = Controls::new(jobs.len(), updates);
+        let cancel = CancellationToken::new();
+
+        let report = thread::scope(|scope| {
+            let worker = scope.spawn(|| run_jobs(&jobs, &controls, &cancel));
+            let mut view = View::new(&jobs);
+            // Retry the failure once every job ran, then quit
+            while view
+                .states
+                .iter()
+                .any(|state| !matches!(state, JobState::Done(_)))
+            {
+                view.apply(updates_rx.recv().unwrap());
+            }
+            view.handle_key(key(KeyCode::Char('r')), &controls, &cancel);
+            while view.states[1] != JobState::Done(Outcome::Completed) {
+                view.apply(updates_rx.recv().unwrap());
+            }
+            view.handle_key(key(KeyCode::Char('q')), &controls, &cancel);
+            worker.join().unwrap().unwrap()
+        });
+
+        assert_eq!(report.count(Outcome::Completed), 3);
+        assert_eq!(report.directories.len(), 3);
+        assert_eq!(report.not_started, 0);
+        assert!(!cancel.is_cancelled());
+    }
+
+    #[test]
+    fn test_draw() {
+        let executor = MockExecute::new();
+        let jobs = jobs(&executor);
+        let mut view = View::new(&jobs);
+        view.apply(Update::Started(0));
+        view.apply(Update::Finished(0, Outcome::Failed));
+        view.apply(Update::Started(1));
+        view.apply(Update::Output("Uploading 3 assets".to_string()));
+        let mut terminal = Terminal::new(TestBackend::new(100, 20)).unwrap();
+
+        terminal.draw(|frame| view.draw(frame)).unwrap();
+
+        let screen: String = terminal
+            .backend()
+            .buffer()
+            .content()
+            .chunks(100)
+            .map(|row| row.iter().map(|cell| cell.symbol()).collect::<String>() + "\n")
+            .collect();
+        assert!(screen.contains("  x summer"), "{screen}");
+        assert!(screen.contains("Running: 2024/winter"), "{screen}");
+        assert!(screen.contains("Uploading 3 assets"), "{screen}");
+        assert!(screen.contains("2024/summer (failed)"), "{screen}");
+        assert!(screen.contains("1/3 done, 1 failed"), "{screen}");
+    }
+}
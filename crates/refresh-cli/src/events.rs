@@ -0,0 +1,411 @@
+use crate::execute::{OutputSink, OutputStream};
+use crate::history::RunStatus;
+use crate::log_rotation::utc_rfc3339;
+use crate::prelude::*;
+use crate::redact::redact;
+use crate::report::{Outcome, RunReport};
+use crate::upload_stats::UploadStats;
+use serde::Serialize;
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+/// Version of the event schema, sent with every event. Fields may be added to events and new
+/// events may appear within a version; removing or changing anything bumps it.
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// Where `--events` sends the event stream.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Target {
+    Stdout,
+    /// Appended to, so the events of several runs can share a file.
+    File(Box<str>),
+}
+
+impl Target {
+    /// Parse `jsonl` or `jsonl:<path>` as given to `--events`.
+    pub fn parse(value: &str) -> Result<Self> {
+        match value.split_once(':') {
+            None if value == "jsonl" => Ok(Self::Stdout),
+            Some(("jsonl", path)) if !path.is_empty() => Ok(Self::File(path.into())),
+            _ => bail!(
+                "Invalid value for --events: {}. Expected jsonl or jsonl:<path>",
+                value
+            ),
+        }
+    }
+}
+
+/// Why a planned directory is not uploaded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SkipReason {
+    /// The run stopped before the directory's turn.
+    NotStarted,
+    /// None of the album's files from the dump exist locally.
+    FilesMissing,
+}
+
+/// Outcome counts of a run that uploaded through the CLI.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Summary {
+    pub completed: usize,
+    pub failed: usize,
+    pub timed_out: usize,
+    pub interrupted: usize,
+    pub not_started: usize,
+    pub stats: UploadStats,
+}
+
+impl From<&RunReport> for Summary {
+    fn from(report: &RunReport) -> Self {
+        Self {
+            completed: report.count(Outcome::Completed),
+            failed: report.count(Outcome::Failed),
+            timed_out: report.count(Outcome::TimedOut),
+            interrupted: report.count(Outcome::Interrupted),
+            not_started: report.not_started,
+            stats: report.total_stats(),
+        }
+    }
+}
+
+/// A lifecycle event of a run. `source` identifies a directory, or a batch of restored files,
+/// the same way the run report does.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event<'a> {
+    RunStarted {
+        mode: &'a str,
+        path: &'a str,
+        dry_run: bool,
+    },
+    /// A directory planned for upload, with the files and bytes it holds.
+    DirDiscovered {
+        source: &'a str,
+        album: &'a str,
+        user: Option<&'a str>,
+        files: u64,
+        bytes: u64,
+    },
+    DirSkipped {
+        source: &'a str,
+        album: &'a str,
+        user: Option<&'a str>,
+        reason: SkipReason,
+    },
+    /// An attempt to upload a directory; retries start again with the next attempt.
+    UploadStarted {
+        source: &'a str,
+        album: &'a str,
+        user: Option<&'a str>,
+        attempt: usize,
+    },
+    /// A line the CLI printed. `source` is the running upload, filled in when the event is sent.
+    UploadOutput {
+        source: Option<&'a str>,
+        stream: OutputStream,
+        line: &'a str,
+    },
+    UploadFinished {
+        source: &'a str,
+        album: &'a str,
+        user: Option<&'a str>,
+        attempt: usize,
+        outcome: Outcome,
+        stats: UploadStats,
+        error: Option<String>,
+    },
+    /// An attempt failed and `attempt` starts after `delay_secs`.
+    Retry {
+        source: &'a str,
+        album: &'a str,
+        user: Option<&'a str>,
+        attempt: usize,
+        delay_secs: u64,
+        error: String,
+    },
+    /// `summary` is only sent by the modes that upload through the CLI.
+    RunFinished {
+        status: RunStatus,
+        error: Option<String>,
+        summary: Option<Summary>,
+    },
+}
+
+#[derive(Serialize)]
+struct Envelope<'a> {
+    schema_version: u32,
+    time: String,
+    run_id: &'a str,
+    #[serde(flatten)]
+    event: &'a Event<'a>,
+}
+
+/// One line of the stream, with secrets redacted like in the logs.
+fn format_event(run_id: &str, time: SystemTime, event: &Event<'_>) -> String {
+    let envelope = Envelope {
+        schema_version: SCHEMA_VERSION,
+        time: utc_rfc3339(time),
+        run_id,
+        event,
+    };
+    let line = serde_json::to_string(&envelope).expect("events serialize to JSON");
+    redact(&line).into_owned()
+}
+
+struct Emitter<W> {
+    writer: W,
+    run_id: Box<str>,
+    /// Source of the upload the CLI's output belongs to.
+    uploading: Option<Box<str>>,
+}
+
+impl<W: Write> Emitter<W> {
+    fn new(writer: W, run_id: &str) -> Self {
+        Self {
+            writer,
+            run_id: run_id.into(),
+            uploading: None,
+        }
+    }
+
+    fn emit(&mut self, event: Event<'_>, time: SystemTime) -> io::Result<()> {
+        match &event {
+            Event::UploadStarted { source, .. } => self.uploading = Some((*source).into()),
+            Event::UploadFinished { .. } => self.uploading = None,
+            _ => {}
+        }
+        let event = match event {
+            Event::UploadOutput { stream, line, .. } => Event::UploadOutput {
+                source: self.uploading.as_deref(),
+                stream,
+                line,
+            },
+            event => event,
+        };
+        let mut line = format_event(&self.run_id, time, &event);
+        line.push('\n');
+        // A single write keeps consumers that tail the file from seeing partial lines
+        self.writer.write_all(line.as_bytes())?;
+        self.writer.flush()
+    }
+}
+
+static EMITTER: Mutex<Option<Emitter<Box<dyn Write + Send>>>> = Mutex::new(None);
+
+/// Send the events of the run `run_id` to `target` from now on.
+pub fn open(target: &Target, run_id: &str) -> Result<()> {
+    let writer: Box<dyn Write + Send> = match target {
+        Target::Stdout => Box::new(io::stdout()),
+        Target::File(path) => Box::new(
+            fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path.as_ref())
+                .with_context(|| format!("Failed to open event stream {}", path))?,
+        ),
+    };
+    *EMITTER.lock().unwrap_or_else(|e| e.into_inner()) = Some(Emitter::new(writer, run_id));
+    Ok(())
+}
+
+/// Send `event` if the run has an event stream. A stream that can't be written to is closed
+/// with an error; the run goes on.
+pub fn emit(event: Event<'_>) {
+    #[cfg(test)]
+    if CAPTURED.with_borrow_mut(|captured| {
+        captured
+            .as_mut()
+            .map(|captured| captured.push(format_event("test", SystemTime::now(), &event)))
+            .is_some()
+    }) {
+        return;
+    }
+
+    let mut emitter = EMITTER.lock().unwrap_or_else(|e| e.into_inner());
+    let Some(open) = emitter.as_mut() else {
+        return;
+    };
+    if let Err(e) = open.emit(event, SystemTime::now()) {
+        *emitter = None;
+        error!("Failed to write event, closing the event stream: {}", e);
+    }
+}
+
+#[cfg(test)]
+thread_local! {
+    /// Events emitted on this thread within `capture`.
+    static CAPTURED: std::cell::RefCell<Option<Vec<String>>> = const { std::cell::RefCell::new(None) };
+}
+
+/// The events `f` emits on this thread, instead of sending them to the run's stream.
+#[cfg(test)]
+pub fn capture(f: impl FnOnce()) -> Vec<serde_json::Value> {
+    CAPTURED.set(Some(Vec::new()));
+    f();
+    CAPTURED
+        .take()
+        .unwrap_or_default()
+        .iter()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect()
+}
+
+/// Sends the output of the executor it is given to as `upload_output` events.
+pub fn output_sink() -> OutputSink {
+    Arc::new(|stream, line: &str| {
+        emit(Event::UploadOutput {
+            source: None,
+            stream,
+            line,
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use rstest::rstest;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    const TIME: Duration = Duration::from_millis(1_792_357_503_512);
+
+    #[rstest]
+    #[case("jsonl", Target::Stdout)]
+    #[case("jsonl:/var/log/events.jsonl", Target::File("/var/log/events.jsonl".into()))]
+    #[case("jsonl:C:events", Target::File("C:events".into()))]
+    fn test_parse_target(#[case] value: &str, #[case] expected: Target) {
+        assert_eq!(Target::parse(value).unwrap(), expected);
+    }
+
+    #[rstest]
+    #[case("json")]
+    #[case("jsonl:")]
+    #[case("csv:/tmp/events")]
+    fn test_parse_target_invalid(#[case] value: &str) {
+        assert!(Target::parse(value).is_err());
+    }
+
+    #[rstest]
+    #[case(
+        Event::RunStarted { mode: "upload", path: "/photos", dry_run: false },
+        r#"{"schema_version":1,"time":"2026-10-18T21:05:03.512Z","run_id":"20261018T210503Z-4711","event":"run_started","mode":"upload","path":"/photos","dry_run":false}"#
+    )]
+    #[case(
+        Event::DirDiscovered {
+            source: "/photos/2024/Summer",
+            album: "Summer",
+            user: None,
+            files: 120,
+            bytes: 4096,
+        },
+        r#"{"schema_version":1,"time":"2026-10-18T21:05:03.512Z","run_id":"20261018T210503Z-4711","event":"dir_discovered","source":"/photos/2024/Summer","album":"Summer","user":null,"files":120,"bytes":4096}"#
+    )]
+    #[case(
+        Event::DirSkipped {
+            source: "/photos/2024/Winter (user alice)",
+            album: "Winter",
+            user: Some("alice"),
+            reason: SkipReason::NotStarted,
+        },
+        r#"{"schema_version":1,"time":"2026-10-18T21:05:03.512Z","run_id":"20261018T210503Z-4711","event":"dir_skipped","source":"/photos/2024/Winter (user alice)","album":"Winter","user":"alice","reason":"not_started"}"#
+    )]
+    #[case(
+        Event::Retry {
+            source: "/photos/2024/Summer",
+            album: "Summer",
+            user: None,
+            attempt: 2,
+            delay_secs: 30,
+            error: "Server error: 502".into(),
+        },
+        r#"{"schema_version":1,"time":"2026-10-18T21:05:03.512Z","run_id":"20261018T210503Z-4711","event":"retry","source":"/photos/2024/Summer","album":"Summer","user":null,"attempt":2,"delay_secs":30,"error":"Server error: 502"}"#
+    )]
+    #[case(
+        Event::UploadFinished {
+            source: "/photos/2024/Summer",
+            album: "Summer",
+            user: None,
+            attempt: 1,
+            outcome: Outcome::Completed,
+            stats: UploadStats { new: 2, duplicates: 1, uploaded: 2, failed: 0, uploaded_bytes: None },
+            error: None,
+        },
+        r#"{"schema_version":1,"time":"2026-10-18T21:05:03.512Z","run_id":"20261018T210503Z-4711","event":"upload_finished","source":"/photos/2024/Summer","album":"Summer","user":null,"attempt":1,"outcome":"completed","stats":{"new":2,"duplicates":1,"uploaded":2,"failed":0,"uploaded_bytes":null},"error":null}"#
+    )]
+    #[case(
+        Event::RunFinished {
+            status: RunStatus::Interrupted,
+            error: None,
+            summary: Some(Summary {
+                completed: 3,
+                failed: 1,
+                timed_out: 0,
+                interrupted: 1,
+                not_started: 2,
+                stats: UploadStats::default(),
+            }),
+        },
+        r#"{"schema_version":1,"time":"2026-10-18T21:05:03.512Z","run_id":"20261018T210503Z-4711","event":"run_finished","status":"interrupted","error":null,"summary":{"completed":3,"failed":1,"timed_out":0,"interrupted":1,"not_started":2,"stats":{"new":0,"duplicates":0,"uploaded":0,"failed":0,"uploaded_bytes":null}}}"#
+    )]
+    fn test_format_event(#[case] event: Event<'_>, #[case] expected: &str) {
+        assert_eq!(
+            format_event("20261018T210503Z-4711", UNIX_EPOCH + TIME, &event),
+            expected
+        );
+    }
+
+    #[test]
+    fn test_output_belongs_to_running_upload() {
+        let mut emitter = Emitter::new(Vec::new(), "run");
+        let upload = |source| Event::UploadStarted {
+            source,
+            album: "Summer",
+            user: None,
+            attempt: 1,
+        };
+        let output = Event::UploadOutput {
+            source: None,
+            stream: OutputStream::Stderr,
+            line: "Found 2 new files",
+        };
+        let finished = Event::UploadFinished {
+            source: "/photos/Summer",
+            album: "Summer",
+            user: None,
+            attempt: 1,
+            outcome: Outcome::Completed,
+            stats: UploadStats::default(),
+            error: None,
+        };
+
+        for event in [
+            output.clone(),
+            upload("/photos/Summer"),
+            output.clone(),
+            finished,
+            output,
+        ] {
+            emitter.emit(event, UNIX_EPOCH + TIME).unwrap();
+        }
+
+        let stream = String::from_utf8(emitter.writer).unwrap();
+        let sources: Vec<serde_json::Value> = stream
+            .lines()
+            .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap())
+            .filter(|event| event["event"] == "upload_output")
+            .map(|event| event["source"].clone())
+            .collect();
+        assert_eq!(
+            sources,
+            [
+                serde_json::Value::Null,
+                "/photos/Summer".into(),
+                serde_json::Value::Null
+            ]
+        );
+        assert_eq!(stream.lines().count(), 5);
+    }
+}
@@ -1,107 +1,465 @@
+use crate::cancel::CancellationToken;
+use crate::classify::classify;
+use crate::compat::CliFlags;
+use crate::config::CliSettings;
+use crate::container;
+use crate::immich_api::ServerCredentials;
 use crate::prelude::*;
-use signal_hook::consts::{SIGINT, SIGTERM};
-use signal_hook::flag;
+use crate::progress;
+use crate::redact::redact;
+use crate::upload_stats::UploadStats;
+use serde::Serialize;
+use std::borrow::Cow;
+use std::collections::VecDeque;
 use std::io::{BufRead, BufReader};
-use std::process::{Command, Stdio};
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::os::unix::process::CommandExt;
+use std::path::PathBuf;
+use std::process::{Command, ExitStatus, Stdio};
+use std::sync::mpsc::{self, RecvTimeoutError};
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 use thiserror::Error;
 
-const IMMICH_CLI_COMMAND: &str = "immich";
+/// Maximum number of child events buffered before the output readers block.
+const EVENT_CHANNEL_CAPACITY: usize = 256;
+/// Number of trailing output lines kept to classify the result of a command.
+const OUTPUT_TAIL_LINES: usize = 200;
+pub const DEFAULT_GRACE_PERIOD: Duration = Duration::from_secs(10);
+/// Where the Immich CLI keeps its `auth.yml`.
+const CLI_CONFIG_DIR_VARIABLE: &str = "IMMICH_CONFIG_DIR";
 
 #[derive(Debug, Error)]
 pub enum ExecuteError {
     #[error("Authentication failed: {0}")]
     AuthFailed(String),
+    #[error("Server unreachable: {0}")]
+    ServerUnreachable(String),
+    #[error("TLS error: {0}")]
+    Tls(String),
+    /// The server answered with a 5xx status.
+    #[error("Server error: {0}")]
+    ServerError(String),
+    #[error("Server disk full: {0}")]
+    ServerDiskFull(String),
+    /// Every file that failed was rejected as unsupported.
+    #[error("Unsupported files: {0}")]
+    UnsupportedFile(String),
+    /// Some files were uploaded, others failed.
+    #[error("Partially uploaded: {0}")]
+    PartialSuccess(String),
     #[error("Immich CLI not found at {0}")]
     ImmichCliNotFound(String),
+    #[error("Cancelled")]
+    Cancelled,
+    /// The command ran past its time limit or stopped producing output and was terminated.
+    #[error("Timed out: {0}")]
+    TimedOut(String),
     #[error("{0}")]
     Other(#[from] anyhow::Error),
 }
 
+/// Everything the supervision loop in `execute_command` waits for.
+enum ChildEvent {
+    Stdout(String),
+    Stderr(String),
+    /// One of the output streams reached end of file.
+    StreamClosed,
+    Exited(std::io::Result<ExitStatus>),
+    /// Every remaining member of the child's process group has been reaped.
+    GroupReaped,
+    Cancelled,
+}
+
+/// Send `signal` to every process in the child's process group. A process group id can't be
+/// reused while the group has members, so this never hits unrelated processes.
+fn signal_process_group(pgid: libc::pid_t, signal: libc::c_int) {
+    // SAFETY: kill(2) has no memory safety requirements.
+    if unsafe { libc::kill(-pgid, signal) } != 0 {
+        let e = std::io::Error::last_os_error();
+        // ESRCH: every process in the group has exited already
+        if e.raw_os_error() != Some(libc::ESRCH) {
+            error!(
+                "Failed to send signal {} to process group {}: {}",
+                signal, pgid, e
+            );
+        }
+    }
+}
+
+fn process_group_alive(pgid: libc::pid_t) -> bool {
+    // SAFETY: kill(2) with signal 0 only checks for existence.
+    unsafe { libc::kill(-pgid, 0) == 0 }
+}
+
+/// Wait for the descendants left in the child's process group after the child itself was reaped.
+/// They are only our children when this process is a child subreaper; otherwise init reaps them
+/// and this returns right away.
+fn reap_process_group(pgid: libc::pid_t) {
+    loop {
+        let mut status = 0;
+        // SAFETY: `status` is a valid pointer for the duration of the call.
+        let pid = unsafe { libc::waitpid(-pgid, &mut status, 0) };
+        if pid > 0 {
+            debug!("Reaped descendant process {}", pid);
+        } else if std::io::Error::last_os_error().kind() != std::io::ErrorKind::Interrupted {
+            break;
+        }
+    }
+}
+
+fn push_tail(tail: &mut VecDeque<String>, line: String) {
+    if tail.len() == OUTPUT_TAIL_LINES {
+        tail.pop_front();
+    }
+    tail.push_back(line);
+}
+
+/// The command line that is executed, quoted so it can be pasted into a shell.
+fn format_command_line(program: &str, args: &[String]) -> String {
+    std::iter::once(program)
+        .chain(args.iter().map(String::as_str))
+        .map(shell_quote)
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Best effort: run a cleanup command after its process had to be killed.
+fn run_cleanup(command: &[String]) {
+    let Some((program, args)) = command.split_first() else {
+        return;
+    };
+    info!("Cleaning up: {}", format_command_line(program, args));
+    let result = Command::new(program)
+        .args(args)
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status();
+    match result {
+        Ok(status) if status.success() => {}
+        Ok(status) => error!("Cleanup command failed with {}", status),
+        Err(e) => error!("Failed to run cleanup command: {}", e),
+    }
+}
+
+/// Quote `arg` for a POSIX shell, leaving words without special characters as they are.
+fn shell_quote(arg: &str) -> Cow<'_, str> {
+    let is_plain = |c: char| c.is_ascii_alphanumeric() || "-_./:=@%+,".contains(c);
+    if !arg.is_empty() && arg.chars().all(is_plain) {
+        Cow::Borrowed(arg)
+    } else {
+        Cow::Owned(format!("'{}'", arg.replace('\'', r"'\''")))
+    }
+}
+
+/// Whole minutes as `5m`, anything else as seconds.
+fn format_duration(duration: Duration) -> String {
+    match duration.as_secs() {
+        secs if secs > 0 && secs % 60 == 0 => format!("{}m", secs / 60),
+        secs => format!("{}s", secs),
+    }
+}
+
+/// Make orphaned descendants of the CLI our children so they can be reaped, instead of
+/// reparenting them to init where `reap_process_group` can't wait for them. This changes the
+/// whole process, so `main` does it once before the run starts.
+#[cfg(target_os = "linux")]
+pub fn become_child_subreaper() {
+    // SAFETY: PR_SET_CHILD_SUBREAPER takes a plain integer argument.
+    if unsafe { libc::prctl(libc::PR_SET_CHILD_SUBREAPER, 1) } != 0 {
+        error!(
+            "Failed to become child subreaper: {}",
+            std::io::Error::last_os_error()
+        );
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn become_child_subreaper() {}
+
 #[derive(Debug, Clone)]
 pub struct ExecuteArgs {
-    pub path: Box<str>,
+    /// Directories or files to upload, usually a single album directory.
+    pub paths: Vec<Box<str>>,
     pub album_name: Box<str>,
     pub dry_run: bool,
 }
 
+/// A fully resolved process to start for an upload.
+struct Invocation {
+    program: Box<str>,
+    args: Vec<String>,
+    /// Only set in the child's environment so secrets never show up on the command line.
+    env: Vec<(&'static str, Box<str>)>,
+    /// Run after the process had to be killed, e.g. to remove the container a killed runtime
+    /// client leaves behind.
+    cleanup: Option<Vec<String>>,
+}
+
+/// Which of the CLI's streams a line of output came from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OutputStream {
+    Stdout,
+    Stderr,
+}
+
+/// A failed upload with what the CLI reported about the files until then, e.g. the files it
+/// uploaded before others failed or it was terminated.
+#[derive(Debug, Error)]
+#[error("{error}")]
+pub struct UploadError {
+    pub error: ExecuteError,
+    pub stats: UploadStats,
+}
+
+impl From<ExecuteError> for UploadError {
+    fn from(error: ExecuteError) -> Self {
+        Self {
+            error,
+            stats: UploadStats::default(),
+        }
+    }
+}
+
+/// Receives every line the CLI prints, e.g. for the dashboard or the event stream.
+pub type OutputSink = Arc<dyn Fn(OutputStream, &str) + Send + Sync>;
+
+#[cfg_attr(test, mockall::automock)]
 pub trait Execute {
-    fn execute(&self, args: &ExecuteArgs) -> Result<(), ExecuteError>;
+    /// Upload `args` and return what the CLI reported about the files, which failures carry as
+    /// well. Returns `ExecuteError::Cancelled` without starting when `cancel` is already
+    /// cancelled, and terminates the upload when `cancel` is aborted.
+    fn execute(
+        &self,
+        args: &ExecuteArgs,
+        cancel: &CancellationToken,
+    ) -> Result<UploadStats, UploadError>;
 }
 
 pub struct Executer {
-    signal_received: Arc<AtomicBool>,
+    cli: CliSettings,
+    /// Optional upload flags the installed CLI understands.
+    flags: CliFlags,
+    /// Time the CLI gets to exit after SIGTERM before it is killed.
+    grace_period: Duration,
+    /// Wall-clock limit for a single invocation.
+    timeout: Option<Duration>,
+    /// Limit for the time between two lines of output.
+    stall_timeout: Option<Duration>,
+    /// Passed to the CLI through its environment. Required in a container, where the CLI can't
+    /// read `immich login`'s file; without them the native CLI uses its own login.
+    credentials: Option<ServerCredentials>,
+    /// `IMMICH_CONFIG_DIR` for the native CLI, keeping its state apart from the user's login.
+    cli_config_dir: Option<PathBuf>,
+    /// Print the CLI's output as is besides logging it. Off when stdout carries JSON logs.
+    echo_output: bool,
+    output_sinks: Vec<OutputSink>,
+}
+
+impl Default for Executer {
+    fn default() -> Self {
+        Self {
+            cli: CliSettings::default(),
+            flags: CliFlags::default(),
+            grace_period: DEFAULT_GRACE_PERIOD,
+            timeout: None,
+            stall_timeout: None,
+            credentials: None,
+            cli_config_dir: None,
+            echo_output: true,
+            output_sinks: Vec::new(),
+        }
+    }
 }
 
 impl Executer {
     pub fn new() -> Self {
-        let signal_received = Arc::new(AtomicBool::new(false));
+        Self::default()
+    }
 
-        // Set up signal handlers for SIGINT and SIGTERM
-        let signal_flag = signal_received.clone();
-        if let Err(e) = flag::register(SIGINT, signal_flag.clone()) {
-            error!("Failed to register SIGINT handler: {}", e);
-        }
+    pub fn with_cli_settings(mut self, cli: CliSettings) -> Self {
+        self.cli = cli;
+        self
+    }
+
+    pub fn with_cli_flags(mut self, flags: CliFlags) -> Self {
+        self.flags = flags;
+        self
+    }
 
-        let signal_flag = signal_received.clone();
-        if let Err(e) = flag::register(SIGTERM, signal_flag) {
-            error!("Failed to register SIGTERM handler: {}", e);
+    pub fn with_echo_output(mut self, echo_output: bool) -> Self {
+        self.echo_output = echo_output;
+        self
+    }
+
+    /// Adds `output_sink` to those receiving the CLI's output.
+    pub fn with_output_sink(mut self, output_sink: OutputSink) -> Self {
+        self.output_sinks.push(output_sink);
+        self
+    }
+
+    #[cfg(test)]
+    fn with_cli_command(mut self, command: &str) -> Self {
+        self.cli.command = command.into();
+        self
+    }
+
+    pub fn with_grace_period(mut self, grace_period: Duration) -> Self {
+        self.grace_period = grace_period;
+        self
+    }
+
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    pub fn with_stall_timeout(mut self, stall_timeout: Duration) -> Self {
+        self.stall_timeout = Some(stall_timeout);
+        self
+    }
+
+    pub fn with_credentials(mut self, credentials: ServerCredentials) -> Self {
+        self.credentials = Some(credentials);
+        self
+    }
+
+    pub fn with_cli_config_dir(mut self, cli_config_dir: PathBuf) -> Self {
+        self.cli_config_dir = Some(cli_config_dir);
+        self
+    }
+
+    /// The environment variables passing the credentials to the CLI.
+    fn credential_env(&self) -> Vec<(&'static str, Box<str>)> {
+        let Some(credentials) = &self.credentials else {
+            return Vec::new();
+        };
+        container::CREDENTIAL_VARIABLES
+            .into_iter()
+            .zip([credentials.url.clone(), credentials.api_key.clone()])
+            .collect()
+    }
+
+    fn build_command_args(&self, args: &ExecuteArgs) -> Vec<String> {
+        let mut command_args = vec!["upload".to_string()];
+        if self.cli.include_hidden && self.flags.include_hidden {
+            command_args.push("-H".to_string());
         }
+        if self.cli.recursive {
+            command_args.push("-r".to_string());
+        }
+        if self.flags.concurrency {
+            command_args.push("-c".to_string());
+            command_args.push(self.cli.concurrency.to_string());
+        }
+        command_args.extend(self.cli.extra_args.iter().map(|arg| arg.to_string()));
+        command_args.push("-A".to_string());
+        command_args.push(args.album_name.to_string());
+        command_args.extend(args.paths.iter().map(|path| path.to_string()));
+        command_args
+    }
+
+    /// The process that uploads `args`: the CLI itself, or the container runtime running it.
+    fn invocation(&self, args: &ExecuteArgs) -> Result<Invocation, ExecuteError> {
+        let Some(container) = &self.cli.container else {
+            let mut env = self.credential_env();
+            if let Some(dir) = &self.cli_config_dir {
+                env.push((CLI_CONFIG_DIR_VARIABLE, dir.to_string_lossy().into()));
+            }
+            return Ok(Invocation {
+                program: self.cli.command.clone(),
+                args: self.build_command_args(args),
+                env,
+                cleanup: None,
+            });
+        };
 
-        Self { signal_received }
+        let (mount_args, container_paths) = container::mounts(&args.paths)?;
+        let upload_args = self.build_command_args(&ExecuteArgs {
+            paths: container_paths,
+            ..args.clone()
+        });
+        let name = container::container_name();
+
+        Ok(Invocation {
+            program: container.runtime.clone(),
+            args: container::run_args(container, &name, mount_args, upload_args),
+            env: self.credential_env(),
+            cleanup: Some(vec![
+                container.runtime.to_string(),
+                "rm".to_string(),
+                "-f".to_string(),
+                name,
+            ]),
+        })
     }
 
-    pub fn check_signal(&self) {
-        if self.signal_received.load(Ordering::Relaxed) {
-            info!("Signal received, exiting");
-            std::process::exit(130);
+    /// When the running command times out if nothing else happens: the wall-clock limit or the
+    /// moment the stall limit is reached without further output, whichever comes first.
+    fn timeout_deadline(&self, started: Instant, last_output: Instant) -> Option<Instant> {
+        let wall_clock = self.timeout.map(|timeout| started + timeout);
+        let stall = self.stall_timeout.map(|timeout| last_output + timeout);
+        match (wall_clock, stall) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
         }
     }
 
-    fn build_command_args(&self, args: &ExecuteArgs) -> Vec<String> {
-        vec![
-            "upload".to_string(),
-            "-H".to_string(),
-            "-r".to_string(),
-            "-c".to_string(),
-            "24".to_string(),
-            "-A".to_string(),
-            args.album_name.to_string(),
-            args.path.to_string(),
-        ]
-    }
-
-    fn format_command_display(&self, args: &ExecuteArgs) -> String {
-        format!(
-            r#"{} upload -H -r -c 24 -A "{}" "{}""#,
-            IMMICH_CLI_COMMAND, args.album_name, args.path
-        )
+    /// Why the running command has timed out, if it has.
+    fn timeout_reason(&self, started: Instant, last_output: Instant) -> Option<String> {
+        let now = Instant::now();
+        if let Some(timeout) = self.timeout.filter(|timeout| now >= started + *timeout) {
+            return Some(format!(
+                "exceeded the timeout of {}",
+                format_duration(timeout)
+            ));
+        }
+        if let Some(timeout) = self
+            .stall_timeout
+            .filter(|timeout| now >= last_output + *timeout)
+        {
+            return Some(format!(
+                "produced no output for {}",
+                format_duration(timeout)
+            ));
+        }
+        None
     }
 
-    fn check_immich_cli_exists() -> Result<(), ExecuteError> {
-        // Try to run 'immich --version' to check if the command exists and is executable
-        let result = Command::new(IMMICH_CLI_COMMAND)
+    /// Run `immich --version`, from the container image when one is configured, and return the
+    /// version it prints.
+    pub fn cli_version(&self) -> Result<String, ExecuteError> {
+        let (program, args) = match &self.cli.container {
+            Some(container) => {
+                let mut args = vec!["run".to_string(), "--rm".to_string()];
+                args.extend(container.run_args.iter().map(|arg| arg.to_string()));
+                args.push(container.image.to_string());
+                (&container.runtime, args)
+            }
+            None => (&self.cli.command, Vec::new()),
+        };
+        let output = Command::new(program.as_ref())
+            .args(&args)
             .arg("--version")
-            .stdout(Stdio::null())
-            .stderr(Stdio::null())
-            .spawn();
+            .stdin(Stdio::null())
+            .output();
 
-        match result {
-            Ok(mut child) => {
-                // Command exists and started, wait for it to finish
-                let _ = child.wait();
-                Ok(())
-            }
-            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
-                Err(ExecuteError::ImmichCliNotFound(format!(
-                    "'{}' command not found in PATH",
-                    IMMICH_CLI_COMMAND
-                )))
+        match output {
+            Ok(output) if output.status.success() => {
+                Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
             }
+            Ok(output) => Err(ExecuteError::Other(anyhow::anyhow!(
+                "'{} --version' failed with {}: {}",
+                format_command_line(program, &args),
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            ))),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Err(
+                ExecuteError::ImmichCliNotFound(format!("'{}' command not found in PATH", program)),
+            ),
             Err(e) => Err(ExecuteError::Other(anyhow::anyhow!(
-                "Failed to check for '{}' command: {}",
-                IMMICH_CLI_COMMAND,
+                "Failed to run '{}': {}",
+                program,
                 e
             ))),
         }
@@ -109,9 +467,10 @@ impl Executer {
 
     fn execute_command(
         &self,
-        command_args: &[String],
+        invocation: &Invocation,
         display_str: &str,
-    ) -> Result<(), ExecuteError> {
+        cancel: &CancellationToken,
+    ) -> Result<UploadStats, UploadError> {
         info!("Executing: {}", display_str);
 
         // Get current working directory
@@ -123,11 +482,21 @@ impl Executer {
         })?;
 
         // Spawn the process with piped stdout and stderr
-        let mut child = Command::new(IMMICH_CLI_COMMAND)
-            .args(command_args)
+        let mut child = Command::new(invocation.program.as_ref())
+            .args(&invocation.args)
+            .envs(
+                invocation
+                    .env
+                    .iter()
+                    .map(|(name, value)| (name, value.as_ref())),
+            )
             .current_dir(&current_dir)
             .stdout(Stdio::piped())
             .stderr(Stdio::piped())
+            // Run the CLI in its own process group: Ctrl+C in the terminal doesn't reach it, so the
+            // first signal lets the current directory finish, and termination can be sent to
+            // every worker process it starts
+            .process_group(0)
             .spawn()
             .map_err(|e| {
                 ExecuteError::Other(anyhow::anyhow!(
@@ -136,6 +505,7 @@ impl Executer {
                     e
                 ))
             })?;
+        let pgid = child.id() as libc::pid_t;
 
         // Get stdout and stderr handles
         let stdout = child
@@ -147,119 +517,242 @@ impl Executer {
             .take()
             .ok_or_else(|| ExecuteError::Other(anyhow::anyhow!("Failed to capture stderr")))?;
 
-        // Create buffered readers
-        let stdout_reader = BufReader::new(stdout);
-        let stderr_reader = BufReader::new(stderr);
+        // All sources report into one bounded channel; readers block when the supervision loop
+        // falls behind, which keeps memory bounded regardless of output volume.
+        let (events_tx, events_rx) = mpsc::sync_channel(EVENT_CHANNEL_CAPACITY);
 
-        // Read and stream stdout
+        let stdout_tx = events_tx.clone();
         let stdout_handle = std::thread::spawn(move || {
-            let mut lines = Vec::new();
-            for line in stdout_reader.lines().map_while(Result::ok) {
-                println!("{}", line);
-                lines.push(line);
+            for line in BufReader::new(stdout).lines().map_while(Result::ok) {
+                if stdout_tx.send(ChildEvent::Stdout(line)).is_err() {
+                    break;
+                }
             }
-            lines
+            let _ = stdout_tx.send(ChildEvent::StreamClosed);
         });
 
-        // Read and stream stderr
+        let stderr_tx = events_tx.clone();
         let stderr_handle = std::thread::spawn(move || {
-            let mut lines = Vec::new();
-            for line in stderr_reader.lines().map_while(Result::ok) {
-                eprintln!("{}", line);
-                lines.push(line);
+            for line in BufReader::new(stderr).lines().map_while(Result::ok) {
+                if stderr_tx.send(ChildEvent::Stderr(line)).is_err() {
+                    break;
+                }
             }
-            lines
+            let _ = stderr_tx.send(ChildEvent::StreamClosed);
         });
 
-        // Check for signals while waiting for the process
-        loop {
-            if self.signal_received.load(Ordering::Relaxed) {
-                // Signal received, kill the child process
-                info!("Signal received, terminating command");
-                if let Err(e) = child.kill() {
-                    error!("Failed to kill child process: {}", e);
-                }
-                // Wait for the child to actually terminate
-                let _ = child.wait();
-                std::process::exit(130);
-            }
+        let wait_tx = events_tx.clone();
+        let wait_handle = std::thread::spawn(move || {
+            let _ = wait_tx.send(ChildEvent::Exited(child.wait()));
+            reap_process_group(pgid);
+            let _ = wait_tx.send(ChildEvent::GroupReaped);
+        });
 
-            // Check if process has exited
-            match child.try_wait() {
-                Ok(Some(status)) => {
-                    // Process has exited
-                    let output_lines = stdout_handle.join().unwrap_or_default();
-                    let error_lines = stderr_handle.join().unwrap_or_default();
+        // Wake the supervision loop on every signal. When the channel is full the wake-up is
+        // dropped, but the loop checks the token after every event it receives anyway.
+        let cancel_tx = events_tx;
+        let _cancel_guard = cancel.on_cancel(move || {
+            let _ = cancel_tx.try_send(ChildEvent::Cancelled);
+        });
 
-                    // Log output to logfile
-                    for line in &output_lines {
-                        info!("stdout: {line}");
-                    }
-                    for line in &error_lines {
-                        info!("stderr: {line}");
-                    }
+        let mut open_streams = 2;
+        let mut exit_status = None;
+        let mut output_tail = VecDeque::with_capacity(OUTPUT_TAIL_LINES);
+        let mut stats = UploadStats::default();
+        let mut group_reaped = false;
+        // Set when we asked the process group to terminate
+        let mut group_signalled = false;
+        let mut group_killed = false;
+        let mut aborted = false;
+        let mut timed_out: Option<String> = None;
+        let mut kill_deadline: Option<Instant> = None;
+        // Set when the command exited, until when its output is still read
+        let mut drain_deadline: Option<Instant> = None;
+        let mut output_abandoned = false;
+        let started = Instant::now();
+        let mut last_output = started;
 
-                    if !status.success() {
-                        let exit_code = status.code().unwrap_or(-1);
-
-                        // Check for authentication error
-                        let auth_error = error_lines.iter().any(|line| {
-                            line.to_lowercase().contains("auth")
-                                || line.to_lowercase().contains("login")
-                                || line.to_lowercase().contains("credential")
-                                || line.to_lowercase().contains("unauthorized")
-                        });
-
-                        if auth_error {
-                            let error_message = format!(
-                                "Immich CLI authentication required. Exit code: {}. Please authenticate first.",
-                                exit_code
-                            );
-                            error!("{}", error_message);
-                            return Err(ExecuteError::AuthFailed(error_message));
-                        } else {
-                            let error_message = format!(
-                                "Command '{}' failed with exit code {}",
-                                display_str, exit_code
-                            );
-                            error!("{}", error_message);
-                            return Err(ExecuteError::Other(anyhow::anyhow!(error_message)));
-                        }
+        // Once the group had to be killed, or the output stayed open for a grace period after the
+        // command exited, don't wait for end of file on the output: a process that left the
+        // group may still hold the pipes open.
+        while exit_status.is_none()
+            || !group_reaped
+            || (open_streams > 0 && !group_killed && !output_abandoned)
+        {
+            let deadline = if group_signalled || exit_status.is_some() {
+                kill_deadline.into_iter().chain(drain_deadline).min()
+            } else {
+                self.timeout_deadline(started, last_output)
+            };
+            let event = match deadline {
+                Some(deadline) => {
+                    match events_rx.recv_timeout(deadline.saturating_duration_since(Instant::now()))
+                    {
+                        Ok(event) => Some(event),
+                        Err(RecvTimeoutError::Timeout) => None,
+                        Err(RecvTimeoutError::Disconnected) => break,
                     }
+                }
+                None => match events_rx.recv() {
+                    Ok(event) => Some(event),
+                    Err(_) => break,
+                },
+            };
 
-                    info!("Command completed successfully");
-                    return Ok(());
+            match event {
+                Some(ChildEvent::Stdout(line)) => {
+                    last_output = Instant::now();
+                    if self.echo_output {
+                        progress::print_line(&redact(&line));
+                    }
+                    for output_sink in &self.output_sinks {
+                        output_sink(OutputStream::Stdout, &redact(&line));
+                    }
+                    info!("stdout: {}", redact(&line));
+                    stats.parse_line(&line);
+                    push_tail(&mut output_tail, line);
                 }
-                Ok(None) => {
-                    // Process still running, sleep briefly
-                    std::thread::sleep(std::time::Duration::from_millis(100));
+                Some(ChildEvent::Stderr(line)) => {
+                    last_output = Instant::now();
+                    if self.echo_output {
+                        progress::eprint_line(&redact(&line));
+                    }
+                    for output_sink in &self.output_sinks {
+                        output_sink(OutputStream::Stderr, &redact(&line));
+                    }
+                    info!("stderr: {}", redact(&line));
+                    stats.parse_line(&line);
+                    push_tail(&mut output_tail, line);
                 }
-                Err(e) => {
-                    return Err(ExecuteError::Other(anyhow::anyhow!(
-                        "Error waiting for process: {}",
-                        e
-                    )));
+                Some(ChildEvent::StreamClosed) => open_streams -= 1,
+                Some(ChildEvent::Exited(status)) => {
+                    exit_status = Some(status);
+                    drain_deadline = Some(Instant::now() + self.grace_period);
                 }
+                Some(ChildEvent::GroupReaped) => group_reaped = true,
+                Some(ChildEvent::Cancelled) | None => {}
+            }
+
+            if open_streams > 0 && drain_deadline.is_some_and(|deadline| Instant::now() >= deadline)
+            {
+                warn!(
+                    "Command exited but its output is still open after {}s, no longer reading it",
+                    self.grace_period.as_secs()
+                );
+                output_abandoned = true;
+                drain_deadline = None;
+            }
+
+            if !group_signalled {
+                if cancel.is_aborted() && exit_status.is_none() {
+                    info!(
+                        "Aborting, asking command to terminate (killing it in {}s)",
+                        self.grace_period.as_secs()
+                    );
+                    aborted = true;
+                } else if let Some(reason) = exit_status
+                    .is_none()
+                    .then(|| self.timeout_reason(started, last_output))
+                    .flatten()
+                {
+                    error!("Command {}, terminating it", reason);
+                    timed_out = Some(reason);
+                } else if exit_status.is_some() && process_group_alive(pgid) {
+                    info!("Command exited but left processes running, terminating them");
+                } else {
+                    continue;
+                }
+                signal_process_group(pgid, libc::SIGTERM);
+                group_signalled = true;
+                kill_deadline = Some(Instant::now() + self.grace_period);
+            } else if kill_deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                info!("Processes did not terminate within the grace period, killing them");
+                signal_process_group(pgid, libc::SIGKILL);
+                group_killed = true;
+                kill_deadline = None;
+                if let Some(cleanup) = &invocation.cleanup {
+                    run_cleanup(cleanup);
+                }
+            }
+        }
+
+        let _ = wait_handle.join();
+        if open_streams == 0 {
+            let _ = stdout_handle.join();
+            let _ = stderr_handle.join();
+        }
+
+        if aborted {
+            return Err(UploadError {
+                error: ExecuteError::Cancelled,
+                stats,
+            });
+        }
+        if let Some(reason) = timed_out {
+            return Err(UploadError {
+                error: ExecuteError::TimedOut(reason),
+                stats,
+            });
+        }
+
+        let status = match exit_status {
+            Some(Ok(status)) => status,
+            Some(Err(e)) => {
+                return Err(UploadError {
+                    error: ExecuteError::Other(anyhow::anyhow!("Error waiting for process: {}", e)),
+                    stats,
+                });
             }
+            None => {
+                return Err(UploadError {
+                    error: ExecuteError::Other(anyhow::anyhow!(
+                        "Lost track of process '{}'",
+                        display_str
+                    )),
+                    stats,
+                });
+            }
+        };
+
+        let exit_code = status.code().unwrap_or(-1);
+        if let Err(error) = classify(exit_code, output_tail.make_contiguous(), &stats) {
+            error!("Command '{}' failed: {}", display_str, error);
+            return Err(UploadError { error, stats });
         }
+
+        info!(
+            "Command completed successfully: {} uploaded, {} duplicates",
+            stats.uploaded, stats.duplicates
+        );
+        Ok(stats)
     }
 }
 
 impl Execute for Executer {
-    fn execute(&self, args: &ExecuteArgs) -> Result<(), ExecuteError> {
-        // Check for signals at the very start
-        self.check_signal();
+    fn execute(
+        &self,
+        args: &ExecuteArgs,
+        cancel: &CancellationToken,
+    ) -> Result<UploadStats, UploadError> {
+        // Check for cancellation at the very start
+        if cancel.is_cancelled() {
+            return Err(ExecuteError::Cancelled.into());
+        }
 
-        let command_args = self.build_command_args(args);
-        let display_str = self.format_command_display(args);
+        let invocation = self.invocation(args)?;
+        let display_str = format_command_line(&invocation.program, &invocation.args);
 
         if args.dry_run {
             info!("[DRY RUN] Would execute: {}", display_str);
-            Ok(())
+            Ok(UploadStats::default())
         } else {
-            // Check if Immich CLI exists before executing actual command
-            Self::check_immich_cli_exists()?;
-            self.execute_command(&command_args, &display_str)
+            if self.cli.container.is_some() && self.credentials.is_none() {
+                return Err(ExecuteError::AuthFailed(
+                    "No Immich credentials to pass into the container. Run 'immich login' or set IMMICH_INSTANCE_URL and IMMICH_API_KEY".to_string(),
+                )
+                .into());
+            }
+            self.execute_command(&invocation, &display_str, cancel)
         }
     }
 }
@@ -270,21 +763,11 @@ mod tests {
     use pretty_assertions::assert_eq;
     use rstest::rstest;
 
-    fn is_immich_cli_installed() -> bool {
-        Command::new("which")
-            .arg("immich")
-            .stdout(Stdio::null())
-            .stderr(Stdio::null())
-            .status()
-            .map(|status| status.success())
-            .unwrap_or(false)
-    }
-
     #[test]
     fn test_build_command_args() {
         let executer = Executer::new();
         let args = ExecuteArgs {
-            path: "/base/child1/grandchildA".into(),
+            paths: vec!["/base/child1/grandchildA".into()],
             album_name: "grandchildA".into(),
             dry_run: false,
         };
@@ -309,18 +792,83 @@ mod tests {
     fn test_format_command_display() {
         let executer = Executer::new();
         let args = ExecuteArgs {
-            path: "/base/child1/grandchildA".into(),
+            paths: vec!["/base/child1/grandchildA".into()],
             album_name: "grandchildA".into(),
             dry_run: false,
         };
 
-        let display = executer.format_command_display(&args);
+        let display = format_command_line("immich", &executer.build_command_args(&args));
         assert_eq!(
             display,
-            r#"immich upload -H -r -c 24 -A "grandchildA" "/base/child1/grandchildA""#
+            "immich upload -H -r -c 24 -A grandchildA /base/child1/grandchildA"
         );
     }
 
+    #[test]
+    fn test_command_reflects_cli_settings() {
+        let executer = Executer::new().with_cli_settings(CliSettings {
+            command: "/opt/immich cli/immich".into(),
+            concurrency: 4,
+            include_hidden: false,
+            recursive: true,
+            extra_args: vec!["--ignore".into(), "**/*.tmp".into()],
+            container: None,
+        });
+        let args = ExecuteArgs {
+            paths: vec!["/photos/Tom's party".into()],
+            album_name: "Tom's party".into(),
+            dry_run: false,
+        };
+
+        let command_args = executer.build_command_args(&args);
+        assert_eq!(
+            command_args,
+            vec![
+                "upload",
+                "-r",
+                "-c",
+                "4",
+                "--ignore",
+                "**/*.tmp",
+                "-A",
+                "Tom's party",
+                "/photos/Tom's party"
+            ]
+        );
+        assert_eq!(
+            format_command_line("/opt/immich cli/immich", &command_args),
+            r#"'/opt/immich cli/immich' upload -r -c 4 --ignore '**/*.tmp' -A 'Tom'\''s party' '/photos/Tom'\''s party'"#
+        );
+    }
+
+    #[test]
+    fn test_command_without_flags_the_cli_lacks() {
+        let executer = Executer::new().with_cli_flags(CliFlags {
+            include_hidden: false,
+            concurrency: false,
+        });
+        let args = ExecuteArgs {
+            paths: vec!["/photos/summer".into()],
+            album_name: "summer".into(),
+            dry_run: false,
+        };
+
+        assert_eq!(
+            executer.build_command_args(&args),
+            vec!["upload", "-r", "-A", "summer", "/photos/summer"]
+        );
+    }
+
+    #[rstest]
+    #[case("plain-word_1.jpg", "plain-word_1.jpg")]
+    #[case("", "''")]
+    #[case("two words", "'two words'")]
+    #[case("$HOME", "'$HOME'")]
+    #[case("it's", r"'it'\''s'")]
+    fn test_shell_quote(#[case] arg: &str, #[case] expected: &str) {
+        assert_eq!(shell_quote(arg), expected);
+    }
+
     #[rstest]
     #[case("/base/child1/grandchildA", "grandchildA", true)]
     #[case("/base/child2/other", "child2", true)]
@@ -332,12 +880,12 @@ mod tests {
     ) {
         let executer = Executer::new();
         let args = ExecuteArgs {
-            path: path.into(),
+            paths: vec![path.into()],
             album_name: album_name.into(),
             dry_run,
         };
 
-        let result = executer.execute(&args);
+        let result = executer.execute(&args, &CancellationToken::new());
 
         // In dry run mode, we don't check for immich CLI, so it should always succeed
         assert!(
@@ -350,12 +898,12 @@ mod tests {
     fn test_execute_trait() {
         let executer: Box<dyn Execute> = Box::new(Executer::new());
         let args = ExecuteArgs {
-            path: "/test/path".into(),
+            paths: vec!["/test/path".into()],
             album_name: "test_album".into(),
             dry_run: true,
         };
 
-        let result = executer.execute(&args);
+        let result = executer.execute(&args, &CancellationToken::new());
 
         // In dry run mode, we don't check for immich CLI, so it should always succeed
         assert!(
@@ -365,21 +913,498 @@ mod tests {
     }
 
     #[test]
-    fn test_check_immich_cli_not_found() {
-        let immich_found = is_immich_cli_installed();
-        let result = Executer::check_immich_cli_exists();
+    fn test_cli_version() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let script = dir.path().join("immich");
+        fs::write(&script, "#!/bin/sh\necho 2.2.3\n").unwrap();
+        fs::set_permissions(&script, std::os::unix::fs::PermissionsExt::from_mode(0o755)).unwrap();
 
-        if immich_found {
-            assert!(result.is_ok(), "Expected success when immich CLI is found");
-        } else {
-            assert!(
-                result.is_err(),
-                "Expected error when immich CLI is not found"
-            );
-            assert!(matches!(
-                result.unwrap_err(),
-                ExecuteError::ImmichCliNotFound(_)
-            ));
+        let version = Executer::new()
+            .with_cli_command(&script.to_string_lossy())
+            .cli_version();
+
+        assert_eq!(version.unwrap(), "2.2.3");
+    }
+
+    #[test]
+    fn test_cli_version_not_found() {
+        let result = Executer::new()
+            .with_cli_command("/nonexistent/immich")
+            .cli_version();
+
+        assert!(
+            matches!(result, Err(ExecuteError::ImmichCliNotFound(_))),
+            "{result:?}"
+        );
+    }
+
+    #[test]
+    fn test_cli_version_from_container_image() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let executer = fake_runtime(dir.path(), "echo 2.2.3");
+
+        let version = executer.cli_version().unwrap();
+
+        assert_eq!(version, "2.2.3");
+        let argv = fs::read_to_string(dir.path().join("argv")).unwrap();
+        assert_eq!(
+            argv.lines().collect::<Vec<_>>(),
+            [
+                "run",
+                "--rm",
+                "ghcr.io/immich-app/immich-cli:latest",
+                "--version"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_execute_cancelled_before_start() {
+        let executer = Executer::new();
+        let args = ExecuteArgs {
+            paths: vec!["/test/path".into()],
+            album_name: "test_album".into(),
+            dry_run: true,
+        };
+        let cancel = CancellationToken::new();
+        cancel.cancel(libc::SIGINT);
+
+        let result = executer.execute(&args, &cancel);
+
+        assert!(matches!(
+            result,
+            Err(UploadError {
+                error: ExecuteError::Cancelled,
+                ..
+            })
+        ));
+    }
+
+    /// Write a fake CLI that starts a worker in the background, like the Immich CLI does, and
+    /// records both pids. The worker inherits the output pipes. `foreground` runs last and can
+    /// touch `$READY` once it is set up.
+    fn fake_cli(dir: &std::path::Path, foreground: &str) -> std::path::PathBuf {
+        // As in `main`, so the workers the script leaves behind can be reaped
+        become_child_subreaper();
+        let script = dir.join("immich");
+        let dir = dir.display();
+        fs::write(
+            &script,
+            format!(
+                "#!/bin/sh\n\
+                 [ \"$1\" = --version ] && exit 0\n\
+                 READY={dir}/ready\n\
+                 echo $$ > {dir}/cli.pid\n\
+                 sh -c 'echo $$ > {dir}/worker.tmp && mv {dir}/worker.tmp {dir}/worker.pid; exec sleep 60' &\n\
+                 while [ ! -f {dir}/worker.pid ]; do sleep 0.01; done\n\
+                 echo started\n\
+                 {foreground}\n"
+            ),
+        )
+        .unwrap();
+        fs::set_permissions(&script, std::os::unix::fs::PermissionsExt::from_mode(0o755)).unwrap();
+        script
+    }
+
+    fn read_pid(path: &std::path::Path) -> libc::pid_t {
+        fs::read_to_string(path).unwrap().trim().parse().unwrap()
+    }
+
+    fn process_exists(pid: libc::pid_t) -> bool {
+        // SAFETY: kill(2) with signal 0 only checks for existence.
+        unsafe { libc::kill(pid, 0) == 0 }
+    }
+
+    fn upload_args() -> ExecuteArgs {
+        ExecuteArgs {
+            paths: vec!["/test/path".into()],
+            album_name: "test_album".into(),
+            dry_run: false,
         }
     }
+
+    #[test]
+    fn test_execute_terminates_leftover_workers() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let script = fake_cli(dir.path(), "exit 0");
+        let executer = Executer::new()
+            .with_cli_command(&script.to_string_lossy())
+            .with_grace_period(Duration::from_secs(1));
+
+        let started = Instant::now();
+        let result = executer.execute(&upload_args(), &CancellationToken::new());
+
+        assert!(result.is_ok(), "{result:?}");
+        assert!(started.elapsed() < Duration::from_secs(30));
+        assert!(!process_exists(read_pid(&dir.path().join("worker.pid"))));
+    }
+
+    #[test]
+    fn test_execute_stops_reading_output_held_open_after_exit() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let script = dir.path().join("immich");
+        // The sleep leaves the process group but keeps the output pipes open
+        fs::write(
+            &script,
+            "#!/bin/sh\nsetsid sleep 30 &\necho started\nexit 0\n",
+        )
+        .unwrap();
+        fs::set_permissions(&script, std::os::unix::fs::PermissionsExt::from_mode(0o755)).unwrap();
+        let executer = Executer::new()
+            .with_cli_command(&script.to_string_lossy())
+            .with_grace_period(Duration::from_secs(1))
+            .with_echo_output(false);
+
+        let started = Instant::now();
+        let result = executer.execute(&upload_args(), &CancellationToken::new());
+
+        assert!(result.is_ok(), "{result:?}");
+        assert!(started.elapsed() < Duration::from_secs(10));
+    }
+
+    #[test]
+    fn test_execute_sends_output_to_sink() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let script = fake_cli(dir.path(), "echo 'Found 2 new files and 0 duplicates' >&2");
+        let lines = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let sink_lines = Arc::clone(&lines);
+        let executer = Executer::new()
+            .with_cli_command(&script.to_string_lossy())
+            .with_grace_period(Duration::from_secs(1))
+            .with_echo_output(false)
+            .with_output_sink(Arc::new(move |stream, line: &str| {
+                sink_lines.lock().unwrap().push((stream, line.to_string()));
+            }));
+
+        let result = executer.execute(&upload_args(), &CancellationToken::new());
+
+        assert!(result.is_ok(), "{result:?}");
+        let mut lines = lines.lock().unwrap().clone();
+        lines.sort_by(|a, b| a.1.cmp(&b.1));
+        assert_eq!(
+            lines,
+            [
+                (
+                    OutputStream::Stderr,
+                    "Found 2 new files and 0 duplicates".to_string()
+                ),
+                (OutputStream::Stdout, "started".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn test_execute_failure_carries_stats() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let script = fake_cli(
+            dir.path(),
+            "echo 'Found 12 new files and 0 duplicates'\n\
+             echo 'Successfully uploaded 10 new assets (24.8 MB)'\n\
+             echo 'Failed to upload 2 assets:'\n\
+             echo '- /photos/IMG_0007.jpg: Error: socket hang up'\n\
+             echo '- /photos/IMG_0008.jpg: Error: socket hang up'\n\
+             exit 1",
+        );
+        let executer = Executer::new()
+            .with_cli_command(&script.to_string_lossy())
+            .with_grace_period(Duration::from_secs(1))
+            .with_echo_output(false);
+
+        let result = executer.execute(&upload_args(), &CancellationToken::new());
+
+        match result {
+            Err(UploadError {
+                error: ExecuteError::PartialSuccess(_),
+                stats,
+            }) => assert_eq!(
+                stats,
+                UploadStats {
+                    new: 12,
+                    duplicates: 0,
+                    uploaded: 10,
+                    failed: 2,
+                    uploaded_bytes: Some(24_800_000),
+                }
+            ),
+            other => panic!("Expected a partial success, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_execute_passes_managed_credentials_to_native_cli() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let script = fake_cli(
+            dir.path(),
+            &format!(
+                "echo \"$IMMICH_CONFIG_DIR $IMMICH_INSTANCE_URL $IMMICH_API_KEY\" > {}/env",
+                dir.path().display()
+            ),
+        );
+        let executer = Executer::new()
+            .with_cli_command(&script.to_string_lossy())
+            .with_grace_period(Duration::from_secs(1))
+            .with_credentials(test_credentials())
+            .with_cli_config_dir(PathBuf::from("/config/cli/nas"));
+
+        let result = executer.execute(&upload_args(), &CancellationToken::new());
+
+        assert!(result.is_ok(), "{result:?}");
+        assert_eq!(
+            fs::read_to_string(dir.path().join("env")).unwrap().trim(),
+            "/config/cli/nas http://immich:2283/api secret-key"
+        );
+    }
+
+    #[rstest]
+    #[case::terminated("touch $READY; sleep 60")]
+    #[case::killed_after_grace_period("trap '' TERM; touch $READY; while true; do sleep 0.1; done")]
+    fn test_execute_abort_kills_process_group(#[case] foreground: &str) {
+        let dir = tempfile::TempDir::new().unwrap();
+        let script = fake_cli(dir.path(), foreground);
+        let executer = Executer::new()
+            .with_cli_command(&script.to_string_lossy())
+            .with_grace_period(Duration::from_secs(1));
+        let cancel = CancellationToken::new();
+
+        let ready_file = dir.path().join("ready");
+        let aborter = {
+            let cancel = cancel.clone();
+            std::thread::spawn(move || {
+                while !ready_file.exists() {
+                    std::thread::sleep(Duration::from_millis(10));
+                }
+                cancel.cancel(libc::SIGINT);
+                cancel.cancel(libc::SIGINT);
+            })
+        };
+
+        let started = Instant::now();
+        let result = executer.execute(&upload_args(), &cancel);
+        aborter.join().unwrap();
+
+        assert!(
+            matches!(
+                result,
+                Err(UploadError {
+                    error: ExecuteError::Cancelled,
+                    ..
+                })
+            ),
+            "{result:?}"
+        );
+        assert!(started.elapsed() < Duration::from_secs(30));
+        assert!(!process_exists(read_pid(&dir.path().join("cli.pid"))));
+        assert!(!process_exists(read_pid(&dir.path().join("worker.pid"))));
+    }
+
+    #[rstest]
+    #[case::stalled(
+        Executer::new().with_stall_timeout(Duration::from_secs(1)),
+        "touch $READY; sleep 60",
+        "produced no output for 1s"
+    )]
+    #[case::wall_clock(
+        Executer::new().with_timeout(Duration::from_secs(2)),
+        "touch $READY; while true; do echo working; sleep 0.2; done",
+        "exceeded the timeout of 2s"
+    )]
+    fn test_execute_times_out(
+        #[case] executer: Executer,
+        #[case] foreground: &str,
+        #[case] expected_reason: &str,
+    ) {
+        let dir = tempfile::TempDir::new().unwrap();
+        let script = fake_cli(dir.path(), foreground);
+        let executer = executer
+            .with_cli_command(&script.to_string_lossy())
+            .with_grace_period(Duration::from_secs(1));
+
+        let started = Instant::now();
+        let result = executer.execute(&upload_args(), &CancellationToken::new());
+
+        match result {
+            Err(UploadError {
+                error: ExecuteError::TimedOut(reason),
+                ..
+            }) => assert_eq!(reason, expected_reason),
+            other => panic!("Expected a timeout, got {other:?}"),
+        }
+        assert!(started.elapsed() < Duration::from_secs(30));
+        assert!(!process_exists(read_pid(&dir.path().join("worker.pid"))));
+    }
+
+    #[test]
+    fn test_execute_with_steady_output_does_not_stall() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let script = fake_cli(
+            dir.path(),
+            "for i in 1 2 3 4 5 6; do echo $i; sleep 0.3; done",
+        );
+        let executer = Executer::new()
+            .with_cli_command(&script.to_string_lossy())
+            .with_stall_timeout(Duration::from_secs(1));
+
+        let result = executer.execute(&upload_args(), &CancellationToken::new());
+
+        assert!(result.is_ok(), "{result:?}");
+    }
+
+    #[rstest]
+    #[case(Duration::from_secs(300), "5m")]
+    #[case(Duration::from_secs(90), "90s")]
+    #[case(Duration::from_secs(0), "0s")]
+    fn test_format_duration(#[case] duration: Duration, #[case] expected: &str) {
+        assert_eq!(format_duration(duration), expected);
+    }
+
+    /// Write a fake container runtime that records its argv, the credentials it received and
+    /// cleanup calls, then runs `foreground` in place of the container.
+    fn fake_runtime(dir: &std::path::Path, foreground: &str) -> Executer {
+        let script = dir.join("docker");
+        let dir_display = dir.display();
+        fs::write(
+            &script,
+            format!(
+                "#!/bin/sh\n\
+                 READY={dir_display}/ready\n\
+                 case \"$1\" in\n\
+                 --version) exit 0 ;;\n\
+                 rm) echo \"$@\" >> {dir_display}/cleanup; exit 0 ;;\n\
+                 esac\n\
+                 printf '%s\\n' \"$@\" > {dir_display}/argv\n\
+                 echo \"$IMMICH_INSTANCE_URL $IMMICH_API_KEY\" > {dir_display}/env\n\
+                 {foreground}\n"
+            ),
+        )
+        .unwrap();
+        fs::set_permissions(&script, std::os::unix::fs::PermissionsExt::from_mode(0o755)).unwrap();
+
+        Executer::new()
+            .with_cli_settings(CliSettings {
+                container: Some(crate::config::ContainerSettings {
+                    runtime: script.to_string_lossy().into(),
+                    image: "ghcr.io/immich-app/immich-cli:latest".into(),
+                    run_args: Vec::new(),
+                }),
+                ..Default::default()
+            })
+            .with_grace_period(Duration::from_secs(1))
+    }
+
+    fn test_credentials() -> ServerCredentials {
+        ServerCredentials {
+            url: "http://immich:2283/api".into(),
+            api_key: "secret-key".into(),
+        }
+    }
+
+    #[test]
+    fn test_container_backend_mounts_album_and_passes_credentials() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let album = dir.path().join("2024/summer");
+        fs::create_dir_all(&album).unwrap();
+        let album = album.to_string_lossy().into_owned();
+        let executer =
+            fake_runtime(dir.path(), "echo uploaded").with_credentials(test_credentials());
+        let args = ExecuteArgs {
+            paths: vec![album.clone().into()],
+            album_name: "summer".into(),
+            dry_run: false,
+        };
+
+        let result = executer.execute(&args, &CancellationToken::new());
+
+        assert!(result.is_ok(), "{result:?}");
+        let argv = fs::read_to_string(dir.path().join("argv")).unwrap();
+        let argv: Vec<&str> = argv.lines().collect();
+        assert_eq!(argv[..4], ["run", "--rm", "--init", "--name"]);
+        assert!(argv[4].starts_with("immich-refresh-"));
+        assert_eq!(
+            argv[5..],
+            [
+                "-e",
+                "IMMICH_INSTANCE_URL",
+                "-e",
+                "IMMICH_API_KEY",
+                "-v",
+                &format!("{album}:/import{album}:ro"),
+                "ghcr.io/immich-app/immich-cli:latest",
+                "upload",
+                "-H",
+                "-r",
+                "-c",
+                "24",
+                "-A",
+                "summer",
+                &format!("/import{album}"),
+            ]
+        );
+        assert_eq!(
+            fs::read_to_string(dir.path().join("env")).unwrap().trim(),
+            "http://immich:2283/api secret-key"
+        );
+        assert!(!dir.path().join("cleanup").exists());
+    }
+
+    #[test]
+    fn test_container_backend_removes_killed_container() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let executer = fake_runtime(
+            dir.path(),
+            "trap '' TERM; touch $READY; while true; do sleep 0.1; done",
+        )
+        .with_credentials(test_credentials());
+        let cancel = CancellationToken::new();
+
+        let ready_file = dir.path().join("ready");
+        let aborter = {
+            let cancel = cancel.clone();
+            std::thread::spawn(move || {
+                while !ready_file.exists() {
+                    std::thread::sleep(Duration::from_millis(10));
+                }
+                cancel.cancel(libc::SIGINT);
+                cancel.cancel(libc::SIGINT);
+            })
+        };
+
+        let result = executer.execute(&upload_args(), &cancel);
+        aborter.join().unwrap();
+
+        assert!(
+            matches!(
+                result,
+                Err(UploadError {
+                    error: ExecuteError::Cancelled,
+                    ..
+                })
+            ),
+            "{result:?}"
+        );
+        let cleanup = fs::read_to_string(dir.path().join("cleanup")).unwrap();
+        assert!(
+            cleanup.starts_with("rm -f immich-refresh-"),
+            "unexpected cleanup: {cleanup}"
+        );
+    }
+
+    #[test]
+    fn test_container_backend_requires_credentials() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let executer = fake_runtime(dir.path(), "exit 0");
+
+        let result = executer.execute(&upload_args(), &CancellationToken::new());
+
+        assert!(
+            matches!(
+                result,
+                Err(UploadError {
+                    error: ExecuteError::AuthFailed(_),
+                    ..
+                })
+            ),
+            "{result:?}"
+        );
+        assert!(!dir.path().join("argv").exists());
+    }
 }
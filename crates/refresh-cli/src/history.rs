@@ -0,0 +1,423 @@
+use crate::config;
+use crate::execute::ExecuteError;
+use crate::log_rotation::utc_datetime;
+use crate::prelude::*;
+use crate::report::{Outcome, RunReport};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const RUNS_DIR: &str = "runs";
+const RECORD_EXTENSION: &str = "json";
+const LOG_EXTENSION: &str = "log";
+/// Runs `history` lists without `--limit`.
+pub const DEFAULT_HISTORY_LIMIT: usize = 20;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RunStatus {
+    /// Still running, or the process ended without finishing the record.
+    Running,
+    Completed,
+    /// The run stopped with an error.
+    Failed,
+    /// The run was cancelled by a signal.
+    Interrupted,
+}
+
+impl RunStatus {
+    /// How a run with `result` ended.
+    pub fn of(result: &Result<Option<RunReport>>, interrupted: bool) -> Self {
+        match result {
+            Err(e) if interrupted && is_cancellation(e) => Self::Interrupted,
+            Err(_) => Self::Failed,
+            Ok(_) if interrupted => Self::Interrupted,
+            Ok(_) => Self::Completed,
+        }
+    }
+}
+
+/// Whether `error` only says that the run stopped for a signal.
+pub fn is_cancellation(error: &anyhow::Error) -> bool {
+    matches!(error.downcast_ref(), Some(ExecuteError::Cancelled))
+}
+
+/// What `history` knows about a run, stored as `runs/<run_id>.json` in the state directory next
+/// to the run's own log file.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RunRecord {
+    pub run_id: Box<str>,
+    pub mode: Box<str>,
+    pub path: Box<str>,
+    pub dry_run: bool,
+    /// Seconds since the epoch.
+    pub started: u64,
+    pub finished: Option<u64>,
+    pub status: RunStatus,
+    pub error: Option<Box<str>>,
+    /// Outcome of every directory, for modes that upload through the CLI.
+    pub report: Option<RunReport>,
+}
+
+/// `runs` in the state directory.
+pub fn runs_dir() -> Result<PathBuf> {
+    Ok(config::state_dir()?.join(RUNS_DIR))
+}
+
+pub fn run_log_path(runs_dir: &Path, run_id: &str) -> PathBuf {
+    runs_dir.join(format!("{}.{}", run_id, LOG_EXTENSION))
+}
+
+fn record_path(runs_dir: &Path, run_id: &str) -> PathBuf {
+    runs_dir.join(format!("{}.{}", run_id, RECORD_EXTENSION))
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// The record of the current run, written when it starts and again when it finishes.
+pub struct RunHistory {
+    path: PathBuf,
+    record: RunRecord,
+}
+
+impl RunHistory {
+    pub fn start(
+        runs_dir: &Path,
+        run_id: &str,
+        mode: &str,
+        base_path: &str,
+        dry_run: bool,
+    ) -> Result<Self> {
+        let history = Self {
+            path: record_path(runs_dir, run_id),
+            record: RunRecord {
+                run_id: run_id.into(),
+                mode: mode.into(),
+                path: base_path.into(),
+                dry_run,
+                started: now(),
+                finished: None,
+                status: RunStatus::Running,
+                error: None,
+                report: None,
+            },
+        };
+        history.write()?;
+        Ok(history)
+    }
+
+    fn write(&self) -> Result<()> {
+        let contents = serde_json::to_string_pretty(&self.record)?;
+        fs::write(&self.path, contents)
+            .with_context(|| format!("Failed to write run record {}", self.path.display()))
+    }
+
+    /// Record how the run ended. Failing to write the record is logged, never fatal.
+    pub fn finish(mut self, result: &Result<Option<RunReport>>, interrupted: bool) {
+        self.record.finished = Some(now());
+        self.record.status = RunStatus::of(result, interrupted);
+        match result {
+            Ok(report) => self.record.report = report.clone(),
+            Err(e) => self.record.error = Some(format!("{:#}", e).into()),
+        }
+
+        match self.write() {
+            Ok(()) => info!("Run report: {}", self.path.display()),
+            Err(e) => error!("{:#}", e),
+        }
+    }
+}
+
+/// Keep the files of the newest `keep` runs; `0` keeps all of them.
+pub fn prune(runs_dir: &Path, keep: usize) -> Result<()> {
+    if keep == 0 {
+        return Ok(());
+    }
+    // Run IDs start with their UTC start time, so they sort chronologically
+    let run_ids: BTreeSet<String> = fs::read_dir(runs_dir)?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| {
+            path.extension().is_some_and(|extension| {
+                extension == RECORD_EXTENSION || extension == LOG_EXTENSION
+            })
+        })
+        .filter_map(|path| Some(path.file_stem()?.to_string_lossy().into_owned()))
+        .collect();
+
+    for run_id in run_ids.iter().take(run_ids.len().saturating_sub(keep)) {
+        for path in [
+            record_path(runs_dir, run_id),
+            run_log_path(runs_dir, run_id),
+        ] {
+            if path.exists() {
+                fs::remove_file(&path)
+                    .with_context(|| format!("Failed to remove {}", path.display()))?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// All readable run records, oldest first.
+pub fn load(runs_dir: &Path) -> Result<Vec<RunRecord>> {
+    let mut paths: Vec<PathBuf> = match fs::read_dir(runs_dir) {
+        Ok(entries) => entries
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| path.extension().is_some_and(|e| e == RECORD_EXTENSION))
+            .collect(),
+        // No run has been recorded yet
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e).with_context(|| format!("Failed to read {}", runs_dir.display())),
+    };
+    paths.sort();
+
+    let mut records = Vec::new();
+    for path in paths {
+        let record = fs::read_to_string(&path)
+            .map_err(anyhow::Error::from)
+            .and_then(|contents| Ok(serde_json::from_str::<RunRecord>(&contents)?));
+        match record {
+            Ok(record) => records.push(record),
+            Err(e) => eprintln!("Skipping unreadable run record {}: {:#}", path.display(), e),
+        }
+    }
+    Ok(records)
+}
+
+fn format_time(seconds: u64) -> String {
+    utc_datetime(UNIX_EPOCH + Duration::from_secs(seconds))
+}
+
+fn format_outcome(record: &RunRecord) -> String {
+    let status = match record.status {
+        RunStatus::Running => "running or ended abnormally",
+        RunStatus::Completed => "completed",
+        RunStatus::Failed => "failed",
+        RunStatus::Interrupted => "interrupted",
+    };
+    match (&record.error, &record.report) {
+        (Some(error), _) => format!("{}: {}", status, error),
+        (None, Some(report)) => format!(
+            "{}: {} completed, {} failed, {} timed out, {} interrupted, {} not started",
+            status,
+            report.count(Outcome::Completed),
+            report.count(Outcome::Failed),
+            report.count(Outcome::TimedOut),
+            report.count(Outcome::Interrupted),
+            report.not_started
+        ),
+        (None, None) => status.to_string(),
+    }
+}
+
+/// A few lines describing `record` for `history`.
+pub fn format_record(runs_dir: &Path, record: &RunRecord) -> String {
+    let dry_run = if record.dry_run { " (dry run)" } else { "" };
+    let finished = match record.finished {
+        Some(finished) => format!(" to {}", format_time(finished)),
+        None => String::new(),
+    };
+    let mut lines = vec![
+        format!(
+            "{}  {}{}  {}",
+            record.run_id, record.mode, dry_run, record.path
+        ),
+        format!(
+            "  {}{} UTC, {}",
+            format_time(record.started),
+            finished,
+            format_outcome(record)
+        ),
+        format!(
+            "  report: {}",
+            record_path(runs_dir, &record.run_id).display()
+        ),
+    ];
+    let log = run_log_path(runs_dir, &record.run_id);
+    if log.exists() {
+        lines.push(format!("  log:    {}", log.display()));
+    }
+    lines.join("\n")
+}
+
+/// Print the newest `limit` runs, oldest first.
+pub fn print_history(limit: usize) -> Result<()> {
+    let runs_dir = runs_dir()?;
+    let records = load(&runs_dir)?;
+    if records.is_empty() {
+        println!("No runs recorded in {}", runs_dir.display());
+        return Ok(());
+    }
+
+    for record in &records[records.len().saturating_sub(limit)..] {
+        println!("{}\n", format_record(&runs_dir, record));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::upload_stats::UploadStats;
+    use pretty_assertions::assert_eq;
+    use tempfile::TempDir;
+
+    fn record(run_id: &str) -> RunRecord {
+        RunRecord {
+            run_id: run_id.into(),
+            mode: "upload".into(),
+            path: "/mnt/photos".into(),
+            dry_run: false,
+            started: 1_792_357_503,
+            finished: Some(1_792_360_030),
+            status: RunStatus::Completed,
+            error: None,
+            report: None,
+        }
+    }
+
+    #[test]
+    fn test_run_status_of_cancelled_run() {
+        let cancelled: Result<Option<RunReport>> = Err(ExecuteError::Cancelled.into());
+        let failed: Result<Option<RunReport>> = Err(anyhow::anyhow!("Failed to relink"));
+
+        assert_eq!(RunStatus::of(&cancelled, true), RunStatus::Interrupted);
+        assert_eq!(RunStatus::of(&failed, true), RunStatus::Failed);
+        assert_eq!(RunStatus::of(&Ok(None), true), RunStatus::Interrupted);
+        assert_eq!(RunStatus::of(&Ok(None), false), RunStatus::Completed);
+    }
+
+    #[test]
+    fn test_run_history_records_start_and_finish() {
+        let temp_dir = TempDir::new().unwrap();
+        let runs_dir = temp_dir.path();
+
+        let history = RunHistory::start(
+            runs_dir,
+            "20261018T210503Z-4711",
+            "upload",
+            "/mnt/photos",
+            false,
+        )
+        .unwrap();
+        let started = load(runs_dir).unwrap();
+        assert_eq!(started.len(), 1);
+        assert_eq!(started[0].status, RunStatus::Running);
+        assert_eq!(started[0].finished, None);
+
+        let mut report = RunReport::default();
+        report.record(
+            "/mnt/photos/2024/summer",
+            "summer",
+            None,
+            Outcome::Completed,
+            UploadStats::default(),
+        );
+        report.not_started = 2;
+        history.finish(&Ok(Some(report.clone())), true);
+
+        let finished = load(runs_dir).unwrap();
+        assert_eq!(finished[0].status, RunStatus::Interrupted);
+        assert!(finished[0].finished.is_some());
+        assert_eq!(finished[0].report, Some(report));
+    }
+
+    #[test]
+    fn test_run_history_records_errors() {
+        let temp_dir = TempDir::new().unwrap();
+
+        let history =
+            RunHistory::start(temp_dir.path(), "run", "relink", "/mnt/photos", false).unwrap();
+        history.finish(&Err(anyhow::anyhow!("server not reachable")), false);
+
+        let records = load(temp_dir.path()).unwrap();
+        assert_eq!(records[0].status, RunStatus::Failed);
+        assert_eq!(records[0].error.as_deref(), Some("server not reachable"));
+    }
+
+    #[test]
+    fn test_load_without_runs() {
+        let temp_dir = TempDir::new().unwrap();
+        assert_eq!(load(&temp_dir.path().join("runs")).unwrap(), Vec::new());
+    }
+
+    #[test]
+    fn test_prune_keeps_newest_runs() {
+        let temp_dir = TempDir::new().unwrap();
+        let runs_dir = temp_dir.path();
+        for run_id in [
+            "20261016T000000Z-1",
+            "20261017T000000Z-2",
+            "20261018T000000Z-3",
+        ] {
+            fs::write(record_path(runs_dir, run_id), "{}").unwrap();
+            fs::write(run_log_path(runs_dir, run_id), "").unwrap();
+        }
+
+        prune(runs_dir, 2).unwrap();
+
+        let mut remaining: Vec<String> = fs::read_dir(runs_dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        remaining.sort();
+        assert_eq!(
+            remaining,
+            vec![
+                "20261017T000000Z-2.json",
+                "20261017T000000Z-2.log",
+                "20261018T000000Z-3.json",
+                "20261018T000000Z-3.log",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_format_record() {
+        let mut report = RunReport::default();
+        report.record(
+            "/mnt/photos/2024/summer",
+            "summer",
+            None,
+            Outcome::Completed,
+            UploadStats::default(),
+        );
+        report.record(
+            "/mnt/photos/2024/winter",
+            "winter",
+            None,
+            Outcome::Failed,
+            UploadStats::default(),
+        );
+        let record = RunRecord {
+            report: Some(report),
+            ..record("20261018T210503Z-4711")
+        };
+
+        assert_eq!(
+            format_record(Path::new("/state/runs"), &record),
+            "20261018T210503Z-4711  upload  /mnt/photos\n  2026-10-18 21:05:03 to 2026-10-18 21:47:10 UTC, completed: 1 completed, 1 failed, 0 timed out, 0 interrupted, 0 not started\n  report: /state/runs/20261018T210503Z-4711.json"
+        );
+    }
+
+    #[test]
+    fn test_format_unfinished_dry_run() {
+        let record = RunRecord {
+            dry_run: true,
+            finished: None,
+            status: RunStatus::Running,
+            ..record("run")
+        };
+
+        assert_eq!(
+            format_record(Path::new("/state/runs"), &record),
+            "run  upload (dry run)  /mnt/photos\n  2026-10-18 21:05:03 UTC, running or ended abnormally\n  report: /state/runs/run.json"
+        );
+    }
+}
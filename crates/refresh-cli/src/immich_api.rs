@@ -0,0 +1,470 @@
+use crate::compat::Version;
+use crate::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use std::time::Duration;
+use ureq::Agent;
+
+const API_KEY_HEADER: &str = "x-api-key";
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// Server URL and API key used to talk to the Immich API directly.
+#[derive(Clone, PartialEq, Eq)]
+pub struct ServerCredentials {
+    pub url: Box<str>,
+    pub api_key: Box<str>,
+}
+
+impl std::fmt::Debug for ServerCredentials {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ServerCredentials")
+            .field("url", &self.url)
+            .field("api_key", &"<redacted>")
+            .finish()
+    }
+}
+
+impl ServerCredentials {
+    /// Read the credentials the same way the Immich CLI does: `IMMICH_INSTANCE_URL` and
+    /// `IMMICH_API_KEY` take precedence, otherwise the `auth.yml` written by `immich login`.
+    pub fn from_environment() -> Result<Self> {
+        if let (Ok(url), Ok(api_key)) =
+            (env::var("IMMICH_INSTANCE_URL"), env::var("IMMICH_API_KEY"))
+        {
+            return Ok(Self {
+                url: normalize_api_url(&url),
+                api_key: api_key.into_boxed_str(),
+            });
+        }
+
+        let auth_file = cli_auth_file()?;
+        let contents = fs::read_to_string(&auth_file).with_context(|| {
+            format!(
+                "Failed to read Immich CLI credentials from {}. Run 'immich login' or set IMMICH_INSTANCE_URL and IMMICH_API_KEY",
+                auth_file.display()
+            )
+        })?;
+
+        parse_auth_file(&contents)
+            .with_context(|| format!("Invalid Immich CLI credentials in {}", auth_file.display()))
+    }
+}
+
+fn cli_auth_file() -> Result<PathBuf> {
+    if let Ok(config_dir) = env::var("IMMICH_CONFIG_DIR") {
+        return Ok(Path::new(&config_dir).join("auth.yml"));
+    }
+
+    let home_dir = env::var("HOME").context("Failed to get HOME environment variable")?;
+
+    Ok(Path::new(&home_dir)
+        .join(".config")
+        .join("immich")
+        .join("auth.yml"))
+}
+
+/// Parse the two-key YAML document (`url:` and `key:`) written by `immich login`.
+pub fn parse_auth_file(contents: &str) -> Result<ServerCredentials> {
+    let mut url = None;
+    let mut api_key = None;
+
+    for line in contents.lines() {
+        let Some((key, value)) = line.split_once(':') else {
+            continue;
+        };
+        let value = value.trim().trim_matches(|c| c == '"' || c == '\'');
+        match key.trim() {
+            "url" => url = Some(value),
+            "key" => api_key = Some(value),
+            _ => {}
+        }
+    }
+
+    match (url, api_key) {
+        (Some(url), Some(api_key)) if !url.is_empty() && !api_key.is_empty() => {
+            Ok(ServerCredentials {
+                url: normalize_api_url(url),
+                api_key: api_key.into(),
+            })
+        }
+        _ => bail!("Expected both 'url' and 'key' entries"),
+    }
+}
+
+/// The CLI stores the API base URL (ending in `/api`); accept the bare server URL as well.
+pub fn normalize_api_url(url: &str) -> Box<str> {
+    let url = url.trim().trim_end_matches('/');
+    if url.ends_with("/api") {
+        url.into()
+    } else {
+        format!("{url}/api").into_boxed_str()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct AssetChecksum {
+    pub id: String,
+    pub checksum: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BulkCheckAction {
+    Accept,
+    Reject,
+}
+
+/// Per-file answer of the bulk upload check. A rejected file with an `asset_id` already exists
+/// on the server.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BulkCheckResult {
+    pub id: String,
+    pub action: BulkCheckAction,
+    #[serde(default)]
+    pub asset_id: Option<String>,
+    #[serde(default)]
+    pub is_trashed: Option<bool>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Album {
+    pub id: String,
+    pub album_name: String,
+}
+
+/// An asset of an album, with what decides whether it may be removed from the album.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AlbumAsset {
+    pub id: String,
+    pub owner_id: String,
+    #[serde(default)]
+    pub is_trashed: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct BulkIdResult {
+    pub id: String,
+    pub success: bool,
+    #[serde(default)]
+    pub error: Option<String>,
+}
+
+/// HTTP status of a failed request, if the server answered at all.
+pub fn status_code(error: &anyhow::Error) -> Option<u16> {
+    match error.downcast_ref::<ureq::Error>() {
+        Some(ureq::Error::StatusCode(status)) => Some(*status),
+        _ => None,
+    }
+}
+
+/// The subset of the Immich server API used by this tool.
+#[cfg_attr(test, mockall::automock)]
+pub trait ImmichApi {
+    /// Check that the server answers; needs no authentication.
+    fn ping(&self) -> Result<()>;
+    /// Version of the server. `None` when no version endpoint answers.
+    fn server_version(&self) -> Result<Option<Version>>;
+    /// Permissions of the API key in use. `None` when the server is too old to report them.
+    fn api_key_permissions(&self) -> Result<Option<Vec<String>>>;
+    /// Look up which of the given checksums already exist on the server.
+    fn check_existing_assets(&self, checksums: &[AssetChecksum]) -> Result<Vec<BulkCheckResult>>;
+    fn list_albums(&self) -> Result<Vec<Album>>;
+    fn create_album(&self, album_name: &str) -> Result<Album>;
+    fn add_assets_to_album(
+        &self,
+        album_id: &str,
+        asset_ids: &[String],
+    ) -> Result<Vec<BulkIdResult>>;
+    /// Id of the user the API key belongs to.
+    fn current_user_id(&self) -> Result<String>;
+    fn album_assets(&self, album_id: &str) -> Result<Vec<AlbumAsset>>;
+    fn remove_assets_from_album(
+        &self,
+        album_id: &str,
+        asset_ids: &[String],
+    ) -> Result<Vec<BulkIdResult>>;
+}
+
+pub struct ImmichClient {
+    agent: Agent,
+    credentials: ServerCredentials,
+}
+
+impl ImmichClient {
+    pub fn new(credentials: ServerCredentials) -> Self {
+        let agent = Agent::config_builder()
+            .timeout_global(Some(REQUEST_TIMEOUT))
+            .build()
+            .into();
+
+        Self { agent, credentials }
+    }
+
+    fn url(&self, endpoint: &str) -> String {
+        format!("{}{}", self.credentials.url, endpoint)
+    }
+}
+
+#[derive(Deserialize)]
+struct ApiKeyResponse {
+    permissions: Vec<String>,
+}
+
+#[derive(Serialize)]
+struct BulkCheckRequest<'a> {
+    assets: &'a [AssetChecksum],
+}
+
+#[derive(Deserialize)]
+struct BulkCheckResponse {
+    results: Vec<BulkCheckResult>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct CreateAlbumRequest<'a> {
+    album_name: &'a str,
+}
+
+#[derive(Deserialize)]
+struct UserResponse {
+    id: String,
+}
+
+#[derive(Deserialize)]
+struct AlbumAssetsResponse {
+    assets: Vec<AlbumAsset>,
+}
+
+#[derive(Serialize)]
+struct BulkIdsRequest<'a> {
+    ids: &'a [String],
+}
+
+impl ImmichApi for ImmichClient {
+    fn ping(&self) -> Result<()> {
+        let url = self.url("/server/ping");
+        self.agent
+            .get(&url)
+            .call()
+            .with_context(|| format!("Request to {} failed", url))?;
+        Ok(())
+    }
+
+    fn server_version(&self) -> Result<Option<Version>> {
+        // Servers before 1.106 serve the version below /server-info
+        for endpoint in ["/server/version", "/server-info/version"] {
+            let url = self.url(endpoint);
+            let response = self
+                .agent
+                .get(&url)
+                .header(API_KEY_HEADER, self.credentials.api_key.as_ref())
+                .call()
+                .and_then(|mut response| response.body_mut().read_json::<Version>());
+            match response {
+                Ok(version) => return Ok(Some(version)),
+                Err(ureq::Error::StatusCode(404)) => continue,
+                Err(e) => {
+                    return Err(anyhow::Error::new(e).context(format!("Request to {} failed", url)))
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    fn api_key_permissions(&self) -> Result<Option<Vec<String>>> {
+        let url = self.url("/api-keys/me");
+        let response = self
+            .agent
+            .get(&url)
+            .header(API_KEY_HEADER, self.credentials.api_key.as_ref())
+            .call()
+            .and_then(|mut response| response.body_mut().read_json::<ApiKeyResponse>());
+        match response {
+            Ok(response) => Ok(Some(response.permissions)),
+            Err(ureq::Error::StatusCode(404)) => Ok(None),
+            Err(e) => Err(anyhow::Error::new(e).context(format!("Request to {} failed", url))),
+        }
+    }
+
+    fn check_existing_assets(&self, checksums: &[AssetChecksum]) -> Result<Vec<BulkCheckResult>> {
+        let url = self.url("/assets/bulk-upload-check");
+        let response: BulkCheckResponse = self
+            .agent
+            .post(&url)
+            .header(API_KEY_HEADER, self.credentials.api_key.as_ref())
+            .send_json(BulkCheckRequest { assets: checksums })
+            .and_then(|mut response| response.body_mut().read_json())
+            .with_context(|| format!("Request to {} failed", url))?;
+
+        Ok(response.results)
+    }
+
+    fn list_albums(&self) -> Result<Vec<Album>> {
+        let url = self.url("/albums");
+        self.agent
+            .get(&url)
+            .header(API_KEY_HEADER, self.credentials.api_key.as_ref())
+            .call()
+            .and_then(|mut response| response.body_mut().read_json())
+            .with_context(|| format!("Request to {} failed", url))
+    }
+
+    fn create_album(&self, album_name: &str) -> Result<Album> {
+        let url = self.url("/albums");
+        self.agent
+            .post(&url)
+            .header(API_KEY_HEADER, self.credentials.api_key.as_ref())
+            .send_json(CreateAlbumRequest { album_name })
+            .and_then(|mut response| response.body_mut().read_json())
+            .with_context(|| format!("Request to {} failed", url))
+    }
+
+    fn add_assets_to_album(
+        &self,
+        album_id: &str,
+        asset_ids: &[String],
+    ) -> Result<Vec<BulkIdResult>> {
+        let url = self.url(&format!("/albums/{album_id}/assets"));
+        self.agent
+            .put(&url)
+            .header(API_KEY_HEADER, self.credentials.api_key.as_ref())
+            .send_json(BulkIdsRequest { ids: asset_ids })
+            .and_then(|mut response| response.body_mut().read_json())
+            .with_context(|| format!("Request to {} failed", url))
+    }
+
+    fn current_user_id(&self) -> Result<String> {
+        let url = self.url("/users/me");
+        let response: UserResponse = self
+            .agent
+            .get(&url)
+            .header(API_KEY_HEADER, self.credentials.api_key.as_ref())
+            .call()
+            .and_then(|mut response| response.body_mut().read_json())
+            .with_context(|| format!("Request to {} failed", url))?;
+
+        Ok(response.id)
+    }
+
+    fn album_assets(&self, album_id: &str) -> Result<Vec<AlbumAsset>> {
+        let url = self.url(&format!("/albums/{album_id}"));
+        let response: AlbumAssetsResponse = self
+            .agent
+            .get(&url)
+            .header(API_KEY_HEADER, self.credentials.api_key.as_ref())
+            .call()
+            .and_then(|mut response| response.body_mut().read_json())
+            .with_context(|| format!("Request to {} failed", url))?;
+
+        Ok(response.assets)
+    }
+
+    fn remove_assets_from_album(
+        &self,
+        album_id: &str,
+        asset_ids: &[String],
+    ) -> Result<Vec<BulkIdResult>> {
+        let url = self.url(&format!("/albums/{album_id}/assets"));
+        self.agent
+            .delete(&url)
+            .header(API_KEY_HEADER, self.credentials.api_key.as_ref())
+            .force_send_body()
+            .send_json(BulkIdsRequest { ids: asset_ids })
+            .and_then(|mut response| response.body_mut().read_json())
+            .with_context(|| format!("Request to {} failed", url))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use rstest::rstest;
+
+    #[rstest]
+    #[case("https://photos.example.com/api", "https://photos.example.com/api")]
+    #[case("https://photos.example.com/api/", "https://photos.example.com/api")]
+    #[case("http://nas.lan:2283", "http://nas.lan:2283/api")]
+    fn test_normalize_api_url(#[case] url: &str, #[case] expected: &str) {
+        assert_eq!(normalize_api_url(url).as_ref(), expected);
+    }
+
+    #[test]
+    fn test_parse_auth_file() {
+        let credentials =
+            parse_auth_file("url: http://nas.lan:2283/api\nkey: \"abc123\"\n").unwrap();
+
+        assert_eq!(
+            credentials,
+            ServerCredentials {
+                url: "http://nas.lan:2283/api".into(),
+                api_key: "abc123".into(),
+            }
+        );
+    }
+
+    #[test]
+    fn test_parse_auth_file_missing_key() {
+        assert!(parse_auth_file("url: http://nas.lan:2283/api\n").is_err());
+    }
+
+    #[test]
+    fn test_deserialize_bulk_check_response() {
+        let response: BulkCheckResponse = serde_json::from_str(
+            r#"{"results":[
+                {"id":"0","action":"reject","reason":"duplicate","assetId":"a1","isTrashed":false},
+                {"id":"1","action":"accept"}
+            ]}"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            response.results,
+            vec![
+                BulkCheckResult {
+                    id: "0".into(),
+                    action: BulkCheckAction::Reject,
+                    asset_id: Some("a1".into()),
+                    is_trashed: Some(false),
+                },
+                BulkCheckResult {
+                    id: "1".into(),
+                    action: BulkCheckAction::Accept,
+                    asset_id: None,
+                    is_trashed: None,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_deserialize_album_assets_response() {
+        let response: AlbumAssetsResponse = serde_json::from_str(
+            r#"{"id":"album","albumName":"summer","assets":[
+                {"id":"a1","ownerId":"u1","isTrashed":true,"type":"IMAGE"},
+                {"id":"a2","ownerId":"u2"}
+            ]}"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            response.assets,
+            vec![
+                AlbumAsset {
+                    id: "a1".into(),
+                    owner_id: "u1".into(),
+                    is_trashed: true,
+                },
+                AlbumAsset {
+                    id: "a2".into(),
+                    owner_id: "u2".into(),
+                    is_trashed: false,
+                },
+            ]
+        );
+    }
+}
@@ -0,0 +1,236 @@
+use crate::prelude::*;
+use crate::redact::redact;
+use crate::syslog::severity;
+use std::fmt;
+use std::io;
+use std::os::unix::net::UnixDatagram;
+use std::path::PathBuf;
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id, Record};
+use tracing::{Event, Metadata, Subscriber};
+use tracing_subscriber::layer;
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::Layer;
+
+/// Socket of journald's native protocol.
+pub const JOURNAL_SOCKET: &str = "/run/systemd/journal/socket";
+/// `SYSLOG_IDENTIFIER=` of journal entries, for `journalctl -t immich-refresh`.
+const SYSLOG_IDENTIFIER: &str = "immich-refresh";
+
+/// Field names and values of a span or event, in recording order.
+#[derive(Default)]
+struct Fields(Vec<(&'static str, String)>);
+
+impl Visit for Fields {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.0.push((field.name(), value.to_string()));
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.0.push((field.name(), format!("{:?}", value)));
+    }
+}
+
+/// Append `name` and `value` in the length-prefixed encoding, which allows any value. Names are
+/// upper case and only keep letters, digits and underscores, as the journal requires.
+fn put_field(entry: &mut Vec<u8>, name: &str, value: &str) {
+    entry.extend(
+        name.bytes()
+            .map(|c| if c == b'.' { b'_' } else { c })
+            .skip_while(|&c| c == b'_')
+            .filter(|&c| c == b'_' || c.is_ascii_alphanumeric())
+            .map(|c| c.to_ascii_uppercase()),
+    );
+    entry.push(b'\n');
+    entry.extend_from_slice(&(value.len() as u64).to_le_bytes());
+    entry.extend_from_slice(value.as_bytes());
+    entry.push(b'\n');
+}
+
+/// A journal entry with the fields of the event's spans, its metadata and its own fields, every
+/// value redacted.
+fn format_entry(metadata: &Metadata<'_>, fields: &[(&str, String)]) -> Vec<u8> {
+    let mut entry = Vec::with_capacity(256);
+    put_field(
+        &mut entry,
+        "PRIORITY",
+        &severity(metadata.level()).to_string(),
+    );
+    put_field(&mut entry, "SYSLOG_IDENTIFIER", SYSLOG_IDENTIFIER);
+    put_field(&mut entry, "TARGET", metadata.target());
+    if let Some(file) = metadata.file() {
+        put_field(&mut entry, "CODE_FILE", file);
+    }
+    if let Some(line) = metadata.line() {
+        put_field(&mut entry, "CODE_LINE", &line.to_string());
+    }
+    for (name, value) in fields {
+        put_field(&mut entry, name, &redact(value));
+    }
+    entry
+}
+
+/// Sends every event to journald over its native protocol, with the fields of its spans
+/// (`RUN_ID=`, `ALBUM=`, ...) as journal fields.
+pub struct JournaldLayer {
+    socket: UnixDatagram,
+    path: PathBuf,
+}
+
+impl JournaldLayer {
+    pub fn connect(path: impl Into<PathBuf>) -> Result<Self> {
+        let path = path.into();
+        let connect = || -> io::Result<UnixDatagram> {
+            // Fail early if journald doesn't listen. Entries are sent to the path each time so
+            // they reach a restarted journald too.
+            UnixDatagram::unbound()?.connect(&path)?;
+            UnixDatagram::unbound()
+        };
+        let socket = connect()
+            .with_context(|| format!("Failed to connect to journald at {}", path.display()))?;
+        Ok(Self { socket, path })
+    }
+}
+
+impl<S> Layer<S> for JournaldLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: layer::Context<'_, S>) {
+        let Some(span) = ctx.span(id) else {
+            return;
+        };
+        let mut fields = Fields::default();
+        attrs.record(&mut fields);
+        span.extensions_mut().insert(fields);
+    }
+
+    fn on_record(&self, id: &Id, values: &Record<'_>, ctx: layer::Context<'_, S>) {
+        let Some(span) = ctx.span(id) else {
+            return;
+        };
+        let mut extensions = span.extensions_mut();
+        if let Some(fields) = extensions.get_mut::<Fields>() {
+            values.record(fields);
+        }
+    }
+
+    fn on_event(&self, event: &Event<'_>, ctx: layer::Context<'_, S>) {
+        let mut fields = Vec::new();
+        if let Some(scope) = ctx.event_scope(event) {
+            for span in scope.from_root() {
+                if let Some(span_fields) = span.extensions().get::<Fields>() {
+                    fields.extend(span_fields.0.iter().cloned());
+                }
+            }
+        }
+        let mut event_fields = Fields::default();
+        event.record(&mut event_fields);
+        fields.extend(event_fields.0);
+
+        // Nothing sensible can be done about a lost entry while logging. Entries too large for
+        // a datagram are lost too, log lines stay far below the limit.
+        let _ = self
+            .socket
+            .send_to(&format_entry(event.metadata(), &fields), &self.path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::redact;
+    use pretty_assertions::assert_eq;
+    use tempfile::TempDir;
+    use tracing_subscriber::layer::SubscriberExt;
+    use tracing_subscriber::Registry;
+
+    /// The fields of an entry in the length-prefixed encoding.
+    fn parse_entry(mut entry: &[u8]) -> Vec<(String, String)> {
+        let mut fields = Vec::new();
+        while let Some(newline) = entry.iter().position(|&b| b == b'\n') {
+            let name = String::from_utf8_lossy(&entry[..newline]).into_owned();
+            let length_start = newline + 1;
+            let length =
+                u64::from_le_bytes(entry[length_start..length_start + 8].try_into().unwrap())
+                    as usize;
+            let value_start = length_start + 8;
+            let value =
+                String::from_utf8_lossy(&entry[value_start..value_start + length]).into_owned();
+            fields.push((name, value));
+            entry = &entry[value_start + length + 1..];
+        }
+        fields
+    }
+
+    fn receive(socket_path: &Path, log: impl FnOnce()) -> Vec<(String, String)> {
+        let receiver = UnixDatagram::bind(socket_path).unwrap();
+        let layer = JournaldLayer::connect(socket_path).unwrap();
+        let subscriber = Registry::default().with(layer);
+        tracing::subscriber::with_default(subscriber, log);
+
+        let mut buffer = [0u8; 4096];
+        let length = receiver.recv(&mut buffer).unwrap();
+        parse_entry(&buffer[..length])
+            .into_iter()
+            .filter(|(name, _)| !name.starts_with("CODE_"))
+            .collect()
+    }
+
+    #[test]
+    fn test_sends_events_with_span_fields() {
+        let temp_dir = TempDir::new().unwrap();
+        let fields = receive(&temp_dir.path().join("socket"), || {
+            let _album = info_span!("album", album = "Summer\nholidays").entered();
+            error!(attempt = 2, "Upload failed");
+        });
+
+        let expected = [
+            ("PRIORITY", "3"),
+            ("SYSLOG_IDENTIFIER", "immich-refresh"),
+            ("TARGET", "immich_refresh::journald::tests"),
+            ("ALBUM", "Summer\nholidays"),
+            ("MESSAGE", "Upload failed"),
+            ("ATTEMPT", "2"),
+        ];
+        assert_eq!(
+            fields,
+            expected.map(|(name, value)| (name.to_string(), value.to_string()))
+        );
+    }
+
+    #[test]
+    fn test_redacts_field_values() {
+        redact::register("journald-secret-key");
+        let temp_dir = TempDir::new().unwrap();
+        let fields = receive(&temp_dir.path().join("socket"), || {
+            let _run = info_span!("run", url = "https://x/?key=journald-secret-key").entered();
+            info!(
+                header = "x-api-key: journald-secret-key",
+                "stdout: key journald-secret-key"
+            );
+        });
+
+        let values: Vec<&str> = fields.iter().map(|(_, value)| value.as_str()).collect();
+        assert_eq!(
+            values[3..],
+            [
+                "https://x/?key=<redacted>",
+                "stdout: key <redacted>",
+                "x-api-key: <redacted>"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_put_field_sanitizes_name() {
+        let mut entry = Vec::new();
+        put_field(&mut entry, "_log.target-name", "a");
+        assert_eq!(entry, b"LOG_TARGETNAME\n\x01\0\0\0\0\0\0\0a\n");
+    }
+
+    #[test]
+    fn test_connect_failure() {
+        assert!(JournaldLayer::connect("/nonexistent/socket").is_err());
+    }
+}
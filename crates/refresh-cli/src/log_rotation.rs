@@ -0,0 +1,538 @@
+use crate::config::{LogSettings, Rotation};
+use crate::prelude::*;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::sync::{Mutex, MutexGuard};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing_subscriber::fmt::MakeWriter;
+
+const SECONDS_PER_HOUR: u64 = 60 * 60;
+const SECONDS_PER_DAY: u64 = 24 * SECONDS_PER_HOUR;
+
+/// A log file that is rotated by time and size. The active file keeps its name; rotated files
+/// get a UTC timestamp (`run-20261018T210000Z.log`, gzipped to `.log.gz`) and are pruned by the
+/// retention settings.
+pub struct RotatingFile {
+    path: PathBuf,
+    settings: LogSettings,
+    state: Mutex<State>,
+}
+
+struct State {
+    file: fs::File,
+    size: u64,
+    /// The rotation period the file's contents belong to.
+    period: Option<u64>,
+}
+
+/// The hour or day `time` falls into, counted from the epoch.
+fn period(rotation: Rotation, time: SystemTime) -> Option<u64> {
+    let seconds = time
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    match rotation {
+        Rotation::Never => None,
+        Rotation::Hourly => Some(seconds / SECONDS_PER_HOUR),
+        Rotation::Daily => Some(seconds / SECONDS_PER_DAY),
+    }
+}
+
+/// Year, month, day, hour, minute and second of `time` in UTC.
+fn utc_fields(time: SystemTime) -> [u64; 6] {
+    let seconds = time
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let (days, time_of_day) = (seconds / SECONDS_PER_DAY, seconds % SECONDS_PER_DAY);
+
+    // Civil date from days since the epoch, after Howard Hinnant's `civil_from_days`
+    let z = days as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1_460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+
+    [
+        year as u64,
+        month as u64,
+        day as u64,
+        time_of_day / SECONDS_PER_HOUR,
+        time_of_day % SECONDS_PER_HOUR / 60,
+        time_of_day % 60,
+    ]
+}
+
+/// `20261018T210503Z`
+pub fn utc_timestamp(time: SystemTime) -> String {
+    let [year, month, day, hour, minute, second] = utc_fields(time);
+    format!("{year:04}{month:02}{day:02}T{hour:02}{minute:02}{second:02}Z")
+}
+
+/// `2026-10-18T21:05:03.512Z`, as RFC 3339 and syslog expect.
+pub fn utc_rfc3339(time: SystemTime) -> String {
+    let [year, month, day, hour, minute, second] = utc_fields(time);
+    let millis = time
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .subsec_millis();
+    format!("{year:04}-{month:02}-{day:02}T{hour:02}:{minute:02}:{second:02}.{millis:03}Z")
+}
+
+/// `2026-10-18 21:05:03`
+pub fn utc_datetime(time: SystemTime) -> String {
+    let [year, month, day, hour, minute, second] = utc_fields(time);
+    format!("{year:04}-{month:02}-{day:02} {hour:02}:{minute:02}:{second:02}")
+}
+
+fn open_append(path: &Path) -> io::Result<fs::File> {
+    fs::OpenOptions::new().create(true).append(true).open(path)
+}
+
+impl RotatingFile {
+    /// Open `path` for appending. A file left from an earlier period is rotated right away.
+    pub fn open(path: &Path, settings: LogSettings) -> Result<Self> {
+        let file = open_append(path)
+            .with_context(|| format!("Failed to open log file {}", path.display()))?;
+        let metadata = file.metadata()?;
+        let modified = metadata.modified().unwrap_or_else(|_| SystemTime::now());
+        let state = State {
+            file,
+            size: metadata.len(),
+            period: period(settings.rotation, modified),
+        };
+
+        let rotating_file = Self {
+            path: path.to_path_buf(),
+            settings,
+            state: Mutex::new(state),
+        };
+        {
+            let mut state = rotating_file.lock();
+            if state.size > 0 && rotating_file.period_ended(&state, SystemTime::now()) {
+                rotating_file.rotate(&mut state, SystemTime::now())?;
+            }
+        }
+        Ok(rotating_file)
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn period_ended(&self, state: &State, now: SystemTime) -> bool {
+        state.period != period(self.settings.rotation, now)
+    }
+
+    fn needs_rotation(&self, state: &State, incoming: usize, now: SystemTime) -> bool {
+        if state.size == 0 {
+            return false;
+        }
+        let too_large = self
+            .settings
+            .max_size
+            .is_some_and(|max_size| state.size + incoming as u64 > max_size);
+        too_large || self.period_ended(state, now)
+    }
+
+    /// Path for the file rotated at `now`, `run-<timestamp>.log`. Files rotated within the same
+    /// second get a counter past the highest one in use, so they keep their order even after
+    /// retention removed the earlier ones.
+    fn rotated_path(&self, now: SystemTime) -> io::Result<PathBuf> {
+        let stem = self.file_stem();
+        let timestamp = utc_timestamp(now);
+        let counter = self
+            .rotated_files()?
+            .into_iter()
+            .map(|(_, order)| order)
+            .filter(|(rotated_at, _)| *rotated_at == timestamp)
+            .map(|(_, counter)| counter + 1)
+            .max()
+            .unwrap_or(0);
+        let suffix = if counter == 0 {
+            String::new()
+        } else {
+            format!("-{}", counter)
+        };
+        Ok(self.path.with_file_name(format!(
+            "{}-{}{}.{}",
+            stem,
+            timestamp,
+            suffix,
+            self.file_extension()
+        )))
+    }
+
+    fn file_stem(&self) -> String {
+        self.path
+            .file_stem()
+            .map_or("run".into(), |stem| stem.to_string_lossy().into_owned())
+    }
+
+    fn file_extension(&self) -> String {
+        self.path.extension().map_or("log".into(), |extension| {
+            extension.to_string_lossy().into_owned()
+        })
+    }
+
+    fn rotate(&self, state: &mut State, now: SystemTime) -> io::Result<()> {
+        state.file.flush()?;
+        let rotated = self.rotated_path(now)?;
+        fs::rename(&self.path, &rotated)?;
+        state.file = open_append(&self.path)?;
+        state.size = 0;
+        state.period = period(self.settings.rotation, now);
+
+        if self.settings.compress {
+            compress(&rotated)?;
+        }
+        self.apply_retention(now)
+    }
+
+    /// Rotated files of this log with their `rotation_order`, oldest first. Other files sharing
+    /// the log's name, like `run-old.log`, are left alone.
+    fn rotated_files(&self) -> io::Result<Vec<(PathBuf, (String, u32))>> {
+        let Some(dir) = self.path.parent() else {
+            return Ok(Vec::new());
+        };
+        let prefix = format!("{}-", self.file_stem());
+        let extension = format!(".{}", self.file_extension());
+
+        let mut files: Vec<(PathBuf, (String, u32))> = fs::read_dir(dir)?
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter_map(|path| {
+                let name = path.file_name()?.to_str()?;
+                let name = name.strip_suffix(".gz").unwrap_or(name);
+                let rest = name.strip_prefix(&prefix)?.strip_suffix(&extension)?;
+                let order = rotation_order(rest)?;
+                Some((path, order))
+            })
+            .collect();
+        files.sort_by(|(_, a), (_, b)| a.cmp(b));
+        Ok(files)
+    }
+
+    fn apply_retention(&self, now: SystemTime) -> io::Result<()> {
+        let mut files: Vec<PathBuf> = self
+            .rotated_files()?
+            .into_iter()
+            .map(|(path, _)| path)
+            .collect();
+
+        if let Some(max_age_days) = self.settings.max_age_days {
+            let cutoff = now
+                .checked_sub(Duration::from_secs(max_age_days * SECONDS_PER_DAY))
+                .unwrap_or(UNIX_EPOCH);
+            let (expired, kept): (Vec<PathBuf>, Vec<PathBuf>) =
+                files.into_iter().partition(|file| {
+                    fs::metadata(file)
+                        .and_then(|metadata| metadata.modified())
+                        .is_ok_and(|modified| modified < cutoff)
+                });
+            for file in expired {
+                fs::remove_file(file)?;
+            }
+            files = kept;
+        }
+
+        let max_files = self.settings.max_files;
+        if max_files > 0 && files.len() > max_files {
+            for file in &files[..files.len() - max_files] {
+                fs::remove_file(file)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Timestamp and collision counter of a rotated file from the `<timestamp>[-<counter>]` part of
+/// its name, which order the files chronologically. `None` for any other name.
+fn rotation_order(name: &str) -> Option<(String, u32)> {
+    let (timestamp, suffix) = name.split_at_checked(16)?;
+    let is_timestamp = timestamp.bytes().enumerate().all(|(index, c)| match index {
+        8 => c == b'T',
+        15 => c == b'Z',
+        _ => c.is_ascii_digit(),
+    });
+    if !is_timestamp {
+        return None;
+    }
+    let counter = match suffix.strip_prefix('-') {
+        Some(counter) if counter.bytes().all(|c| c.is_ascii_digit()) => counter.parse().ok()?,
+        Some(_) => return None,
+        None if suffix.is_empty() => 0,
+        None => return None,
+    };
+    Some((timestamp.to_string(), counter))
+}
+
+fn gz_path(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_os_string();
+    name.push(".gz");
+    PathBuf::from(name)
+}
+
+/// Replace `path` with a gzipped copy, keeping its modification time for the retention by age.
+fn compress(path: &Path) -> io::Result<()> {
+    let compressed_path = gz_path(path);
+    let mut input = fs::File::open(path)?;
+    let modified = input.metadata()?.modified()?;
+    let output = fs::File::create(&compressed_path)?;
+    let mut encoder = GzEncoder::new(output, Compression::default());
+    io::copy(&mut input, &mut encoder)?;
+    encoder.finish()?.set_modified(modified)?;
+    fs::remove_file(path)
+}
+
+impl Write for &RotatingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut state = self.lock();
+        let now = SystemTime::now();
+        if self.needs_rotation(&state, buf.len(), now) {
+            // Keep logging into the current file rather than losing messages
+            if let Err(e) = self.rotate(&mut state, now) {
+                eprintln!("Failed to rotate log file {}: {}", self.path.display(), e);
+                state.period = period(self.settings.rotation, now);
+            }
+        }
+        state.file.write_all(buf)?;
+        state.size += buf.len() as u64;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.lock().file.flush()
+    }
+}
+
+impl<'a> MakeWriter<'a> for RotatingFile {
+    type Writer = &'a RotatingFile;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::read::GzDecoder;
+    use pretty_assertions::assert_eq;
+    use rstest::rstest;
+    use std::io::Read;
+    use tempfile::TempDir;
+
+    fn settings(max_size: Option<u64>, max_files: usize, compress: bool) -> LogSettings {
+        LogSettings {
+            rotation: Rotation::Never,
+            max_size,
+            max_files,
+            max_age_days: None,
+            compress,
+            ..LogSettings::default()
+        }
+    }
+
+    fn rotated(dir: &Path) -> Vec<String> {
+        let mut names: Vec<String> = fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .filter(|name| name != "run.log")
+            .collect();
+        names.sort();
+        names
+    }
+
+    #[rstest]
+    #[case(0, "19700101T000000Z")]
+    #[case(951_782_400, "20000229T000000Z")]
+    #[case(1_792_357_503, "20261018T210503Z")]
+    fn test_utc_timestamp(#[case] seconds: u64, #[case] expected: &str) {
+        assert_eq!(
+            utc_timestamp(UNIX_EPOCH + Duration::from_secs(seconds)),
+            expected
+        );
+    }
+
+    #[test]
+    fn test_utc_datetime() {
+        assert_eq!(
+            utc_datetime(UNIX_EPOCH + Duration::from_secs(1_792_357_503)),
+            "2026-10-18 21:05:03"
+        );
+    }
+
+    #[test]
+    fn test_utc_rfc3339() {
+        assert_eq!(
+            utc_rfc3339(UNIX_EPOCH + Duration::from_millis(1_792_357_503_512)),
+            "2026-10-18T21:05:03.512Z"
+        );
+    }
+
+    #[test]
+    fn test_period() {
+        let time = UNIX_EPOCH + Duration::from_secs(2 * SECONDS_PER_DAY + 3 * SECONDS_PER_HOUR);
+        assert_eq!(period(Rotation::Never, time), None);
+        assert_eq!(period(Rotation::Daily, time), Some(2));
+        assert_eq!(period(Rotation::Hourly, time), Some(51));
+    }
+
+    #[test]
+    fn test_rotates_by_size() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("run.log");
+        let log = RotatingFile::open(&path, settings(Some(10), 0, false)).unwrap();
+
+        (&log).write_all(b"first 8\n").unwrap();
+        (&log).write_all(b"second 9\n").unwrap();
+
+        assert_eq!(fs::read_to_string(&path).unwrap(), "second 9\n");
+        let rotated = rotated(temp_dir.path());
+        assert_eq!(rotated.len(), 1);
+        assert!(rotated[0].starts_with("run-") && rotated[0].ends_with(".log"));
+        assert_eq!(
+            fs::read_to_string(temp_dir.path().join(&rotated[0])).unwrap(),
+            "first 8\n"
+        );
+    }
+
+    #[test]
+    fn test_compresses_rotated_files() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("run.log");
+        let log = RotatingFile::open(&path, settings(Some(10), 0, true)).unwrap();
+
+        (&log).write_all(b"first 8\n").unwrap();
+        (&log).write_all(b"second 9\n").unwrap();
+
+        let rotated = rotated(temp_dir.path());
+        assert_eq!(rotated.len(), 1);
+        assert!(rotated[0].ends_with(".log.gz"), "{rotated:?}");
+        let mut contents = String::new();
+        GzDecoder::new(fs::File::open(temp_dir.path().join(&rotated[0])).unwrap())
+            .read_to_string(&mut contents)
+            .unwrap();
+        assert_eq!(contents, "first 8\n");
+    }
+
+    #[test]
+    fn test_keeps_max_files() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("run.log");
+        let log = RotatingFile::open(&path, settings(Some(5), 2, false)).unwrap();
+
+        for line in ["one\n", "two\n", "three\n", "four\n", "five\n"] {
+            (&log).write_all(line.as_bytes()).unwrap();
+        }
+
+        let rotated = rotated(temp_dir.path());
+        assert_eq!(rotated.len(), 2);
+        let contents: Vec<String> = rotated
+            .iter()
+            .map(|name| fs::read_to_string(temp_dir.path().join(name)).unwrap())
+            .collect();
+        assert_eq!(contents, vec!["three\n", "four\n"]);
+        assert_eq!(fs::read_to_string(&path).unwrap(), "five\n");
+    }
+
+    #[rstest]
+    #[case("20261018T210503Z", Some(("20261018T210503Z", 0)))]
+    #[case("20261018T210503Z-12", Some(("20261018T210503Z", 12)))]
+    #[case("foo", None)]
+    #[case("20261018T210503Z-", None)]
+    #[case("20261018T210503Z-x", None)]
+    #[case("20261018T210503Z.old", None)]
+    #[case("20261018X210503Z", None)]
+    fn test_rotation_order(#[case] name: &str, #[case] expected: Option<(&str, u32)>) {
+        assert_eq!(
+            rotation_order(name),
+            expected.map(|(timestamp, counter)| (timestamp.to_string(), counter))
+        );
+    }
+
+    #[test]
+    fn test_retention_keeps_foreign_files() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("run.log");
+        let foreign = [
+            "run-foo.log",
+            "run-20261018T210503Z.log.bak",
+            "run-2026.log.gz",
+        ];
+        for name in foreign {
+            fs::write(temp_dir.path().join(name), "keep").unwrap();
+        }
+        let log = RotatingFile::open(&path, settings(Some(5), 1, false)).unwrap();
+
+        for line in ["one\n", "two\n", "three\n"] {
+            (&log).write_all(line.as_bytes()).unwrap();
+        }
+
+        for name in foreign {
+            assert!(temp_dir.path().join(name).exists(), "{name} was removed");
+        }
+        assert_eq!(rotated(temp_dir.path()).len(), foreign.len() + 1);
+    }
+
+    #[test]
+    fn test_rotates_file_from_earlier_period_on_open() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("run.log");
+        fs::write(&path, "yesterday\n").unwrap();
+        fs::File::options()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_modified(SystemTime::now() - Duration::from_secs(2 * SECONDS_PER_DAY))
+            .unwrap();
+
+        let _log = RotatingFile::open(
+            &path,
+            LogSettings {
+                rotation: Rotation::Daily,
+                ..settings(None, 0, false)
+            },
+        )
+        .unwrap();
+
+        assert_eq!(fs::read_to_string(&path).unwrap(), "");
+        assert_eq!(rotated(temp_dir.path()).len(), 1);
+    }
+
+    #[test]
+    fn test_removes_files_older_than_max_age() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("run.log");
+        let old = temp_dir.path().join("run-20200101T000000Z.log.gz");
+        fs::write(&old, "old").unwrap();
+        fs::File::options()
+            .write(true)
+            .open(&old)
+            .unwrap()
+            .set_modified(SystemTime::now() - Duration::from_secs(100 * SECONDS_PER_DAY))
+            .unwrap();
+        let log = RotatingFile::open(
+            &path,
+            LogSettings {
+                max_age_days: Some(30),
+                ..settings(Some(5), 0, false)
+            },
+        )
+        .unwrap();
+
+        (&log).write_all(b"one\n").unwrap();
+        (&log).write_all(b"two\n").unwrap();
+
+        let rotated = rotated(temp_dir.path());
+        assert_eq!(rotated.len(), 1);
+        assert!(!old.exists());
+    }
+}
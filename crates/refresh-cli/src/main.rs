@@ -1,14 +1,133 @@
+mod cancel;
+mod classify;
+mod compat;
+mod config;
+mod container;
+mod credentials;
+mod dashboard;
+mod events;
 mod execute;
+mod history;
+mod immich_api;
+mod journald;
+mod log_rotation;
+mod pg_dump;
+mod preflight;
 mod prelude;
+mod progress;
+mod reconcile;
+mod redact;
+mod relink;
+mod report;
+mod restore;
+mod syslog;
 mod tracing_config;
 mod traverse;
+mod upload_stats;
+mod users;
 
 use prelude::*;
+use std::io::IsTerminal;
+use std::process::ExitCode;
+use std::time::Duration;
 
-#[derive(Debug, PartialEq, Eq)]
+const USAGE: &str = "Usage: immich-refresh <path> [--dry-run] [<options>] [<upload options>]
+       immich-refresh relink <path> [--dry-run] [<options>]
+       immich-refresh reconcile <path> [--dry-run] [--prune] [--yes] [<options>]
+       immich-refresh restore-albums <dump.sql> [--map-prefix FROM=TO]... [--dry-run] [<options>] [<upload options>]
+       immich-refresh history [--limit <n>]
+Options: [--config <file>] [--profile <name>] [-v | -vv | -q] [--log-format <text|json>]
+         [--log-file <file> | --no-log-file] [--events jsonl[:<file>]]
+Upload options: [--grace-period <seconds>] [--timeout <minutes>] [--stall-timeout <minutes>]
+                [--no-cli-output]
+                [--cli-path <path>] [--concurrency <n>] [--no-hidden] [--no-recursive]
+                [--upload-arg <arg>]... [--allow-destructive] [--tui]
+                [--container-runtime <docker|podman|native>] [--container-image <image>]";
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    /// Upload every album directory with the Immich CLI.
+    #[default]
+    Upload,
+    /// Add assets that already exist on the server to their albums without uploading.
+    Relink,
+    /// Add missing assets to albums and optionally prune assets that no longer belong.
+    Reconcile,
+    /// Recreate albums from a `pg_dump` of the Immich database; `path` is the dump file.
+    RestoreAlbums,
+    /// List past runs; takes no path.
+    History,
+}
+
+impl Mode {
+    /// The subcommand, as recorded in the run history.
+    fn name(self) -> &'static str {
+        match self {
+            Mode::Upload => "upload",
+            Mode::Relink => "relink",
+            Mode::Reconcile => "reconcile",
+            Mode::RestoreAlbums => "restore-albums",
+            Mode::History => "history",
+        }
+    }
+
+    /// Whether this mode uploads through the Immich CLI.
+    fn runs_cli(self) -> bool {
+        matches!(self, Mode::Upload | Mode::RestoreAlbums)
+    }
+}
+
+#[derive(Debug, Default, PartialEq, Eq)]
 pub struct Arguments {
+    pub mode: Mode,
     pub path: Box<str>,
     pub dry_run: bool,
+    /// Remove assets from albums that no longer belong to them (reconcile only).
+    pub prune: bool,
+    /// Skip the confirmation prompt before pruning.
+    pub assume_yes: bool,
+    /// Server to local path prefixes (restore-albums only).
+    pub path_mappings: Vec<restore::PathMapping>,
+    /// Time the Immich CLI gets to exit after an abort before it is killed.
+    pub grace_period: Option<Duration>,
+    /// Wall-clock limit for uploading a single directory.
+    pub timeout: Option<Duration>,
+    /// Terminate an upload that produced no output for this long.
+    pub stall_timeout: Option<Duration>,
+    /// Config file to use instead of the default one.
+    pub config_path: Option<Box<str>>,
+    /// Profile from the config file whose settings apply to this run.
+    pub profile: Option<Box<str>>,
+    /// Log format that overrides `log.format` from the config file.
+    pub log_format: Option<config::LogFormat>,
+    /// Log file that overrides `log.file`; also written in dry runs.
+    pub log_file: Option<Box<str>>,
+    /// Log to stdout only.
+    pub no_log_file: bool,
+    /// 1 for debug (`-v`), 2 for trace (`-vv`), -1 for warnings and errors only on stdout (`-q`).
+    pub verbosity: i8,
+    /// Don't print the Immich CLI's output to the terminal; it is still logged.
+    pub no_cli_output: bool,
+    /// Run with the full-screen dashboard.
+    pub tui: bool,
+    /// Where to send the JSON-lines event stream.
+    pub events: Option<events::Target>,
+    /// Runs to list (history only).
+    pub limit: Option<usize>,
+    /// Immich CLI settings that override the config file for this run.
+    pub cli: config::CliOverrides,
+}
+
+impl Arguments {
+    /// Whether the event stream has stdout to itself.
+    fn events_on_stdout(&self) -> bool {
+        self.events == Some(events::Target::Stdout)
+    }
+
+    /// Whether the run prompts before removing assets from albums.
+    fn needs_confirmation(&self) -> bool {
+        self.prune && !self.assume_yes && !self.dry_run
+    }
 }
 
 /// Parse command line arguments.
@@ -16,49 +135,491 @@ pub struct Arguments {
 /// Expected format:
 /// - `immich-refresh <path>`
 /// - `immich-refresh <path> --dry-run`
+/// - `immich-refresh <path> --grace-period <seconds>`
+/// - `immich-refresh <path> --timeout <minutes> --stall-timeout <minutes>`
+/// - `immich-refresh <path> --profile <name> --concurrency <n> --upload-arg <arg>`
+/// - `immich-refresh relink <path> [--dry-run]`
+/// - `immich-refresh reconcile <path> [--dry-run] [--prune] [--yes]`
+/// - `immich-refresh restore-albums <dump.sql> [--map-prefix FROM=TO]... [--dry-run]`
+/// - `immich-refresh history [--limit <n>]`
 pub fn parse_arguments() -> Result<Arguments> {
     parse_arguments_from_args(env::args().collect())
 }
 
 fn parse_arguments_from_args(args: Vec<String>) -> Result<Arguments> {
-    if args.len() < 2 {
-        bail!("Usage: immich-refresh <path> [--dry-run]");
-    }
+    let mut args = args.into_iter().skip(1).peekable();
 
-    let starting_path = args[1].clone().into_boxed_str();
+    let mode = match args.peek().map(String::as_str) {
+        Some("relink") => {
+            args.next();
+            Mode::Relink
+        }
+        Some("reconcile") => {
+            args.next();
+            Mode::Reconcile
+        }
+        Some("restore-albums") => {
+            args.next();
+            Mode::RestoreAlbums
+        }
+        Some("history") => {
+            args.next();
+            Mode::History
+        }
+        _ => Mode::Upload,
+    };
 
-    let mut dry_run = false;
+    let starting_path = match mode {
+        Mode::History => Box::default(),
+        _ => match args.next() {
+            Some(path) if !path.starts_with('-') => path.into_boxed_str(),
+            _ => bail!(USAGE),
+        },
+    };
 
-    if args.len() > 2 {
-        if args[2] == "--dry-run" {
-            dry_run = true;
-        } else {
-            bail!(
-                "Invalid argument: {}. Expected --dry-run or no additional arguments",
-                args[2]
-            );
+    let mut arguments = Arguments {
+        mode,
+        path: starting_path,
+        ..Default::default()
+    };
+
+    while let Some(arg) = args.next() {
+        match (arg.as_str(), mode) {
+            ("--dry-run", _) if !arguments.dry_run => arguments.dry_run = true,
+            ("--prune", Mode::Reconcile) if !arguments.prune => arguments.prune = true,
+            ("--yes", Mode::Reconcile) if !arguments.assume_yes => arguments.assume_yes = true,
+            ("--map-prefix", Mode::RestoreAlbums) => {
+                let value = flag_value(&mut args, &arg)?;
+                arguments
+                    .path_mappings
+                    .push(restore::PathMapping::parse(&value)?);
+            }
+            ("--grace-period", _) if mode.runs_cli() && arguments.grace_period.is_none() => {
+                let value = flag_value(&mut args, &arg)?;
+                let seconds = value
+                    .parse()
+                    .with_context(|| format!("Invalid value for --grace-period: {}", value))?;
+                arguments.grace_period = Some(Duration::from_secs(seconds));
+            }
+            ("--timeout", _) if mode.runs_cli() && arguments.timeout.is_none() => {
+                arguments.timeout = Some(minutes_value(&mut args, &arg)?);
+            }
+            ("--stall-timeout", _) if mode.runs_cli() && arguments.stall_timeout.is_none() => {
+                arguments.stall_timeout = Some(minutes_value(&mut args, &arg)?);
+            }
+            ("--config", _) if arguments.config_path.is_none() => {
+                arguments.config_path = Some(flag_value(&mut args, &arg)?.into());
+            }
+            ("--profile", _) if arguments.profile.is_none() => {
+                arguments.profile = Some(flag_value(&mut args, &arg)?.into());
+            }
+            ("--log-format", _) if arguments.log_format.is_none() => {
+                let value = flag_value(&mut args, &arg)?;
+                arguments.log_format = Some(config::LogFormat::parse(&value)?);
+            }
+            ("--limit", Mode::History) if arguments.limit.is_none() => {
+                let value = flag_value(&mut args, &arg)?;
+                let limit = value
+                    .parse()
+                    .with_context(|| format!("Invalid value for --limit: {}", value))?;
+                arguments.limit = Some(limit);
+            }
+            ("--log-file", _) if arguments.log_file.is_none() && !arguments.no_log_file => {
+                arguments.log_file = Some(flag_value(&mut args, &arg)?.into());
+            }
+            ("--no-log-file", _) if arguments.log_file.is_none() && !arguments.no_log_file => {
+                arguments.no_log_file = true;
+            }
+            ("-v", _) if arguments.verbosity >= 0 => {
+                arguments.verbosity = (arguments.verbosity + 1).min(2);
+            }
+            ("-vv", _) if arguments.verbosity >= 0 => arguments.verbosity = 2,
+            ("-q", _) if arguments.verbosity == 0 => arguments.verbosity = -1,
+            ("--no-cli-output", _) if mode.runs_cli() && !arguments.no_cli_output => {
+                arguments.no_cli_output = true;
+            }
+            ("--tui", _) if mode.runs_cli() && !arguments.tui => arguments.tui = true,
+            ("--events", _) if mode != Mode::History && arguments.events.is_none() => {
+                arguments.events = Some(events::Target::parse(&flag_value(&mut args, &arg)?)?);
+            }
+            ("--cli-path", _) if mode.runs_cli() && arguments.cli.path.is_none() => {
+                arguments.cli.path = Some(flag_value(&mut args, &arg)?.into());
+            }
+            ("--concurrency", _) if mode.runs_cli() && arguments.cli.concurrency.is_none() => {
+                let value = flag_value(&mut args, &arg)?;
+                let concurrency = value
+                    .parse()
+                    .with_context(|| format!("Invalid value for --concurrency: {}", value))?;
+                arguments.cli.concurrency = Some(concurrency);
+            }
+            ("--no-hidden", _) if mode.runs_cli() && arguments.cli.include_hidden.is_none() => {
+                arguments.cli.include_hidden = Some(false)
+            }
+            ("--no-recursive", _) if mode.runs_cli() && arguments.cli.recursive.is_none() => {
+                arguments.cli.recursive = Some(false)
+            }
+            ("--upload-arg", _) if mode.runs_cli() => {
+                let value = flag_value(&mut args, &arg)?;
+                arguments.cli.extra_args.push(value.into());
+            }
+            ("--allow-destructive", _)
+                if mode.runs_cli() && arguments.cli.allow_destructive.is_none() =>
+            {
+                arguments.cli.allow_destructive = Some(true);
+            }
+            ("--container-runtime", _)
+                if mode.runs_cli() && arguments.cli.container_runtime.is_none() =>
+            {
+                arguments.cli.container_runtime = Some(flag_value(&mut args, &arg)?.into());
+            }
+            ("--container-image", _)
+                if mode.runs_cli() && arguments.cli.container_image.is_none() =>
+            {
+                arguments.cli.container_image = Some(flag_value(&mut args, &arg)?.into());
+            }
+            _ => bail!("Invalid argument: {}. {}", arg, USAGE),
         }
     }
+    if arguments.tui && arguments.events_on_stdout() {
+        bail!("--tui needs stdout for the dashboard, send the events to a file with --events jsonl:<file>");
+    }
+    if arguments.needs_confirmation() && arguments.events_on_stdout() {
+        bail!("--prune asks for confirmation, which doesn't fit events on stdout: pass --yes");
+    }
 
-    if args.len() > 3 {
-        bail!("Too many arguments. Usage: immich-refresh <path> [--dry-run]");
+    Ok(arguments)
+}
+
+/// The value following a flag that takes one.
+fn flag_value(args: &mut impl Iterator<Item = String>, flag: &str) -> Result<String> {
+    match args.next() {
+        Some(value) => Ok(value),
+        None => bail!("Missing value for {}. {}", flag, USAGE),
     }
+}
 
-    Ok(Arguments {
-        path: starting_path,
-        dry_run,
+/// The positive number of minutes following a flag.
+fn minutes_value(args: &mut impl Iterator<Item = String>, flag: &str) -> Result<Duration> {
+    let value = flag_value(args, flag)?;
+    match value.parse::<u64>() {
+        Ok(minutes) if minutes > 0 => Ok(Duration::from_secs(minutes * 60)),
+        _ => bail!("Invalid value for {}: {}. Expected minutes", flag, value),
+    }
+}
+
+/// The credentials from the config file, or else those of `immich login`. The flag tells whether
+/// they came from the config file.
+fn server_credentials(
+    arguments: &Arguments,
+    config: &config::Config,
+) -> Result<(immich_api::ServerCredentials, bool)> {
+    let settings = config.server_settings(arguments.profile.as_deref())?;
+    let (credentials, configured) = match credentials::load(&settings)? {
+        Some(credentials) => (credentials, true),
+        None => (immich_api::ServerCredentials::from_environment()?, false),
+    };
+    redact::register(&credentials.api_key);
+    Ok((credentials, configured))
+}
+
+/// The credentials of a user from the config file.
+fn user_credentials(account: &config::UserAccount) -> Result<immich_api::ServerCredentials> {
+    let credentials = credentials::load(&account.server)?
+        .ok_or_else(|| anyhow::anyhow!("User {} has no credentials", account.route.name))?;
+    redact::register(&credentials.api_key);
+    Ok(credentials)
+}
+
+/// Relink and reconcile work with the run's own API key only. Albums of directories routed to
+/// other users would end up in the wrong account, so refuse to start rather than misplace them.
+fn reject_user_routing(arguments: &Arguments, config: &config::Config) -> Result<()> {
+    if !config.users.is_empty() {
+        bail!(
+            "{} doesn't support [users] in the config file, it would put every album into the account of the run's own API key. Use a config file without [users] (--config <file>) and run it once per user",
+            arguments.mode.name()
+        );
+    }
+    Ok(())
+}
+
+/// Check the server and API key of an account and return the upload flags the CLI of
+/// `cli_version` takes against it.
+fn check_account(
+    arguments: &Arguments,
+    credentials: &immich_api::ServerCredentials,
+    cli_version: &str,
+) -> Result<compat::CliFlags> {
+    let api = immich_api::ImmichClient::new(credentials.clone());
+    preflight::check_server(
+        &api,
+        &credentials.url,
+        &preflight::required_permissions(arguments),
+    )?;
+    let server_version = preflight::server_version(&api)?;
+    compat::cli_flags(cli_version, server_version)
+}
+
+/// `credentials` are `None` in dry runs, which never start the CLI. `user` is the user from the
+/// config file the executer uploads for.
+fn executer(
+    arguments: &Arguments,
+    config: &config::Config,
+    credentials: Option<&(immich_api::ServerCredentials, bool)>,
+    user: Option<&str>,
+) -> Result<execute::Executer> {
+    let cli = config.cli_settings(arguments.profile.as_deref(), &arguments.cli)?;
+    let in_container = cli.container.is_some();
+
+    let mut executer = execute::Executer::new().with_cli_settings(cli);
+    match credentials {
+        // The CLI in a container can't read the credentials `immich login` stored on the host
+        Some((credentials, _)) if in_container => {
+            executer = executer.with_credentials(credentials.clone());
+        }
+        Some((credentials, true)) => {
+            let cli_config_dir =
+                credentials::provision_cli_config(arguments.profile.as_deref(), user, credentials)?;
+            executer = executer
+                .with_credentials(credentials.clone())
+                .with_cli_config_dir(cli_config_dir);
+        }
+        _ => {}
+    }
+    // JSON logs or events on stdout must not be interleaved with the CLI's raw output, -q keeps
+    // the terminal to warnings and errors, and the dashboard shows the output itself
+    if arguments.log_format.unwrap_or(config.log.format) == config::LogFormat::Json
+        || !config.log.echo_cli_output
+        || arguments.no_cli_output
+        || arguments.verbosity < 0
+        || arguments.tui
+        || arguments.events_on_stdout()
+    {
+        executer = executer.with_echo_output(false);
+    }
+    if let Some(grace_period) = arguments.grace_period {
+        executer = executer.with_grace_period(grace_period);
+    }
+    if let Some(timeout) = arguments.timeout {
+        executer = executer.with_timeout(timeout);
+    }
+    if let Some(stall_timeout) = arguments.stall_timeout {
+        executer = executer.with_stall_timeout(stall_timeout);
+    }
+    Ok(executer)
+}
+
+/// Which files progress counts, following the CLI settings, and how it is shown.
+fn progress_options(arguments: &Arguments, config: &config::Config) -> Result<progress::Options> {
+    let cli = config.cli_settings(arguments.profile.as_deref(), &arguments.cli)?;
+    Ok(progress::Options {
+        scan: progress::ScanOptions {
+            recursive: cli.recursive,
+            include_hidden: cli.include_hidden,
+        },
+        display: if arguments.events_on_stdout() {
+            progress::Display::Lines
+        } else {
+            progress::Display::detect(arguments.log_format.unwrap_or(config.log.format))
+        },
     })
 }
 
-fn main() -> Result<()> {
+/// Run the selected mode. Returns the report of the modes that upload through the CLI.
+fn run(
+    arguments: &Arguments,
+    config: &config::Config,
+    cancel: &cancel::CancellationToken,
+) -> Result<Option<report::RunReport>> {
+    // Fail before the first directory rather than in the middle of the run
+    preflight::check_path(arguments)?;
+
+    match arguments.mode {
+        Mode::Upload | Mode::RestoreAlbums => {
+            let dashboard = arguments.tui.then(dashboard::Dashboard::new);
+            let with_output_sinks = |mut executor: execute::Executer| {
+                if let Some(dashboard) = &dashboard {
+                    executor = executor.with_output_sink(dashboard.output_sink());
+                }
+                if arguments.events.is_some() {
+                    executor = executor.with_output_sink(events::output_sink());
+                }
+                executor
+            };
+            let progress = progress_options(arguments, config)?;
+
+            let credentials = if arguments.dry_run {
+                None
+            } else {
+                Some(server_credentials(arguments, config)?)
+            };
+            let mut executor =
+                with_output_sinks(executer(arguments, config, credentials.as_ref(), None)?);
+            let cli_version = credentials
+                .as_ref()
+                .map(|_| preflight::check_cli(&executor))
+                .transpose()?;
+            if let (Some((credentials, _)), Some(cli_version)) = (&credentials, &cli_version) {
+                executor =
+                    executor.with_cli_flags(check_account(arguments, credentials, cli_version)?);
+            }
+
+            if arguments.mode == Mode::Upload {
+                let mut accounts = users::Accounts::single(executor);
+                for account in config.user_accounts(arguments.profile.as_deref())? {
+                    let credentials = if arguments.dry_run {
+                        None
+                    } else {
+                        Some((user_credentials(&account)?, true))
+                    };
+                    let mut user_executor = with_output_sinks(executer(
+                        arguments,
+                        config,
+                        credentials.as_ref(),
+                        Some(&account.route.name),
+                    )?);
+                    if let (Some((credentials, _)), Some(cli_version)) =
+                        (&credentials, &cli_version)
+                    {
+                        user_executor = user_executor.with_cli_flags(
+                            check_account(arguments, credentials, cli_version)
+                                .with_context(|| format!("User {}", account.route.name))?,
+                        );
+                    }
+                    accounts.users.push((account.route, user_executor));
+                }
+                let report = match dashboard {
+                    Some(dashboard) => dashboard.run(
+                        &traverse::album_jobs(arguments, &accounts, progress.scan)?,
+                        cancel,
+                    )?,
+                    None => traverse::traverse(arguments, &accounts, &progress, cancel)?,
+                };
+                Ok(Some(report))
+            } else {
+                let report = match dashboard {
+                    Some(dashboard) => {
+                        dashboard.run(&restore::restore_jobs(arguments, &executor)?, cancel)?
+                    }
+                    None => restore::restore_albums(arguments, &executor, &progress, cancel)?,
+                };
+                Ok(Some(report))
+            }
+        }
+        Mode::Relink | Mode::Reconcile => {
+            reject_user_routing(arguments, config)?;
+            let (credentials, _) = server_credentials(arguments, config)?;
+            let server_url = credentials.url.clone();
+            let api = immich_api::ImmichClient::new(credentials);
+            preflight::check_server(
+                &api,
+                &server_url,
+                &preflight::required_permissions(arguments),
+            )?;
+            if let Some(server_version) = preflight::server_version(&api)? {
+                compat::check_server_version(server_version)?;
+            }
+            if arguments.mode == Mode::Relink {
+                relink::relink(arguments, &api, cancel)?;
+            } else {
+                reconcile::reconcile(arguments, &api, &mut std::io::stdin().lock(), cancel)?;
+            }
+            Ok(None)
+        }
+        Mode::History => unreachable!("history is listed before a run starts"),
+    }
+}
+
+fn main() -> Result<ExitCode> {
     let arguments = parse_arguments().context("Failed to parse arguments")?;
+    if arguments.mode == Mode::History {
+        history::print_history(arguments.limit.unwrap_or(history::DEFAULT_HISTORY_LIMIT))?;
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    if arguments.tui && !std::io::stdout().is_terminal() {
+        bail!("--tui needs a terminal");
+    }
+    if arguments.needs_confirmation() && !std::io::stdin().is_terminal() {
+        bail!("--prune asks for confirmation, but stdin is not a terminal: pass --yes");
+    }
 
-    tracing_config::configure(arguments.dry_run)?;
+    // The config file also configures logging, so errors in it only reach stderr
+    let config = config::Config::load(arguments.config_path.as_deref())?;
+
+    let mut log_settings = config.log.clone();
+    if let Some(format) = arguments.log_format {
+        log_settings.format = format;
+    }
+    let destination = tracing_config::destination(
+        arguments.dry_run,
+        arguments.log_file.as_deref(),
+        arguments.no_log_file,
+        &log_settings,
+    );
+    let run_id = tracing_config::run_id();
+    let run_log = tracing_config::configure(
+        arguments.dry_run,
+        destination,
+        &log_settings,
+        arguments.verbosity,
+        !arguments.tui && !arguments.events_on_stdout(),
+        &run_id,
+    )?;
+    // Every log line of this run carries its ID
+    let _run = info_span!("run", run_id = %run_id).entered();
+    if let Some(target) = &arguments.events {
+        events::open(target, &run_id)?;
+    }
+    events::emit(events::Event::RunStarted {
+        mode: arguments.mode.name(),
+        path: &arguments.path,
+        dry_run: arguments.dry_run,
+    });
+    // Runs are recorded for `history` whenever they get a log file of their own
+    let run_history = run_log.and_then(|run_log| {
+        let runs_dir = run_log.parent()?;
+        let started = history::RunHistory::start(
+            runs_dir,
+            &run_id,
+            arguments.mode.name(),
+            &arguments.path,
+            arguments.dry_run,
+        )
+        .and_then(|run_history| {
+            history::prune(runs_dir, log_settings.max_runs)?;
+            Ok(run_history)
+        });
+        started.map_err(|e| error!("{:#}", e)).ok()
+    });
 
-    let executor = execute::Executer::new();
-    traverse::traverse(&arguments, &executor)?;
+    // Signals cancel the run cooperatively so the summary is still written
+    let cancel = cancel::CancellationToken::new();
+    // Lines on stderr would tear the dashboard
+    cancel.install_signal_handlers(!arguments.tui)?;
+    // Workers the Immich CLI leaves behind become our children, so every upload can wait for
+    // them before the next one starts
+    execute::become_child_subreaper();
 
-    Ok(())
+    let result = run(&arguments, &config, &cancel);
+    events::emit(events::Event::RunFinished {
+        status: history::RunStatus::of(&result, cancel.is_cancelled()),
+        error: result.as_ref().err().map(|e| format!("{:#}", e)),
+        summary: result
+            .as_ref()
+            .ok()
+            .and_then(Option::as_ref)
+            .map(events::Summary::from),
+    });
+    if let Some(run_history) = run_history {
+        run_history.finish(&result, cancel.is_cancelled());
+    }
+    match (result, cancel.exit_code()) {
+        // Exit with the conventional code of the signal that stopped the run
+        (Ok(_), Some(exit_code)) => Ok(ExitCode::from(exit_code)),
+        (Err(e), Some(exit_code)) if history::is_cancellation(&e) => Ok(ExitCode::from(exit_code)),
+        (Ok(_), None) => Ok(ExitCode::SUCCESS),
+        (Err(e), _) => Err(e),
+    }
 }
 
 #[cfg(test)]
@@ -80,6 +641,7 @@ mod tests {
 
         assert!(parsed.is_ok());
         let arguments = parsed.unwrap();
+        assert_eq!(arguments.mode, Mode::Upload);
         assert_eq!(arguments.path.as_ref(), expected_path);
         assert_eq!(arguments.dry_run, expected_dry_run);
     }
@@ -88,8 +650,292 @@ mod tests {
     #[case(vec!["immich-refresh"])]
     #[case(vec!["immich-refresh", "/base", "--invalid"])]
     #[case(vec!["immich-refresh", "/base", "--dry-run", "extra"])]
+    #[case(vec!["immich-refresh", "/base", "--dry-run", "--dry-run"])]
+    #[case(vec!["immich-refresh", "--dry-run"])]
+    #[case(vec!["immich-refresh", "relink"])]
+    #[case(vec!["immich-refresh", "/base", "--prune"])]
+    #[case(vec!["immich-refresh", "relink", "/base", "--prune"])]
+    #[case(vec!["immich-refresh", "restore-albums", "dump.sql", "--map-prefix"])]
+    #[case(vec!["immich-refresh", "restore-albums", "dump.sql", "--map-prefix", "/data"])]
+    #[case(vec!["immich-refresh", "/base", "--map-prefix", "/data=/photos"])]
+    #[case(vec!["immich-refresh", "/base", "--grace-period"])]
+    #[case(vec!["immich-refresh", "/base", "--grace-period", "soon"])]
+    #[case(vec!["immich-refresh", "relink", "/base", "--grace-period", "5"])]
+    #[case(vec!["immich-refresh", "/base", "--timeout", "0"])]
+    #[case(vec!["immich-refresh", "/base", "--stall-timeout", "1.5"])]
+    #[case(vec!["immich-refresh", "reconcile", "/base", "--timeout", "5"])]
+    #[case(vec!["immich-refresh", "relink", "/base", "--concurrency", "4"])]
+    #[case(vec!["immich-refresh", "/base", "--concurrency", "many"])]
+    #[case(vec!["immich-refresh", "/base", "--upload-arg"])]
+    #[case(vec!["immich-refresh", "/base", "--profile", "a", "--profile", "b"])]
+    #[case(vec!["immich-refresh", "/base", "--log-format", "xml"])]
+    #[case(vec!["immich-refresh", "/base", "--log-file"])]
+    #[case(vec!["immich-refresh", "/base", "--limit", "5"])]
+    #[case(vec!["immich-refresh", "history", "--limit", "few"])]
+    #[case(vec!["immich-refresh", "/base", "--log-file", "a.log", "--no-log-file"])]
+    #[case(vec!["immich-refresh", "/base", "--no-log-file", "--log-file", "a.log"])]
+    #[case(vec!["immich-refresh", "/base", "-v", "-q"])]
+    #[case(vec!["immich-refresh", "/base", "-q", "-vv"])]
+    #[case(vec!["immich-refresh", "/base", "-q", "-q"])]
+    #[case(vec!["immich-refresh", "relink", "/base", "--no-cli-output"])]
+    #[case(vec!["immich-refresh", "/base", "--grace-period", "5", "--grace-period", "10"])]
+    #[case(vec!["immich-refresh", "/base", "--cli-path", "/a/immich", "--cli-path", "/b/immich"])]
+    #[case(vec!["immich-refresh", "/base", "--concurrency", "2", "--concurrency", "4"])]
+    #[case(vec!["immich-refresh", "/base", "--timeout", "5", "--timeout", "10"])]
+    #[case(vec!["immich-refresh", "/base", "--container-image", "a", "--container-image", "b"])]
+    #[case(vec!["immich-refresh", "/base", "--no-hidden", "--no-hidden"])]
+    #[case(vec!["immich-refresh", "/base", "--events", "json"])]
+    #[case(vec!["immich-refresh", "/base", "--events"])]
+    #[case(vec!["immich-refresh", "/base", "--tui", "--events", "jsonl"])]
+    #[case(vec!["immich-refresh", "history", "--events", "jsonl"])]
+    #[case(vec!["immich-refresh", "reconcile", "/base", "--prune", "--events", "jsonl"])]
     fn test_parse_arguments_failure(#[case] args: Vec<&str>) {
         let parsed = parse_arguments_from_args(args.iter().map(|s| s.to_string()).collect());
         assert!(parsed.is_err());
     }
+
+    #[rstest]
+    #[case(vec!["immich-refresh", "relink", "/base"], false)]
+    #[case(vec!["immich-refresh", "relink", "/base", "--dry-run"], true)]
+    fn test_parse_arguments_relink(#[case] args: Vec<&str>, #[case] expected_dry_run: bool) {
+        let arguments =
+            parse_arguments_from_args(args.iter().map(|s| s.to_string()).collect()).unwrap();
+
+        assert_eq!(
+            arguments,
+            Arguments {
+                mode: Mode::Relink,
+                path: "/base".into(),
+                dry_run: expected_dry_run,
+                ..Default::default()
+            }
+        );
+    }
+
+    #[rstest]
+    #[case(vec!["immich-refresh", "reconcile", "/base"], false, false, false)]
+    #[case(vec!["immich-refresh", "reconcile", "/base", "--dry-run"], true, false, false)]
+    #[case(vec!["immich-refresh", "reconcile", "/base", "--prune", "--yes"], false, true, true)]
+    fn test_parse_arguments_reconcile(
+        #[case] args: Vec<&str>,
+        #[case] expected_dry_run: bool,
+        #[case] expected_prune: bool,
+        #[case] expected_assume_yes: bool,
+    ) {
+        let arguments =
+            parse_arguments_from_args(args.iter().map(|s| s.to_string()).collect()).unwrap();
+
+        assert_eq!(
+            arguments,
+            Arguments {
+                mode: Mode::Reconcile,
+                path: "/base".into(),
+                dry_run: expected_dry_run,
+                prune: expected_prune,
+                assume_yes: expected_assume_yes,
+                ..Default::default()
+            }
+        );
+    }
+
+    #[test]
+    fn test_parse_arguments_restore_albums() {
+        let args = [
+            "immich-refresh",
+            "restore-albums",
+            "dump.sql",
+            "--map-prefix",
+            "/data=/photos",
+            "--dry-run",
+            "--map-prefix",
+            "/ext=/mnt/ext",
+        ];
+        let arguments =
+            parse_arguments_from_args(args.iter().map(|s| s.to_string()).collect()).unwrap();
+
+        assert_eq!(
+            arguments,
+            Arguments {
+                mode: Mode::RestoreAlbums,
+                path: "dump.sql".into(),
+                dry_run: true,
+                path_mappings: vec![
+                    restore::PathMapping::parse("/data=/photos").unwrap(),
+                    restore::PathMapping::parse("/ext=/mnt/ext").unwrap(),
+                ],
+                ..Default::default()
+            }
+        );
+    }
+
+    #[rstest]
+    #[case(vec!["immich-refresh", "/base", "--grace-period", "30"])]
+    #[case(vec!["immich-refresh", "restore-albums", "/base", "--grace-period", "30"])]
+    fn test_parse_arguments_grace_period(#[case] args: Vec<&str>) {
+        let arguments =
+            parse_arguments_from_args(args.iter().map(|s| s.to_string()).collect()).unwrap();
+
+        assert_eq!(arguments.grace_period, Some(Duration::from_secs(30)));
+    }
+
+    #[test]
+    fn test_parse_arguments_timeouts() {
+        let args = [
+            "immich-refresh",
+            "/base",
+            "--timeout",
+            "60",
+            "--stall-timeout",
+            "5",
+        ];
+        let arguments =
+            parse_arguments_from_args(args.iter().map(|s| s.to_string()).collect()).unwrap();
+
+        assert_eq!(arguments.timeout, Some(Duration::from_secs(3600)));
+        assert_eq!(arguments.stall_timeout, Some(Duration::from_secs(300)));
+    }
+
+    #[test]
+    fn test_parse_arguments_cli_overrides() {
+        let args = [
+            "immich-refresh",
+            "/base",
+            "--profile",
+            "nas",
+            "--cli-path",
+            "/opt/immich",
+            "--concurrency",
+            "4",
+            "--no-hidden",
+            "--upload-arg",
+            "--skip-hash",
+            "--upload-arg",
+            "--delete",
+            "--allow-destructive",
+            "--container-runtime",
+            "podman",
+        ];
+        let arguments =
+            parse_arguments_from_args(args.iter().map(|s| s.to_string()).collect()).unwrap();
+
+        assert_eq!(arguments.profile.as_deref(), Some("nas"));
+        assert_eq!(
+            arguments.cli,
+            config::CliOverrides {
+                path: Some("/opt/immich".into()),
+                concurrency: Some(4),
+                include_hidden: Some(false),
+                recursive: None,
+                extra_args: vec!["--skip-hash".into(), "--delete".into()],
+                allow_destructive: Some(true),
+                container_runtime: Some("podman".into()),
+                ..Default::default()
+            }
+        );
+    }
+
+    #[rstest]
+    #[case(vec!["immich-refresh", "/base"], None)]
+    #[case(vec!["immich-refresh", "relink", "/base", "--log-format", "json"], Some(config::LogFormat::Json))]
+    #[case(vec!["immich-refresh", "/base", "--log-format", "text"], Some(config::LogFormat::Text))]
+    fn test_parse_arguments_log_format(
+        #[case] args: Vec<&str>,
+        #[case] expected: Option<config::LogFormat>,
+    ) {
+        let arguments =
+            parse_arguments_from_args(args.iter().map(|s| s.to_string()).collect()).unwrap();
+
+        assert_eq!(arguments.log_format, expected);
+    }
+
+    #[test]
+    fn test_parse_arguments_log_file() {
+        let args = [
+            "immich-refresh",
+            "/base",
+            "--dry-run",
+            "--log-file",
+            "/tmp/run.log",
+        ];
+        let arguments =
+            parse_arguments_from_args(args.iter().map(|s| s.to_string()).collect()).unwrap();
+
+        assert_eq!(arguments.log_file.as_deref(), Some("/tmp/run.log"));
+        assert!(!arguments.no_log_file);
+    }
+
+    #[rstest]
+    #[case(vec!["immich-refresh", "/base"], 0)]
+    #[case(vec!["immich-refresh", "/base", "-v"], 1)]
+    #[case(vec!["immich-refresh", "/base", "-v", "-v"], 2)]
+    #[case(vec!["immich-refresh", "relink", "/base", "-vv", "-v"], 2)]
+    #[case(vec!["immich-refresh", "/base", "-q"], -1)]
+    fn test_parse_arguments_verbosity(#[case] args: Vec<&str>, #[case] expected: i8) {
+        let arguments =
+            parse_arguments_from_args(args.iter().map(|s| s.to_string()).collect()).unwrap();
+
+        assert_eq!(arguments.verbosity, expected);
+    }
+
+    #[test]
+    fn test_parse_arguments_no_cli_output() {
+        let args = ["immich-refresh", "/base", "--no-cli-output"];
+        let arguments =
+            parse_arguments_from_args(args.iter().map(|s| s.to_string()).collect()).unwrap();
+
+        assert!(arguments.no_cli_output);
+    }
+
+    #[rstest]
+    #[case(vec!["immich-refresh", "/base", "--events", "jsonl"], events::Target::Stdout)]
+    #[case(
+        vec!["immich-refresh", "relink", "/base", "--events", "jsonl:events.jsonl"],
+        events::Target::File("events.jsonl".into())
+    )]
+    #[case(
+        vec!["immich-refresh", "/base", "--tui", "--events", "jsonl:events.jsonl"],
+        events::Target::File("events.jsonl".into())
+    )]
+    #[case(
+        vec!["immich-refresh", "reconcile", "/base", "--prune", "--yes", "--events", "jsonl"],
+        events::Target::Stdout
+    )]
+    fn test_parse_arguments_events(#[case] args: Vec<&str>, #[case] expected: events::Target) {
+        let arguments =
+            parse_arguments_from_args(args.iter().map(|s| s.to_string()).collect()).unwrap();
+
+        assert_eq!(arguments.events, Some(expected));
+    }
+
+    #[rstest]
+    #[case(vec!["immich-refresh", "history"], None)]
+    #[case(vec!["immich-refresh", "history", "--limit", "5"], Some(5))]
+    fn test_parse_arguments_history(
+        #[case] args: Vec<&str>,
+        #[case] expected_limit: Option<usize>,
+    ) {
+        let arguments =
+            parse_arguments_from_args(args.iter().map(|s| s.to_string()).collect()).unwrap();
+
+        assert_eq!(arguments.mode, Mode::History);
+        assert_eq!(arguments.limit, expected_limit);
+    }
+
+    #[rstest]
+    #[case(Mode::Relink)]
+    #[case(Mode::Reconcile)]
+    fn test_reject_user_routing(#[case] mode: Mode) {
+        let arguments = Arguments {
+            mode,
+            ..Default::default()
+        };
+        let users = config::Config::parse(
+            "[users.alice]\ndirectories = [\"alice\"]\n\n[users.alice.server]\napi_key_env = \"KEY\"\n",
+        )
+        .unwrap();
+
+        let error = reject_user_routing(&arguments, &users).unwrap_err();
+        assert!(error.to_string().contains("[users]"), "{error}");
+        assert!(reject_user_routing(&arguments, &config::Config::default()).is_ok());
+    }
 }
@@ -0,0 +1,282 @@
+use crate::prelude::*;
+use std::collections::HashMap;
+use std::io::BufRead;
+
+/// Table names used by different Immich versions.
+const ALBUM_TABLES: &[&str] = &["albums", "album"];
+const ALBUM_ASSET_TABLES: &[&str] = &["albums_assets_assets", "albums_assets", "album_asset"];
+const ASSET_TABLES: &[&str] = &["assets", "asset"];
+
+/// Column names used by different Immich versions.
+const ALBUM_NAME_COLUMNS: &[&str] = &["albumName", "album_name"];
+const ALBUM_ID_COLUMNS: &[&str] = &["albumsId", "albumId", "album_id"];
+const ASSET_ID_COLUMNS: &[&str] = &["assetsId", "assetId", "asset_id"];
+const ORIGINAL_PATH_COLUMNS: &[&str] = &["originalPath", "original_path"];
+const DELETED_AT_COLUMNS: &[&str] = &["deletedAt", "deleted_at"];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DumpAlbum {
+    pub id: String,
+    pub name: String,
+}
+
+/// Album memberships read from a plain-text `pg_dump` of the Immich database. Deleted albums and
+/// assets are left out.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct ImmichDump {
+    pub albums: Vec<DumpAlbum>,
+    /// `(album id, asset id)` pairs.
+    pub album_assets: Vec<(String, String)>,
+    /// Original path of each asset keyed by asset id.
+    pub asset_paths: HashMap<String, String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Table {
+    Albums,
+    AlbumAssets,
+    Assets,
+}
+
+/// Column positions of the `COPY` block currently being read.
+struct CopyBlock {
+    table: Table,
+    columns: Vec<String>,
+}
+
+impl CopyBlock {
+    fn column(&self, names: &[&str]) -> Result<usize> {
+        self.columns
+            .iter()
+            .position(|column| names.contains(&column.as_str()))
+            .ok_or_else(|| {
+                anyhow::anyhow!(
+                    "Column {} not found in {:?} (columns: {})",
+                    names.join(" or "),
+                    self.table,
+                    self.columns.join(", ")
+                )
+            })
+    }
+
+    fn optional_column(&self, names: &[&str]) -> Option<usize> {
+        self.column(names).ok()
+    }
+}
+
+/// Parse the albums, album membership and assets tables from a plain-text `pg_dump`.
+///
+/// Only `COPY ... FROM stdin;` data blocks are read; dumps made with `--inserts` or in the custom
+/// format are not supported.
+pub fn parse_dump(reader: impl BufRead) -> Result<ImmichDump> {
+    let mut dump = ImmichDump::default();
+    let mut deleted_albums = Vec::new();
+    let mut deleted_assets = Vec::new();
+    let mut block: Option<CopyBlock> = None;
+
+    for (index, line) in reader.lines().enumerate() {
+        let line = line.with_context(|| format!("Failed to read line {}", index + 1))?;
+
+        let Some(current) = &block else {
+            block = parse_copy_header(&line);
+            continue;
+        };
+
+        if line == "\\." {
+            block = None;
+            continue;
+        }
+
+        let fields: Vec<Option<String>> = line.split('\t').map(unescape_field).collect();
+        let field = |position: usize| -> Result<&str> {
+            fields
+                .get(position)
+                .and_then(|field| field.as_deref())
+                .ok_or_else(|| anyhow::anyhow!("Missing value on line {}", index + 1))
+        };
+        let is_deleted = current
+            .optional_column(DELETED_AT_COLUMNS)
+            .is_some_and(|position| fields.get(position).is_some_and(Option::is_some));
+
+        match current.table {
+            Table::Albums => {
+                let id = field(current.column(&["id"])?)?.to_string();
+                if is_deleted {
+                    deleted_albums.push(id);
+                    continue;
+                }
+                let name = field(current.column(ALBUM_NAME_COLUMNS)?)?.to_string();
+                dump.albums.push(DumpAlbum { id, name });
+            }
+            Table::AlbumAssets => {
+                let album_id = field(current.column(ALBUM_ID_COLUMNS)?)?.to_string();
+                let asset_id = field(current.column(ASSET_ID_COLUMNS)?)?.to_string();
+                dump.album_assets.push((album_id, asset_id));
+            }
+            Table::Assets => {
+                let id = field(current.column(&["id"])?)?.to_string();
+                if is_deleted {
+                    deleted_assets.push(id);
+                    continue;
+                }
+                let original_path = field(current.column(ORIGINAL_PATH_COLUMNS)?)?.to_string();
+                dump.asset_paths.insert(id, original_path);
+            }
+        }
+    }
+
+    if block.is_some() {
+        bail!("Unexpected end of dump inside a COPY block");
+    }
+
+    if dump.albums.is_empty() && deleted_albums.is_empty() {
+        bail!("No albums table found in dump");
+    }
+
+    debug!(
+        "Parsed dump: {} albums ({} deleted), {} memberships, {} assets ({} deleted)",
+        dump.albums.len(),
+        deleted_albums.len(),
+        dump.album_assets.len(),
+        dump.asset_paths.len(),
+        deleted_assets.len()
+    );
+
+    Ok(dump)
+}
+
+/// Recognise `COPY public.albums (id, "albumName", ...) FROM stdin;` for the tables of interest.
+fn parse_copy_header(line: &str) -> Option<CopyBlock> {
+    let rest = line.strip_prefix("COPY ")?.strip_suffix(" FROM stdin;")?;
+    let (table_name, columns) = rest.split_once(" (")?;
+    let columns = columns.strip_suffix(')')?;
+
+    let table_name = table_name.rsplit('.').next()?.trim_matches('"');
+    let table = if ALBUM_TABLES.contains(&table_name) {
+        Table::Albums
+    } else if ALBUM_ASSET_TABLES.contains(&table_name) {
+        Table::AlbumAssets
+    } else if ASSET_TABLES.contains(&table_name) {
+        Table::Assets
+    } else {
+        return None;
+    };
+
+    let columns = columns
+        .split(", ")
+        .map(|column| column.trim_matches('"').to_string())
+        .collect();
+
+    Some(CopyBlock { table, columns })
+}
+
+/// Decode a field of the `COPY` text format; `\N` is NULL.
+fn unescape_field(field: &str) -> Option<String> {
+    if field == "\\N" {
+        return None;
+    }
+
+    let mut value = String::with_capacity(field.len());
+    let mut chars = field.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            value.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('t') => value.push('\t'),
+            Some('n') => value.push('\n'),
+            Some('r') => value.push('\r'),
+            Some('b') => value.push('\u{8}'),
+            Some('f') => value.push('\u{c}'),
+            Some('v') => value.push('\u{b}'),
+            Some(other) => value.push(other),
+            None => value.push('\\'),
+        }
+    }
+
+    Some(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use rstest::rstest;
+
+    const IMMICH_V1_DUMP: &str = include_str!(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/fixtures/pg_dump/immich_v1.sql"
+    ));
+    const IMMICH_V2_DUMP: &str = include_str!(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/fixtures/pg_dump/immich_v2.sql"
+    ));
+
+    #[test]
+    fn test_parse_dump_plural_table_names() {
+        let dump = parse_dump(IMMICH_V1_DUMP.as_bytes()).unwrap();
+
+        assert_eq!(
+            dump.albums,
+            vec![
+                DumpAlbum {
+                    id: "a1".into(),
+                    name: "Summer".into(),
+                },
+                DumpAlbum {
+                    id: "a2".into(),
+                    name: "Tabs\tand \\ backslashes".into(),
+                },
+            ]
+        );
+        assert_eq!(dump.album_assets.len(), 5);
+        assert_eq!(
+            dump.asset_paths.get("x3").map(String::as_str),
+            Some("/mnt/external/scans/scan.png")
+        );
+        assert_eq!(dump.asset_paths.get("x4"), None);
+    }
+
+    #[test]
+    fn test_parse_dump_singular_table_names() {
+        let dump = parse_dump(IMMICH_V2_DUMP.as_bytes()).unwrap();
+
+        assert_eq!(
+            dump.albums,
+            vec![DumpAlbum {
+                id: "b1".into(),
+                name: "Winter".into(),
+            }]
+        );
+        assert_eq!(
+            dump.album_assets,
+            vec![("b1".into(), "y1".into()), ("b1".into(), "y2".into())]
+        );
+        assert_eq!(
+            dump.asset_paths.get("y2").map(String::as_str),
+            Some("/data/library/admin/2025/winter/line\nbreak.jpg")
+        );
+    }
+
+    #[test]
+    fn test_parse_dump_without_albums() {
+        assert!(parse_dump("SET statement_timeout = 0;\n".as_bytes()).is_err());
+    }
+
+    #[test]
+    fn test_parse_dump_truncated() {
+        let truncated = "COPY public.albums (id, \"albumName\") FROM stdin;\na1\tSummer\n";
+        assert!(parse_dump(truncated.as_bytes()).is_err());
+    }
+
+    #[rstest]
+    #[case("\\N", None)]
+    #[case("plain", Some("plain"))]
+    #[case("a\\tb", Some("a\tb"))]
+    #[case("back\\\\slash", Some("back\\slash"))]
+    #[case("", Some(""))]
+    fn test_unescape_field(#[case] field: &str, #[case] expected: Option<&str>) {
+        assert_eq!(unescape_field(field).as_deref(), expected);
+    }
+}
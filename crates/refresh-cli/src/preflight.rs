@@ -0,0 +1,227 @@
+use crate::compat::Version;
+use crate::execute::{ExecuteError, Executer};
+use crate::immich_api::{self, ImmichApi};
+use crate::prelude::*;
+use crate::{Arguments, Mode};
+
+/// Grants every permission.
+const ALL_PERMISSIONS: &str = "all";
+/// What uploading into albums needs, through the CLI or the API.
+const ALBUM_UPLOAD_PERMISSIONS: &[&str] = &[
+    "asset.upload",
+    "album.read",
+    "album.create",
+    "albumAsset.create",
+];
+/// What a dry run of `relink` or `reconcile` needs to look up assets and albums.
+const DRY_RUN_PERMISSIONS: &[&str] = &["asset.upload", "album.read"];
+const PRUNE_PERMISSION: &str = "albumAsset.delete";
+/// What `reconcile` needs to tell the assets of the key's user from those of other users.
+const RECONCILE_PERMISSION: &str = "user.read";
+
+/// The input of the run is readable: a directory tree, or the dump file for `restore-albums`.
+pub fn check_path(arguments: &Arguments) -> Result<()> {
+    let path = arguments.path.as_ref();
+    if arguments.mode == Mode::RestoreAlbums {
+        fs::File::open(path).with_context(|| format!("Cannot read database dump {}", path))?;
+    } else {
+        fs::read_dir(path).with_context(|| format!("Cannot read directory {}", path))?;
+    }
+    Ok(())
+}
+
+/// The Immich CLI (or the container runtime running it) can be started. Returns its version.
+pub fn check_cli(executor: &Executer) -> Result<String> {
+    let version = executor.cli_version().map_err(|e| match e {
+        ExecuteError::ImmichCliNotFound(_) => anyhow::anyhow!(
+            "{}. Install it with 'npm install -g @immich/cli', or point --cli-path (cli.path in the config file) at it",
+            e
+        ),
+        e => anyhow::anyhow!("Immich CLI is not usable: {}", e),
+    })?;
+    info!("Immich CLI version: {}", version);
+    Ok(version)
+}
+
+/// The server's version, when it reports one.
+pub fn server_version(api: &impl ImmichApi) -> Result<Option<Version>> {
+    let version = api
+        .server_version()
+        .context("Failed to get the Immich server version")?;
+    match version {
+        Some(version) => info!("Immich server version: {}", version),
+        None => debug!("Server does not report its version"),
+    }
+    Ok(version)
+}
+
+/// Permissions the API key needs for the run.
+pub fn required_permissions(arguments: &Arguments) -> Vec<&'static str> {
+    let mut permissions = if arguments.dry_run {
+        DRY_RUN_PERMISSIONS.to_vec()
+    } else {
+        ALBUM_UPLOAD_PERMISSIONS.to_vec()
+    };
+    if arguments.mode == Mode::Reconcile {
+        permissions.push(RECONCILE_PERMISSION);
+        if arguments.prune && !arguments.dry_run {
+            permissions.push(PRUNE_PERMISSION);
+        }
+    }
+    permissions
+}
+
+/// The server at `server_url` answers and accepts the API key, and the key has `required`
+/// permissions. Servers too old to report a key's permissions only get the first check.
+pub fn check_server(api: &impl ImmichApi, server_url: &str, required: &[&str]) -> Result<()> {
+    api.ping().map_err(|e| {
+        anyhow::anyhow!(
+            "Immich server at {} is not reachable: {:#}. Check server.url in the config file, IMMICH_INSTANCE_URL or the URL given to 'immich login'",
+            server_url,
+            e
+        )
+    })?;
+
+    let permissions = match api.api_key_permissions() {
+        Ok(Some(permissions)) => permissions,
+        Ok(None) => {
+            debug!("Server does not report API key permissions, skipping the check");
+            return Ok(());
+        }
+        Err(e) if immich_api::status_code(&e) == Some(401) => bail!(
+            "Immich server at {} rejected the API key. Check the key configured for the server, IMMICH_API_KEY or the key given to 'immich login'",
+            server_url
+        ),
+        Err(e) if immich_api::status_code(&e) == Some(403) => {
+            info!("API key is not allowed to read its own permissions, skipping the check");
+            return Ok(());
+        }
+        Err(e) => return Err(e.context("Failed to check the API key")),
+    };
+
+    if permissions.iter().any(|p| p == ALL_PERMISSIONS) {
+        return Ok(());
+    }
+    let missing: Vec<&str> = required
+        .iter()
+        .copied()
+        .filter(|required| !permissions.iter().any(|p| p == required))
+        .collect();
+    if !missing.is_empty() {
+        bail!(
+            "API key is missing permissions: {}. Grant them (or 'all') to the key in Immich under Account Settings > API Keys",
+            missing.join(", ")
+        );
+    }
+
+    debug!("Preflight checks against {} passed", server_url);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::immich_api::MockImmichApi;
+    use pretty_assertions::assert_eq;
+    use rstest::rstest;
+    use tempfile::TempDir;
+
+    const URL: &str = "http://immich:2283/api";
+
+    fn status_error(status: u16) -> anyhow::Error {
+        anyhow::Error::new(ureq::Error::StatusCode(status)).context("Request failed")
+    }
+
+    fn api(permissions: Result<Option<Vec<&'static str>>, u16>) -> MockImmichApi {
+        let mut api = MockImmichApi::new();
+        api.expect_ping().returning(|| Ok(()));
+        api.expect_api_key_permissions()
+            .returning(move || match &permissions {
+                Ok(permissions) => Ok(permissions
+                    .as_ref()
+                    .map(|p| p.iter().map(|p| p.to_string()).collect())),
+                Err(status) => Err(status_error(*status)),
+            });
+        api
+    }
+
+    #[rstest]
+    #[case(Ok(Some(vec!["all"])), true)]
+    #[case(Ok(Some(vec!["asset.upload", "album.read", "album.create", "albumAsset.create"])), true)]
+    #[case(Ok(Some(vec!["asset.upload", "album.read"])), false)]
+    #[case(Ok(None), true)]
+    #[case(Err(401), false)]
+    #[case(Err(403), true)]
+    #[case(Err(500), false)]
+    fn test_check_server(
+        #[case] permissions: Result<Option<Vec<&'static str>>, u16>,
+        #[case] passes: bool,
+    ) {
+        let result = check_server(&api(permissions), URL, ALBUM_UPLOAD_PERMISSIONS);
+        assert_eq!(result.is_ok(), passes, "{result:?}");
+    }
+
+    #[test]
+    fn test_check_server_names_missing_permissions() {
+        let api = api(Ok(Some(vec!["asset.upload", "album.read"])));
+
+        let error = check_server(&api, URL, ALBUM_UPLOAD_PERMISSIONS).unwrap_err();
+
+        assert!(
+            error
+                .to_string()
+                .starts_with("API key is missing permissions: album.create, albumAsset.create."),
+            "{error}"
+        );
+    }
+
+    #[test]
+    fn test_check_server_unreachable() {
+        let mut api = MockImmichApi::new();
+        api.expect_ping()
+            .returning(|| Err(anyhow::anyhow!("Connection refused")));
+        api.expect_api_key_permissions().never();
+
+        let error = check_server(&api, URL, ALBUM_UPLOAD_PERMISSIONS).unwrap_err();
+
+        assert!(error.to_string().contains("not reachable"), "{error}");
+    }
+
+    #[rstest]
+    #[case(Mode::Upload, false, false, 4)]
+    #[case(Mode::Upload, false, true, 2)]
+    #[case(Mode::Reconcile, false, false, 5)]
+    #[case(Mode::Reconcile, true, false, 6)]
+    #[case(Mode::Reconcile, true, true, 3)]
+    fn test_required_permissions(
+        #[case] mode: Mode,
+        #[case] prune: bool,
+        #[case] dry_run: bool,
+        #[case] count: usize,
+    ) {
+        let arguments = Arguments {
+            mode,
+            prune,
+            dry_run,
+            ..Default::default()
+        };
+        assert_eq!(required_permissions(&arguments).len(), count);
+    }
+
+    #[test]
+    fn test_check_path() {
+        let temp_dir = TempDir::new().unwrap();
+        let dump = temp_dir.path().join("dump.sql");
+        fs::write(&dump, "").unwrap();
+        let arguments = |mode, path: &std::path::Path| Arguments {
+            mode,
+            path: path.to_string_lossy().into(),
+            ..Default::default()
+        };
+
+        assert!(check_path(&arguments(Mode::Upload, temp_dir.path())).is_ok());
+        assert!(check_path(&arguments(Mode::Upload, &temp_dir.path().join("missing"))).is_err());
+        assert!(check_path(&arguments(Mode::Upload, &dump)).is_err());
+        assert!(check_path(&arguments(Mode::RestoreAlbums, &dump)).is_ok());
+    }
+}
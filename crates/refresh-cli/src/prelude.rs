@@ -2,4 +2,4 @@ pub use anyhow::{bail, Context, Result};
 pub use std::env;
 pub use std::fs;
 pub use std::path::Path;
-pub use tracing::{debug, error, info};
+pub use tracing::{debug, error, info, info_span, warn};
@@ -0,0 +1,509 @@
+use crate::config::LogFormat;
+use crate::prelude::*;
+use std::io::{self, IsTerminal, Write};
+use std::ops::AddAssign;
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+use tracing::Span;
+use tracing_subscriber::fmt::MakeWriter;
+
+/// How often the status line is redrawn so elapsed time, throughput and ETA keep moving.
+const REDRAW_INTERVAL: Duration = Duration::from_secs(1);
+/// How often progress is logged when stdout is not a terminal.
+const LINE_INTERVAL: Duration = Duration::from_secs(60);
+/// Carriage return and "erase line".
+const CLEAR_LINE: &str = "\r\x1b[2K";
+
+/// The status line at the bottom of the terminal, if one is shown. Everything written to the
+/// terminal goes above it.
+static STATUS_LINE: Mutex<Option<String>> = Mutex::new(None);
+
+/// How progress is shown.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Display {
+    /// A status line at the bottom of the terminal, redrawn as the run goes.
+    Terminal,
+    /// Progress lines logged every minute.
+    #[default]
+    Lines,
+}
+
+impl Display {
+    /// The status line needs a terminal on stdout that isn't carrying JSON logs.
+    pub fn detect(format: LogFormat) -> Self {
+        if format == LogFormat::Text && io::stdout().is_terminal() {
+            Self::Terminal
+        } else {
+            Self::Lines
+        }
+    }
+}
+
+/// Which files of an album directory the CLI will look at.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ScanOptions {
+    pub recursive: bool,
+    pub include_hidden: bool,
+}
+
+impl Default for ScanOptions {
+    fn default() -> Self {
+        Self {
+            recursive: true,
+            include_hidden: true,
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Options {
+    pub scan: ScanOptions,
+    pub display: Display,
+}
+
+/// Number and size of files.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Size {
+    pub files: u64,
+    pub bytes: u64,
+}
+
+impl AddAssign for Size {
+    fn add_assign(&mut self, other: Self) {
+        self.files += other.files;
+        self.bytes += other.bytes;
+    }
+}
+
+/// The files below `dir` the CLI will find. Entries that can't be read are left out; the upload
+/// reports them.
+pub fn measure(dir: &Path, options: ScanOptions) -> Size {
+    let mut size = Size::default();
+    let Ok(entries) = fs::read_dir(dir) else {
+        return size;
+    };
+    for entry in entries.flatten() {
+        if !options.include_hidden && entry.file_name().to_string_lossy().starts_with('.') {
+            continue;
+        }
+        let Ok(metadata) = entry.metadata() else {
+            continue;
+        };
+        if metadata.is_dir() {
+            if options.recursive {
+                size += measure(&entry.path(), options);
+            }
+        } else if metadata.is_file() {
+            size += Size {
+                files: 1,
+                bytes: metadata.len(),
+            };
+        }
+    }
+    size
+}
+
+/// `12.3 MB`, `4.0 GB`.
+fn format_bytes(bytes: u64) -> String {
+    if bytes >= 1_000_000_000 {
+        format!("{:.1} GB", bytes as f64 / 1_000_000_000.0)
+    } else {
+        format!("{:.1} MB", bytes as f64 / 1_000_000.0)
+    }
+}
+
+/// `1h 05m`, `4m 10s`, `12s`.
+fn format_eta(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    match (secs / 3600, secs % 3600 / 60, secs % 60) {
+        (0, 0, s) => format!("{}s", s),
+        (0, m, s) => format!("{}m {:02}s", m, s),
+        (h, m, _) => format!("{}h {:02}m", h, m),
+    }
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+struct State {
+    albums: usize,
+    total: Size,
+    finished_albums: usize,
+    failed_albums: usize,
+    finished: Size,
+    /// The album being uploaded.
+    current: Option<Box<str>>,
+}
+
+impl State {
+    /// Time left at the pace so far: by bytes once some were processed, else by albums.
+    fn eta(&self, elapsed: Duration) -> Option<Duration> {
+        let remaining_albums = self.albums - self.finished_albums;
+        if remaining_albums == 0 {
+            return Some(Duration::ZERO);
+        }
+        if self.finished.bytes > 0 {
+            let remaining = self.total.bytes.saturating_sub(self.finished.bytes);
+            return Some(elapsed.mul_f64(remaining as f64 / self.finished.bytes as f64));
+        }
+        if self.finished_albums > 0 {
+            return Some(elapsed * remaining_albums as u32 / self.finished_albums as u32);
+        }
+        None
+    }
+
+    fn render(&self, elapsed: Duration) -> String {
+        let failed = match self.failed_albums {
+            0 => String::new(),
+            failed => format!(", {} failed", failed),
+        };
+        let throughput = match elapsed.as_secs_f64() {
+            secs if secs > 0.0 => format!(
+                "{}/s",
+                format_bytes((self.finished.bytes as f64 / secs) as u64)
+            ),
+            _ => "-".to_string(),
+        };
+        let eta = self.eta(elapsed).map_or("-".to_string(), format_eta);
+        let mut line = format!(
+            "Albums {}/{} ({} left{}), files {}/{}, {} of {}, {}, ETA {}",
+            self.finished_albums,
+            self.albums,
+            self.albums - self.finished_albums,
+            failed,
+            self.finished.files,
+            self.total.files,
+            format_bytes(self.finished.bytes),
+            format_bytes(self.total.bytes),
+            throughput,
+            eta
+        );
+        if let Some(current) = &self.current {
+            line.push_str(&format!(" | {}", current));
+        }
+        line
+    }
+}
+
+/// Columns of the terminal on stdout.
+fn terminal_width() -> Option<usize> {
+    // SAFETY: winsize is plain integers, and TIOCGWINSZ only writes the one it is given.
+    let mut size: libc::winsize = unsafe { std::mem::zeroed() };
+    let result = unsafe { libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut size) };
+    (result == 0 && size.ws_col > 0).then_some(size.ws_col as usize)
+}
+
+/// Show `line` as the status line, cut to the terminal width so it never wraps.
+fn show_status(line: &str) {
+    let mut status = STATUS_LINE.lock().unwrap_or_else(|e| e.into_inner());
+    let line: String = match terminal_width() {
+        Some(width) => line.chars().take(width - 1).collect(),
+        None => line.to_string(),
+    };
+    let mut stdout = io::stdout().lock();
+    let _ = write!(stdout, "{}{}", CLEAR_LINE, line);
+    let _ = stdout.flush();
+    *status = Some(line);
+}
+
+fn clear_status() {
+    let mut status = STATUS_LINE.lock().unwrap_or_else(|e| e.into_inner());
+    if status.take().is_some() {
+        let mut stdout = io::stdout().lock();
+        let _ = write!(stdout, "{}", CLEAR_LINE);
+        let _ = stdout.flush();
+    }
+}
+
+/// Write `text` to `target`, moving the status line below it.
+fn write_above(target: &mut dyn Write, text: &[u8]) -> io::Result<()> {
+    let status = STATUS_LINE.lock().unwrap_or_else(|e| e.into_inner());
+    let Some(line) = status.as_deref() else {
+        return target.write_all(text);
+    };
+    let mut stdout = io::stdout().lock();
+    stdout.write_all(CLEAR_LINE.as_bytes())?;
+    stdout.flush()?;
+    target.write_all(text)?;
+    target.flush()?;
+    stdout.write_all(line.as_bytes())?;
+    stdout.flush()
+}
+
+/// Print a line of the CLI's output to stdout.
+pub fn print_line(line: &str) {
+    let _ = write_above(&mut io::stdout(), format!("{}\n", line).as_bytes());
+}
+
+/// Print a line of the CLI's output to stderr.
+pub fn eprint_line(line: &str) {
+    let _ = write_above(&mut io::stderr(), format!("{}\n", line).as_bytes());
+}
+
+/// Stdout for the log layer, keeping the status line below the log lines.
+pub struct Stdout;
+
+impl Write for Stdout {
+    /// The formatter writes each event with a single call, so the status line never ends up in
+    /// the middle of one.
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        write_above(&mut io::stdout(), buf)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        io::stdout().flush()
+    }
+}
+
+impl<'a> MakeWriter<'a> for Stdout {
+    type Writer = Stdout;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        Stdout
+    }
+}
+
+/// Progress of the albums of a run, shown until it is dropped.
+pub struct Progress {
+    state: Arc<Mutex<State>>,
+    started: Instant,
+    display: Display,
+    stop: Option<mpsc::Sender<()>>,
+    ticker: Option<thread::JoinHandle<()>>,
+}
+
+impl Progress {
+    /// Start showing progress through albums of the given sizes.
+    pub fn start(albums: &[Size], display: Display) -> Self {
+        let mut total = Size::default();
+        for size in albums {
+            total += *size;
+        }
+        let state = State {
+            albums: albums.len(),
+            total,
+            ..State::default()
+        };
+        info!(
+            "Planned {} albums with {} files, {}",
+            state.albums,
+            total.files,
+            format_bytes(total.bytes)
+        );
+
+        let state = Arc::new(Mutex::new(state));
+        let started = Instant::now();
+        let (stop, stop_rx) = mpsc::channel();
+        let interval = match display {
+            Display::Terminal => REDRAW_INTERVAL,
+            Display::Lines => LINE_INTERVAL,
+        };
+        let ticker_state = Arc::clone(&state);
+        // Progress lines belong to the run like every other log line
+        let span = Span::current();
+        let ticker = thread::spawn(move || {
+            let _span = span.enter();
+            while let Err(RecvTimeoutError::Timeout) = stop_rx.recv_timeout(interval) {
+                let line = ticker_state
+                    .lock()
+                    .unwrap_or_else(|e| e.into_inner())
+                    .render(started.elapsed());
+                match display {
+                    Display::Terminal => show_status(&line),
+                    Display::Lines => info!("Progress: {}", line),
+                }
+            }
+        });
+
+        let progress = Self {
+            state,
+            started,
+            display,
+            stop: Some(stop),
+            ticker: Some(ticker),
+        };
+        progress.redraw();
+        progress
+    }
+
+    fn update(&self, change: impl FnOnce(&mut State)) {
+        change(&mut self.state.lock().unwrap_or_else(|e| e.into_inner()));
+        self.redraw();
+    }
+
+    fn redraw(&self) {
+        if self.display == Display::Terminal {
+            let line = self
+                .state
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .render(self.started.elapsed());
+            show_status(&line);
+        }
+    }
+
+    pub fn album_started(&self, album_name: &str) {
+        self.update(|state| state.current = Some(album_name.into()));
+    }
+
+    /// Count an album of `size` as done, whether its upload completed or not.
+    pub fn album_finished(&self, size: Size, completed: bool) {
+        self.update(|state| {
+            state.current = None;
+            state.finished_albums += 1;
+            state.finished += size;
+            if !completed {
+                state.failed_albums += 1;
+            }
+        });
+    }
+}
+
+impl Drop for Progress {
+    fn drop(&mut self) {
+        // Dropping the sender wakes the ticker up
+        self.stop.take();
+        if let Some(ticker) = self.ticker.take() {
+            let _ = ticker.join();
+        }
+        if self.display == Display::Terminal {
+            clear_status();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use rstest::rstest;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_measure() {
+        let temp_dir = TempDir::new().unwrap();
+        let album = temp_dir.path();
+        fs::write(album.join("a.jpg"), [0u8; 100]).unwrap();
+        fs::write(album.join(".hidden.jpg"), [0u8; 10]).unwrap();
+        fs::create_dir(album.join("raw")).unwrap();
+        fs::write(album.join("raw/a.dng"), [0u8; 1000]).unwrap();
+
+        assert_eq!(
+            measure(album, ScanOptions::default()),
+            Size {
+                files: 3,
+                bytes: 1110
+            }
+        );
+        assert_eq!(
+            measure(
+                album,
+                ScanOptions {
+                    recursive: false,
+                    include_hidden: false
+                }
+            ),
+            Size {
+                files: 1,
+                bytes: 100
+            }
+        );
+        assert_eq!(
+            measure(&album.join("missing"), ScanOptions::default()),
+            Size::default()
+        );
+    }
+
+    #[rstest]
+    #[case(Duration::from_secs(12), "12s")]
+    #[case(Duration::from_secs(250), "4m 10s")]
+    #[case(Duration::from_secs(3900), "1h 05m")]
+    fn test_format_eta(#[case] duration: Duration, #[case] expected: &str) {
+        assert_eq!(format_eta(duration), expected);
+    }
+
+    fn state(finished_albums: usize, finished: Size) -> State {
+        State {
+            albums: 10,
+            total: Size {
+                files: 400,
+                bytes: 4_000_000_000,
+            },
+            finished_albums,
+            finished,
+            ..State::default()
+        }
+    }
+
+    #[test]
+    fn test_render() {
+        let mut running = state(
+            3,
+            Size {
+                files: 120,
+                bytes: 1_000_000_000,
+            },
+        );
+        running.failed_albums = 1;
+        running.current = Some("Summer".into());
+
+        assert_eq!(
+            running.render(Duration::from_secs(100)),
+            "Albums 3/10 (7 left, 1 failed), files 120/400, 1.0 GB of 4.0 GB, 10.0 MB/s, ETA 5m 00s | Summer"
+        );
+        assert_eq!(
+            state(0, Size::default()).render(Duration::ZERO),
+            "Albums 0/10 (10 left), files 0/400, 0.0 MB of 4.0 GB, -, ETA -"
+        );
+    }
+
+    #[rstest]
+    #[case(0, 0, None)]
+    #[case(2, 0, Some(Duration::from_secs(400)))]
+    #[case(2, 1_000_000_000, Some(Duration::from_secs(300)))]
+    #[case(10, 4_000_000_000, Some(Duration::ZERO))]
+    fn test_eta(
+        #[case] finished_albums: usize,
+        #[case] finished_bytes: u64,
+        #[case] expected: Option<Duration>,
+    ) {
+        let state = state(
+            finished_albums,
+            Size {
+                files: 0,
+                bytes: finished_bytes,
+            },
+        );
+        assert_eq!(state.eta(Duration::from_secs(100)), expected);
+    }
+
+    #[test]
+    fn test_progress_counts_albums() {
+        let sizes = [
+            Size {
+                files: 2,
+                bytes: 200,
+            },
+            Size {
+                files: 1,
+                bytes: 100,
+            },
+        ];
+        let progress = Progress::start(&sizes, Display::Lines);
+        progress.album_started("Summer");
+        assert_eq!(
+            progress.state.lock().unwrap().current.as_deref(),
+            Some("Summer")
+        );
+        progress.album_finished(sizes[0], true);
+        progress.album_started("Winter");
+        progress.album_finished(sizes[1], false);
+
+        let state = progress.state.lock().unwrap().clone();
+        assert_eq!(state.finished_albums, 2);
+        assert_eq!(state.failed_albums, 1);
+        assert_eq!(state.finished, state.total);
+        assert_eq!(state.current, None);
+    }
+}
@@ -0,0 +1,498 @@
+use crate::cancel::CancellationToken;
+use crate::immich_api::ImmichApi;
+use crate::prelude::*;
+use crate::relink::{collect_files, ensure_album, existing_album_ids, find_existing_asset_ids};
+use crate::traverse::{plan_albums, AlbumPlan};
+use crate::Arguments;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::io::{BufRead, Write};
+use std::path::PathBuf;
+
+/// Number of asset ids sent to the server in a single request.
+const BATCH_SIZE: usize = 1000;
+
+/// Difference between the assets an album should contain and the assets it contains.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct AlbumDiff {
+    pub album_name: Box<str>,
+    pub album_id: Option<String>,
+    /// Files below the album directories that are not on the server at all.
+    pub missing_on_server: usize,
+    pub to_add: Vec<String>,
+    pub to_remove: Vec<String>,
+}
+
+/// Bring album memberships on the server in line with the directory layout.
+///
+/// All directories mapping to the same album are combined into the desired asset set. Missing
+/// assets are added; assets that no longer belong are only removed with `--prune` after the
+/// user confirmed on `input`. Assets of other users and assets in the trash are never removed.
+pub fn reconcile(
+    arguments: &Arguments,
+    api: &dyn ImmichApi,
+    input: &mut dyn BufRead,
+    cancel: &CancellationToken,
+) -> Result<()> {
+    info!("Reconciling albums for directory: {}", arguments.path);
+
+    let plans = plan_albums(Path::new(arguments.path.as_ref()))?;
+    let mut album_ids = existing_album_ids(api)?;
+    let user_id = api
+        .current_user_id()
+        .context("Failed to look up the user of the API key")?;
+
+    let mut diffs = Vec::new();
+    for (album_name, directories) in group_by_album(plans) {
+        // Don't change anything based on an incomplete diff
+        if cancel.is_cancelled() {
+            info!("Cancelled before all albums were compared, no albums were changed");
+            return Ok(());
+        }
+
+        match diff_album(&album_name, &directories, api, &album_ids, &user_id) {
+            Ok(diff) => diffs.push(diff),
+            Err(e) => {
+                error!("Failed to reconcile album \"{}\": {:#}", album_name, e);
+                // Continue processing other albums
+            }
+        }
+    }
+
+    log_diff(&diffs, arguments.prune);
+
+    if arguments.dry_run {
+        info!("[DRY RUN] No albums were changed");
+        return Ok(());
+    }
+
+    for diff in diffs.iter().filter(|diff| !diff.to_add.is_empty()) {
+        if cancel.is_cancelled() {
+            return Ok(());
+        }
+        if let Err(e) = add_missing(diff, api, &mut album_ids) {
+            error!("Failed to update album \"{}\": {:#}", diff.album_name, e);
+        }
+    }
+
+    let removals: usize = diffs.iter().map(|diff| diff.to_remove.len()).sum();
+    if !arguments.prune || removals == 0 {
+        return Ok(());
+    }
+
+    let prompt = format!(
+        "Remove {} assets from {} albums? [y/N] ",
+        removals,
+        diffs
+            .iter()
+            .filter(|diff| !diff.to_remove.is_empty())
+            .count()
+    );
+    if !arguments.assume_yes && !confirm(&prompt, input)? {
+        info!("Pruning cancelled, no assets were removed");
+        return Ok(());
+    }
+
+    for diff in diffs.iter().filter(|diff| !diff.to_remove.is_empty()) {
+        if cancel.is_cancelled() {
+            return Ok(());
+        }
+        if let Err(e) = remove_strays(diff, api) {
+            error!("Failed to prune album \"{}\": {:#}", diff.album_name, e);
+        }
+    }
+
+    Ok(())
+}
+
+/// Combine planned directories by album name, keeping album order stable.
+fn group_by_album(plans: Vec<AlbumPlan>) -> BTreeMap<Box<str>, Vec<PathBuf>> {
+    let mut albums: BTreeMap<Box<str>, Vec<PathBuf>> = BTreeMap::new();
+    for plan in plans {
+        albums.entry(plan.album_name).or_default().push(plan.path);
+    }
+    albums
+}
+
+fn diff_album(
+    album_name: &str,
+    directories: &[PathBuf],
+    api: &dyn ImmichApi,
+    album_ids: &HashMap<String, String>,
+    user_id: &str,
+) -> Result<AlbumDiff> {
+    let mut files = Vec::new();
+    for directory in directories {
+        files.extend(collect_files(directory)?);
+    }
+
+    let found = find_existing_asset_ids(&files, api)?;
+    let missing_on_server = files.len() - found.len();
+    let desired: BTreeSet<String> = found.into_iter().collect();
+
+    let album_id = album_ids.get(album_name).cloned();
+    let actual = match &album_id {
+        Some(album_id) => api
+            .album_assets(album_id)
+            .with_context(|| format!("Failed to read assets of album \"{}\"", album_name))?,
+        None => Vec::new(),
+    };
+    let actual_ids: BTreeSet<&str> = actual.iter().map(|asset| asset.id.as_str()).collect();
+
+    // Shared albums contain assets of other users, which aren't ours to remove
+    let (to_remove, kept): (Vec<_>, Vec<_>) = actual
+        .iter()
+        .filter(|asset| !desired.contains(&asset.id))
+        .partition(|asset| asset.owner_id == user_id && !asset.is_trashed);
+    if !kept.is_empty() {
+        debug!(
+            "Album \"{}\": keeping {} assets of other users or in the trash",
+            album_name,
+            kept.len()
+        );
+    }
+
+    Ok(AlbumDiff {
+        album_name: album_name.into(),
+        album_id,
+        missing_on_server,
+        to_add: desired
+            .iter()
+            .filter(|asset_id| !actual_ids.contains(asset_id.as_str()))
+            .cloned()
+            .collect(),
+        to_remove: to_remove
+            .into_iter()
+            .map(|asset| asset.id.clone())
+            .collect(),
+    })
+}
+
+fn log_diff(diffs: &[AlbumDiff], prune: bool) {
+    for diff in diffs {
+        if diff.to_add.is_empty() && diff.to_remove.is_empty() {
+            debug!("Album \"{}\" is up to date", diff.album_name);
+            continue;
+        }
+
+        info!(
+            "Album \"{}\"{}: {} to add, {} {}",
+            diff.album_name,
+            if diff.album_id.is_none() {
+                " (new)"
+            } else {
+                ""
+            },
+            diff.to_add.len(),
+            diff.to_remove.len(),
+            if prune {
+                "to remove"
+            } else {
+                "not belonging (use --prune to remove)"
+            }
+        );
+        for asset_id in &diff.to_add {
+            debug!("  + {}", asset_id);
+        }
+        for asset_id in &diff.to_remove {
+            debug!("  - {}", asset_id);
+        }
+        if diff.missing_on_server > 0 {
+            info!(
+                "Album \"{}\": {} files are not on the server",
+                diff.album_name, diff.missing_on_server
+            );
+        }
+    }
+
+    info!(
+        "Reconcile plan: {} albums, {} assets to add, {} assets not belonging",
+        diffs.len(),
+        diffs.iter().map(|diff| diff.to_add.len()).sum::<usize>(),
+        diffs.iter().map(|diff| diff.to_remove.len()).sum::<usize>()
+    );
+}
+
+fn add_missing(
+    diff: &AlbumDiff,
+    api: &dyn ImmichApi,
+    album_ids: &mut HashMap<String, String>,
+) -> Result<()> {
+    let album_id = ensure_album(api, album_ids, &diff.album_name)?;
+
+    let mut added = 0;
+    for batch in diff.to_add.chunks(BATCH_SIZE) {
+        for result in api.add_assets_to_album(&album_id, batch)? {
+            if result.success {
+                added += 1;
+            } else {
+                error!(
+                    "Failed to add asset {} to album \"{}\": {}",
+                    result.id,
+                    diff.album_name,
+                    result.error.as_deref().unwrap_or("unknown error")
+                );
+            }
+        }
+    }
+
+    info!("Album \"{}\": added {} assets", diff.album_name, added);
+    Ok(())
+}
+
+fn remove_strays(diff: &AlbumDiff, api: &dyn ImmichApi) -> Result<()> {
+    let Some(album_id) = &diff.album_id else {
+        return Ok(());
+    };
+
+    let mut removed = 0;
+    for batch in diff.to_remove.chunks(BATCH_SIZE) {
+        for result in api.remove_assets_from_album(album_id, batch)? {
+            if result.success {
+                removed += 1;
+            } else {
+                error!(
+                    "Failed to remove asset {} from album \"{}\": {}",
+                    result.id,
+                    diff.album_name,
+                    result.error.as_deref().unwrap_or("unknown error")
+                );
+            }
+        }
+    }
+
+    info!("Album \"{}\": removed {} assets", diff.album_name, removed);
+    Ok(())
+}
+
+/// Ask on stderr, which stays free of events and reports.
+fn confirm(prompt: &str, input: &mut dyn BufRead) -> Result<bool> {
+    eprint!("{}", prompt);
+    std::io::stderr().flush().ok();
+
+    let mut answer = String::new();
+    input
+        .read_line(&mut answer)
+        .context("Failed to read confirmation")?;
+
+    Ok(matches!(answer.trim().to_lowercase().as_str(), "y" | "yes"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::immich_api::{
+        Album, AlbumAsset, AssetChecksum, BulkCheckAction, BulkCheckResult, BulkIdResult,
+        MockImmichApi,
+    };
+    use crate::Mode;
+    use pretty_assertions::assert_eq;
+    use sha1::{Digest, Sha1};
+    use tempfile::TempDir;
+
+    /// Files are known to the server as asset "asset-<contents>".
+    fn mock_check_existing_assets(checksums: &[AssetChecksum]) -> Result<Vec<BulkCheckResult>> {
+        let known = ["a", "b", "c"];
+        Ok(checksums
+            .iter()
+            .map(|c| {
+                let asset = known.iter().find(|contents| {
+                    format!("{:x}", Sha1::digest(contents.as_bytes())) == c.checksum
+                });
+                BulkCheckResult {
+                    id: c.id.clone(),
+                    action: if asset.is_some() {
+                        BulkCheckAction::Reject
+                    } else {
+                        BulkCheckAction::Accept
+                    },
+                    asset_id: asset.map(|contents| format!("asset-{contents}")),
+                    is_trashed: None,
+                }
+            })
+            .collect())
+    }
+
+    fn success(ids: &[String]) -> Result<Vec<BulkIdResult>> {
+        Ok(ids
+            .iter()
+            .map(|id| BulkIdResult {
+                id: id.clone(),
+                success: true,
+                error: None,
+            })
+            .collect())
+    }
+
+    fn album_asset(id: &str, owner_id: &str, is_trashed: bool) -> AlbumAsset {
+        AlbumAsset {
+            id: id.into(),
+            owner_id: owner_id.into(),
+            is_trashed,
+        }
+    }
+
+    /// `2024/summer` holds a and b, `2024/other` holds c; the server album "summer" holds b, a
+    /// stray asset, an asset of another user and an asset in the trash.
+    fn setup() -> (TempDir, MockImmichApi) {
+        let temp_dir = TempDir::new().unwrap();
+        let base_path = temp_dir.path();
+        fs::create_dir_all(base_path.join("2024/summer")).unwrap();
+        fs::create_dir_all(base_path.join("2024/other")).unwrap();
+        fs::write(base_path.join("2024/summer/a.jpg"), "a").unwrap();
+        fs::write(base_path.join("2024/summer/b.jpg"), "b").unwrap();
+        fs::write(base_path.join("2024/summer/new.jpg"), "new").unwrap();
+        fs::write(base_path.join("2024/other/c.jpg"), "c").unwrap();
+
+        let mut api = MockImmichApi::new();
+        api.expect_list_albums().returning(|| {
+            Ok(vec![Album {
+                id: "album-summer".into(),
+                album_name: "summer".into(),
+            }])
+        });
+        api.expect_check_existing_assets()
+            .returning(mock_check_existing_assets);
+        api.expect_current_user_id().returning(|| Ok("user".into()));
+        api.expect_album_assets().returning(|_| {
+            Ok(vec![
+                album_asset("asset-b", "user", false),
+                album_asset("asset-stray", "user", false),
+                album_asset("asset-foreign", "other-user", false),
+                album_asset("asset-trashed", "user", true),
+            ])
+        });
+
+        (temp_dir, api)
+    }
+
+    fn arguments(base_path: &Path, dry_run: bool, prune: bool, assume_yes: bool) -> Arguments {
+        Arguments {
+            mode: Mode::Reconcile,
+            path: base_path.to_string_lossy().into_owned().into_boxed_str(),
+            dry_run,
+            prune,
+            assume_yes,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_diff_album() {
+        let (temp_dir, api) = setup();
+        let album_ids = existing_album_ids(&api).unwrap();
+
+        let diff = diff_album(
+            "summer",
+            &[temp_dir.path().join("2024/summer")],
+            &api,
+            &album_ids,
+            "user",
+        )
+        .unwrap();
+
+        assert_eq!(
+            diff,
+            AlbumDiff {
+                album_name: "summer".into(),
+                album_id: Some("album-summer".into()),
+                missing_on_server: 1,
+                to_add: vec!["asset-a".into()],
+                to_remove: vec!["asset-stray".into()],
+            }
+        );
+    }
+
+    #[test]
+    fn test_group_by_album_combines_directories() {
+        let plans = vec![
+            AlbumPlan {
+                path: "/base/2024/other".into(),
+                album_name: "2024".into(),
+            },
+            AlbumPlan {
+                path: "/base/archive/2024".into(),
+                album_name: "2024".into(),
+            },
+        ];
+
+        let albums = group_by_album(plans);
+
+        assert_eq!(
+            albums.get("2024"),
+            Some(&vec![
+                PathBuf::from("/base/2024/other"),
+                PathBuf::from("/base/archive/2024")
+            ])
+        );
+    }
+
+    #[test]
+    fn test_reconcile_dry_run_changes_nothing() {
+        let (temp_dir, mut api) = setup();
+        api.expect_create_album().never();
+        api.expect_add_assets_to_album().never();
+        api.expect_remove_assets_from_album().never();
+
+        let arguments = arguments(temp_dir.path(), true, true, true);
+        assert!(reconcile(
+            &arguments,
+            &api,
+            &mut "y\n".as_bytes(),
+            &CancellationToken::new()
+        )
+        .is_ok());
+    }
+
+    #[test]
+    fn test_reconcile_adds_missing_without_prune() {
+        let (temp_dir, mut api) = setup();
+        api.expect_create_album().times(1).returning(|name| {
+            Ok(Album {
+                id: format!("album-{name}"),
+                album_name: name.into(),
+            })
+        });
+        api.expect_add_assets_to_album()
+            .times(2)
+            .returning(|_, ids| success(ids));
+        api.expect_remove_assets_from_album().never();
+
+        let arguments = arguments(temp_dir.path(), false, false, false);
+        assert!(reconcile(
+            &arguments,
+            &api,
+            &mut "".as_bytes(),
+            &CancellationToken::new()
+        )
+        .is_ok());
+    }
+
+    #[rstest::rstest]
+    #[case("y\n", 1)]
+    #[case("n\n", 0)]
+    #[case("", 0)]
+    fn test_reconcile_prune_requires_confirmation(#[case] answer: &str, #[case] removals: usize) {
+        let (temp_dir, mut api) = setup();
+        api.expect_create_album().returning(|name| {
+            Ok(Album {
+                id: format!("album-{name}"),
+                album_name: name.into(),
+            })
+        });
+        api.expect_add_assets_to_album()
+            .returning(|_, ids| success(ids));
+        api.expect_remove_assets_from_album()
+            .withf(|album_id, ids| album_id == "album-summer" && ids == ["asset-stray"])
+            .times(removals)
+            .returning(|_, ids| success(ids));
+
+        let arguments = arguments(temp_dir.path(), false, true, false);
+        assert!(reconcile(
+            &arguments,
+            &api,
+            &mut answer.as_bytes(),
+            &CancellationToken::new()
+        )
+        .is_ok());
+    }
+}
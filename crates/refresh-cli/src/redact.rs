@@ -0,0 +1,100 @@
+use std::borrow::Cow;
+use std::io::{self, Write};
+use std::sync::RwLock;
+use tracing_subscriber::fmt::MakeWriter;
+
+const REDACTED: &str = "<redacted>";
+
+/// Secrets that must never show up in log output or echoed CLI output.
+static SECRETS: RwLock<Vec<Box<str>>> = RwLock::new(Vec::new());
+
+/// Redact `secret` from all output written from now on.
+pub fn register(secret: &str) {
+    if secret.is_empty() {
+        return;
+    }
+    let mut secrets = SECRETS.write().unwrap_or_else(|e| e.into_inner());
+    if !secrets.iter().any(|known| known.as_ref() == secret) {
+        secrets.push(secret.into());
+    }
+}
+
+/// `text` with every registered secret replaced.
+pub fn redact(text: &str) -> Cow<'_, str> {
+    let secrets = SECRETS.read().unwrap_or_else(|e| e.into_inner());
+    redact_secrets(text, &secrets)
+}
+
+fn redact_secrets<'a>(text: &'a str, secrets: &[Box<str>]) -> Cow<'a, str> {
+    let mut text = Cow::Borrowed(text);
+    for secret in secrets {
+        if text.contains(secret.as_ref()) {
+            text = Cow::Owned(text.replace(secret.as_ref(), REDACTED));
+        }
+    }
+    text
+}
+
+/// Wraps the writers of a tracing layer so every formatted event is redacted.
+pub struct Redacting<M>(pub M);
+
+impl<'a, M: MakeWriter<'a>> MakeWriter<'a> for Redacting<M> {
+    type Writer = RedactingWriter<M::Writer>;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        RedactingWriter(self.0.make_writer())
+    }
+}
+
+pub struct RedactingWriter<W>(W);
+
+impl<W: Write> Write for RedactingWriter<W> {
+    /// The formatter writes each event with a single call, so a secret is never split between
+    /// two writes.
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match std::str::from_utf8(buf) {
+            Ok(text) => self.0.write_all(redact(text).as_bytes())?,
+            Err(_) => self.0.write_all(buf)?,
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_redact_secrets() {
+        let secrets = vec!["s3cret".into(), "other".into()];
+
+        assert_eq!(
+            redact_secrets("key=s3cret, again s3cret and other", &secrets),
+            "key=<redacted>, again <redacted> and <redacted>"
+        );
+        assert!(matches!(
+            redact_secrets("nothing here", &secrets),
+            Cow::Borrowed(_)
+        ));
+    }
+
+    #[test]
+    fn test_redacting_writer() {
+        register("writer-test-key");
+        let mut output = Vec::new();
+
+        RedactingWriter(&mut output)
+            .write_all(b"x-api-key: writer-test-key\n")
+            .unwrap();
+
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "x-api-key: <redacted>\n"
+        );
+    }
+}
@@ -0,0 +1,493 @@
+use crate::cancel::CancellationToken;
+use crate::execute::ExecuteError;
+use crate::immich_api::{AssetChecksum, BulkCheckAction, ImmichApi};
+use crate::prelude::*;
+use crate::traverse::{plan_albums, AlbumPlan};
+use crate::Arguments;
+use sha1::{Digest, Sha1};
+use std::collections::HashMap;
+use std::io::Read;
+use std::path::PathBuf;
+
+/// Number of checksums or asset ids sent to the server in a single request.
+const BATCH_SIZE: usize = 1000;
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct RelinkStats {
+    pub files: usize,
+    pub found: usize,
+    pub missing: usize,
+    pub added: usize,
+    pub already_in_album: usize,
+}
+
+/// Rebuild albums from the directory layout using assets that already exist on the server.
+///
+/// Every file below a planned album directory is looked up by its SHA-1 checksum; matching
+/// assets are added to the album, which is created when missing. Nothing is uploaded. Fails when
+/// any directory failed, and with `ExecuteError::Cancelled` when cancelled.
+pub fn relink(
+    arguments: &Arguments,
+    api: &dyn ImmichApi,
+    cancel: &CancellationToken,
+) -> Result<()> {
+    info!("Relinking albums for directory: {}", arguments.path);
+
+    let plans = plan_albums(Path::new(arguments.path.as_ref()))?;
+
+    let mut album_ids = existing_album_ids(api)?;
+
+    let mut totals = RelinkStats::default();
+    let mut completed = 0;
+    let mut failed = 0;
+
+    for (index, plan) in plans.iter().enumerate() {
+        // Stop scheduling new directories once cancelled
+        if cancel.is_cancelled() {
+            info!(
+                "Cancelled, {} directories not relinked",
+                plans.len() - index
+            );
+            break;
+        }
+
+        match relink_album(plan, api, &mut album_ids, arguments.dry_run) {
+            Ok(stats) => {
+                completed += 1;
+                totals.files += stats.files;
+                totals.found += stats.found;
+                totals.missing += stats.missing;
+                totals.added += stats.added;
+                totals.already_in_album += stats.already_in_album;
+            }
+            Err(e) => {
+                failed += 1;
+                error!(
+                    "Failed to relink directory {}: {:#}",
+                    plan.path.display(),
+                    e
+                );
+                // Continue processing other directories
+            }
+        }
+    }
+
+    info!(
+        "Relinked {} of {} directories, {} failed: {} files, {} found on server, {} not found, {} added, {} already in album",
+        completed,
+        plans.len(),
+        failed,
+        totals.files,
+        totals.found,
+        totals.missing,
+        totals.added,
+        totals.already_in_album
+    );
+
+    if cancel.is_cancelled() {
+        return Err(ExecuteError::Cancelled.into());
+    }
+    if failed > 0 {
+        bail!("Failed to relink {} of {} directories", failed, plans.len());
+    }
+    Ok(())
+}
+
+fn relink_album(
+    plan: &AlbumPlan,
+    api: &dyn ImmichApi,
+    album_ids: &mut HashMap<String, String>,
+    dry_run: bool,
+) -> Result<RelinkStats> {
+    let files = collect_files(&plan.path)?;
+    let mut stats = RelinkStats {
+        files: files.len(),
+        ..Default::default()
+    };
+
+    let asset_ids = find_existing_asset_ids(&files, api)?;
+    stats.found = asset_ids.len();
+    stats.missing = stats.files - stats.found;
+
+    if stats.missing > 0 {
+        info!(
+            "{} of {} files in {} are not on the server",
+            stats.missing,
+            stats.files,
+            plan.path.display()
+        );
+    }
+
+    if asset_ids.is_empty() {
+        info!(
+            "No existing assets for album \"{}\" in {}",
+            plan.album_name,
+            plan.path.display()
+        );
+        return Ok(stats);
+    }
+
+    if dry_run {
+        info!(
+            "[DRY RUN] Would add {} assets to album \"{}\" from {}",
+            asset_ids.len(),
+            plan.album_name,
+            plan.path.display()
+        );
+        return Ok(stats);
+    }
+
+    let album_id = ensure_album(api, album_ids, &plan.album_name)?;
+
+    for batch in asset_ids.chunks(BATCH_SIZE) {
+        let results = api
+            .add_assets_to_album(&album_id, batch)
+            .with_context(|| format!("Failed to add assets to album \"{}\"", plan.album_name))?;
+
+        for result in results {
+            match (result.success, result.error.as_deref()) {
+                (true, _) => stats.added += 1,
+                (false, Some("duplicate")) => stats.already_in_album += 1,
+                (false, error) => error!(
+                    "Failed to add asset {} to album \"{}\": {}",
+                    result.id,
+                    plan.album_name,
+                    error.unwrap_or("unknown error")
+                ),
+            }
+        }
+    }
+
+    info!(
+        "Album \"{}\": added {} assets, {} already present",
+        plan.album_name, stats.added, stats.already_in_album
+    );
+
+    Ok(stats)
+}
+
+/// Album ids on the server keyed by album name.
+pub fn existing_album_ids(api: &dyn ImmichApi) -> Result<HashMap<String, String>> {
+    Ok(api
+        .list_albums()
+        .context("Failed to list albums")?
+        .into_iter()
+        .map(|album| (album.album_name, album.id))
+        .collect())
+}
+
+/// Id of the album named `album_name`, creating the album when it doesn't exist yet.
+pub fn ensure_album(
+    api: &dyn ImmichApi,
+    album_ids: &mut HashMap<String, String>,
+    album_name: &str,
+) -> Result<String> {
+    if let Some(album_id) = album_ids.get(album_name) {
+        return Ok(album_id.clone());
+    }
+
+    let album = api
+        .create_album(album_name)
+        .with_context(|| format!("Failed to create album \"{}\"", album_name))?;
+    info!("Created album \"{}\"", album_name);
+    album_ids.insert(album.album_name, album.id.clone());
+
+    Ok(album.id)
+}
+
+/// Resolve local files to the ids of identical assets on the server.
+pub fn find_existing_asset_ids(files: &[PathBuf], api: &dyn ImmichApi) -> Result<Vec<String>> {
+    let mut asset_ids = Vec::new();
+
+    for batch in files.chunks(BATCH_SIZE) {
+        let checksums = batch
+            .iter()
+            .enumerate()
+            .map(|(index, file)| {
+                Ok(AssetChecksum {
+                    id: index.to_string(),
+                    checksum: sha1_file(file)?,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let results = api
+            .check_existing_assets(&checksums)
+            .context("Failed to look up assets by checksum")?;
+
+        for result in results {
+            match (result.action, result.asset_id) {
+                (BulkCheckAction::Reject, Some(asset_id)) => {
+                    if result.is_trashed == Some(true) {
+                        debug!("Skipping trashed asset {}", asset_id);
+                    } else {
+                        asset_ids.push(asset_id);
+                    }
+                }
+                _ => {
+                    if let Some(file) = result.id.parse::<usize>().ok().and_then(|i| batch.get(i)) {
+                        debug!("Not found on server: {}", file.display());
+                    }
+                }
+            }
+        }
+    }
+
+    Ok(asset_ids)
+}
+
+/// All files below `dir`, including hidden ones, matching `immich upload -H -r`.
+pub fn collect_files(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    let mut pending = vec![dir.to_path_buf()];
+
+    while let Some(dir) = pending.pop() {
+        let entries = fs::read_dir(&dir)
+            .with_context(|| format!("Failed to read directory: {}", dir.display()))?;
+
+        for entry in entries {
+            let entry =
+                entry.with_context(|| format!("Failed to read entry in {}", dir.display()))?;
+            let path = entry.path();
+            if path.is_dir() {
+                pending.push(path);
+            } else if path.is_file() {
+                files.push(path);
+            }
+        }
+    }
+    files.sort();
+
+    Ok(files)
+}
+
+/// Hex encoded SHA-1 of a file, the checksum Immich uses to detect duplicates.
+fn sha1_file(path: &Path) -> Result<String> {
+    let mut file =
+        fs::File::open(path).with_context(|| format!("Failed to open {}", path.display()))?;
+    let mut hasher = Sha1::new();
+    let mut buffer = vec![0; 64 * 1024];
+
+    loop {
+        let read = file
+            .read(&mut buffer)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+    }
+
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::immich_api::{Album, BulkCheckResult, BulkIdResult, MockImmichApi};
+    use mockall::predicate::eq;
+    use pretty_assertions::assert_eq;
+    use tempfile::TempDir;
+
+    fn arguments(base_path: &Path, dry_run: bool) -> Arguments {
+        Arguments {
+            path: base_path.to_string_lossy().into_owned().into_boxed_str(),
+            dry_run,
+            ..Default::default()
+        }
+    }
+
+    /// Treat files whose contents start with "on-server" as existing assets.
+    fn mock_check_existing_assets(checksums: &[AssetChecksum]) -> Result<Vec<BulkCheckResult>> {
+        let existing = sha1_of(b"on-server");
+        Ok(checksums
+            .iter()
+            .map(|c| BulkCheckResult {
+                id: c.id.clone(),
+                action: if c.checksum == existing {
+                    BulkCheckAction::Reject
+                } else {
+                    BulkCheckAction::Accept
+                },
+                asset_id: (c.checksum == existing).then(|| format!("asset-{}", c.id)),
+                is_trashed: None,
+            })
+            .collect())
+    }
+
+    fn sha1_of(contents: &[u8]) -> String {
+        format!("{:x}", Sha1::digest(contents))
+    }
+
+    #[test]
+    fn test_sha1_file() {
+        let temp_dir = TempDir::new().unwrap();
+        let file = temp_dir.path().join("photo.jpg");
+        fs::write(&file, "hello").unwrap();
+
+        assert_eq!(
+            sha1_file(&file).unwrap(),
+            "aaf4c61ddcc5e8a2dabede0f3b482cd9aea9434d"
+        );
+    }
+
+    #[test]
+    fn test_collect_files_includes_hidden_and_nested() {
+        let temp_dir = TempDir::new().unwrap();
+        let dir = temp_dir.path();
+        fs::create_dir(dir.join("nested")).unwrap();
+        fs::write(dir.join("a.jpg"), "a").unwrap();
+        fs::write(dir.join(".hidden.jpg"), "b").unwrap();
+        fs::write(dir.join("nested/c.jpg"), "c").unwrap();
+
+        assert_eq!(
+            collect_files(dir).unwrap(),
+            vec![
+                dir.join(".hidden.jpg"),
+                dir.join("a.jpg"),
+                dir.join("nested/c.jpg")
+            ]
+        );
+    }
+
+    #[test]
+    fn test_relink_creates_missing_album_and_adds_existing_assets() {
+        let temp_dir = TempDir::new().unwrap();
+        let base_path = temp_dir.path();
+        fs::create_dir_all(base_path.join("2024/summer")).unwrap();
+        fs::write(base_path.join("2024/summer/a.jpg"), "on-server").unwrap();
+        fs::write(base_path.join("2024/summer/b.jpg"), "new").unwrap();
+
+        let mut api = MockImmichApi::new();
+        api.expect_list_albums().returning(|| Ok(vec![]));
+        api.expect_check_existing_assets()
+            .returning(mock_check_existing_assets);
+        api.expect_create_album()
+            .with(eq("summer"))
+            .times(1)
+            .returning(|name| {
+                Ok(Album {
+                    id: "album-1".into(),
+                    album_name: name.into(),
+                })
+            });
+        api.expect_add_assets_to_album()
+            .withf(|album_id, asset_ids| album_id == "album-1" && asset_ids == ["asset-0"])
+            .times(1)
+            .returning(|_, ids| {
+                Ok(ids
+                    .iter()
+                    .map(|id| BulkIdResult {
+                        id: id.clone(),
+                        success: true,
+                        error: None,
+                    })
+                    .collect())
+            });
+
+        assert!(relink(
+            &arguments(base_path, false),
+            &api,
+            &CancellationToken::new()
+        )
+        .is_ok());
+    }
+
+    #[test]
+    fn test_relink_reuses_existing_album() {
+        let temp_dir = TempDir::new().unwrap();
+        let base_path = temp_dir.path();
+        fs::create_dir_all(base_path.join("2024/other")).unwrap();
+        fs::write(base_path.join("2024/other/a.jpg"), "on-server").unwrap();
+
+        let mut api = MockImmichApi::new();
+        api.expect_list_albums().returning(|| {
+            Ok(vec![Album {
+                id: "album-2024".into(),
+                album_name: "2024".into(),
+            }])
+        });
+        api.expect_check_existing_assets()
+            .returning(mock_check_existing_assets);
+        api.expect_create_album().never();
+        api.expect_add_assets_to_album()
+            .withf(|album_id, _| album_id == "album-2024")
+            .times(1)
+            .returning(|_, ids| {
+                Ok(ids
+                    .iter()
+                    .map(|id| BulkIdResult {
+                        id: id.clone(),
+                        success: false,
+                        error: Some("duplicate".into()),
+                    })
+                    .collect())
+            });
+
+        assert!(relink(
+            &arguments(base_path, false),
+            &api,
+            &CancellationToken::new()
+        )
+        .is_ok());
+    }
+
+    #[test]
+    fn test_relink_fails_when_a_directory_fails() {
+        let temp_dir = TempDir::new().unwrap();
+        let base_path = temp_dir.path();
+        fs::create_dir_all(base_path.join("2024/summer")).unwrap();
+        fs::create_dir_all(base_path.join("2024/winter")).unwrap();
+        fs::write(base_path.join("2024/summer/a.jpg"), "on-server").unwrap();
+        fs::write(base_path.join("2024/winter/b.jpg"), "on-server").unwrap();
+
+        let mut api = MockImmichApi::new();
+        api.expect_list_albums().returning(|| Ok(vec![]));
+        api.expect_check_existing_assets()
+            .returning(|_| Err(anyhow::anyhow!("Bad Gateway")));
+
+        let error = relink(
+            &arguments(base_path, false),
+            &api,
+            &CancellationToken::new(),
+        )
+        .unwrap_err();
+        assert_eq!(error.to_string(), "Failed to relink 2 of 2 directories");
+    }
+
+    #[test]
+    fn test_relink_cancelled() {
+        let temp_dir = TempDir::new().unwrap();
+        let base_path = temp_dir.path();
+        fs::create_dir_all(base_path.join("2024/summer")).unwrap();
+
+        let mut api = MockImmichApi::new();
+        api.expect_list_albums().returning(|| Ok(vec![]));
+        api.expect_check_existing_assets().never();
+        let cancel = CancellationToken::new();
+        cancel.cancel(2);
+
+        let error = relink(&arguments(base_path, false), &api, &cancel).unwrap_err();
+        assert!(matches!(
+            error.downcast_ref(),
+            Some(ExecuteError::Cancelled)
+        ));
+    }
+
+    #[test]
+    fn test_relink_dry_run_does_not_modify_albums() {
+        let temp_dir = TempDir::new().unwrap();
+        let base_path = temp_dir.path();
+        fs::create_dir_all(base_path.join("2024/summer")).unwrap();
+        fs::write(base_path.join("2024/summer/a.jpg"), "on-server").unwrap();
+
+        let mut api = MockImmichApi::new();
+        api.expect_list_albums().returning(|| Ok(vec![]));
+        api.expect_check_existing_assets()
+            .returning(mock_check_existing_assets);
+        api.expect_create_album().never();
+        api.expect_add_assets_to_album().never();
+
+        assert!(relink(&arguments(base_path, true), &api, &CancellationToken::new()).is_ok());
+    }
+}
@@ -0,0 +1,250 @@
+use crate::prelude::*;
+use crate::upload_stats::UploadStats;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Outcome {
+    Completed,
+    Failed,
+    /// The run was cancelled while this directory was being processed.
+    Interrupted,
+    /// The upload hung or ran too long and was terminated; the directory can be run again.
+    TimedOut,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DirectoryReport {
+    /// The album directory, or a description of the files when uploading individual files.
+    pub source: Box<str>,
+    pub album_name: Box<str>,
+    /// The Immich user the directory was uploaded for; `None` for the run's own account.
+    pub user: Option<Box<str>>,
+    pub outcome: Outcome,
+    /// Counts reported by the CLI, also for failed uploads, combined over retries.
+    pub stats: UploadStats,
+}
+
+/// What happened to every planned directory of a run.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RunReport {
+    pub directories: Vec<DirectoryReport>,
+    /// Planned directories that were never started because the run stopped early.
+    pub not_started: usize,
+}
+
+impl RunReport {
+    pub fn record(
+        &mut self,
+        source: &str,
+        album_name: &str,
+        user: Option<&str>,
+        outcome: Outcome,
+        stats: UploadStats,
+    ) {
+        self.directories.push(DirectoryReport {
+            source: source.into(),
+            album_name: album_name.into(),
+            user: user.map(Into::into),
+            outcome,
+            stats,
+        });
+    }
+
+    /// Sum of the statistics of all directories. The size is only known when every completed
+    /// upload reported it.
+    pub fn total_stats(&self) -> UploadStats {
+        sum_stats(self.directories.iter())
+    }
+
+    pub fn count(&self, outcome: Outcome) -> usize {
+        self.directories
+            .iter()
+            .filter(|directory| directory.outcome == outcome)
+            .count()
+    }
+
+    /// The users directories were uploaded for, in the order they first appear.
+    pub fn users(&self) -> Vec<Option<&str>> {
+        let mut users = Vec::new();
+        for directory in &self.directories {
+            let user = directory.user.as_deref();
+            if !users.contains(&user) {
+                users.push(user);
+            }
+        }
+        users
+    }
+
+    fn log_user_summary(&self, user: Option<&str>) {
+        let directories: Vec<&DirectoryReport> = self
+            .directories
+            .iter()
+            .filter(|directory| directory.user.as_deref() == user)
+            .collect();
+        let count = |outcome| {
+            directories
+                .iter()
+                .filter(|directory| directory.outcome == outcome)
+                .count()
+        };
+        let stats = sum_stats(directories.iter().copied());
+
+        info!(
+            "User {}: {} completed, {} failed, {} timed out, {} interrupted; {} uploaded, {} duplicates, {} failed files",
+            user.unwrap_or("(default account)"),
+            count(Outcome::Completed),
+            count(Outcome::Failed),
+            count(Outcome::TimedOut),
+            count(Outcome::Interrupted),
+            stats.uploaded,
+            stats.duplicates,
+            stats.failed
+        );
+    }
+
+    /// `Summary: 3 completed, 1 failed, ...`
+    pub fn summary(&self) -> String {
+        format!(
+            "Summary: {} completed, {} failed, {} timed out, {} interrupted, {} not started",
+            self.count(Outcome::Completed),
+            self.count(Outcome::Failed),
+            self.count(Outcome::TimedOut),
+            self.count(Outcome::Interrupted),
+            self.not_started
+        )
+    }
+
+    pub fn log_summary(&self) {
+        for directory in &self.directories {
+            match directory.outcome {
+                Outcome::Completed => {}
+                Outcome::Failed => error!(
+                    "Failed: {} (album \"{}\")",
+                    directory.source, directory.album_name
+                ),
+                Outcome::Interrupted => info!(
+                    "Interrupted: {} (album \"{}\")",
+                    directory.source, directory.album_name
+                ),
+                Outcome::TimedOut => error!(
+                    "Timed out: {} (album \"{}\")",
+                    directory.source, directory.album_name
+                ),
+            }
+        }
+
+        let users = self.users();
+        if users.iter().any(Option::is_some) {
+            for user in users {
+                self.log_user_summary(user);
+            }
+        }
+
+        info!("{}", self.summary());
+
+        let total = self.total_stats();
+        info!(
+            "Files: {} found, {} duplicates, {} uploaded{}, {} failed",
+            total.found(),
+            total.duplicates,
+            total.uploaded,
+            total
+                .uploaded_bytes
+                .map(|bytes| format!(" ({:.1} MB)", bytes as f64 / 1_000_000.0))
+                .unwrap_or_default(),
+            total.failed
+        );
+    }
+}
+
+fn sum_stats<'a>(directories: impl Iterator<Item = &'a DirectoryReport>) -> UploadStats {
+    directories
+        .map(|directory| directory.stats)
+        .fold(UploadStats::default(), |total, stats| UploadStats {
+            new: total.new + stats.new,
+            duplicates: total.duplicates + stats.duplicates,
+            uploaded: total.uploaded + stats.uploaded,
+            failed: total.failed + stats.failed,
+            uploaded_bytes: total.uploaded_bytes_with(&stats),
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_record_and_count() {
+        let mut report = RunReport::default();
+        report.record(
+            "/base/2024/summer",
+            "summer",
+            None,
+            Outcome::Completed,
+            UploadStats::default(),
+        );
+        report.record(
+            "/base/2024/winter",
+            "winter",
+            None,
+            Outcome::Failed,
+            UploadStats::default(),
+        );
+        report.record(
+            "/base/2024/other",
+            "2024",
+            None,
+            Outcome::Interrupted,
+            UploadStats::default(),
+        );
+        report.not_started = 2;
+
+        assert_eq!(report.count(Outcome::Completed), 1);
+        assert_eq!(report.count(Outcome::Failed), 1);
+        assert_eq!(report.count(Outcome::Interrupted), 1);
+        assert_eq!(
+            report.directories[2],
+            DirectoryReport {
+                source: "/base/2024/other".into(),
+                album_name: "2024".into(),
+                user: None,
+                outcome: Outcome::Interrupted,
+                stats: UploadStats::default(),
+            }
+        );
+    }
+
+    #[test]
+    fn test_total_stats() {
+        let mut report = RunReport::default();
+        let stats = |new, duplicates, uploaded_bytes| UploadStats {
+            new,
+            duplicates,
+            uploaded: new,
+            failed: 0,
+            uploaded_bytes,
+        };
+        report.record("a", "a", None, Outcome::Completed, stats(2, 1, Some(2_000)));
+        report.record("b", "b", None, Outcome::Completed, stats(0, 5, None));
+        report.record("c", "c", None, Outcome::Completed, stats(3, 0, Some(500)));
+
+        assert_eq!(report.total_stats(), stats(5, 6, Some(2_500)));
+
+        report.record("d", "d", None, Outcome::Completed, stats(1, 0, None));
+        assert_eq!(report.total_stats().uploaded_bytes, None);
+    }
+
+    #[test]
+    fn test_users_in_order_of_appearance() {
+        let mut report = RunReport::default();
+        let stats = UploadStats::default();
+        report.record("a", "a", Some("bob"), Outcome::Completed, stats);
+        report.record("b", "b", None, Outcome::Completed, stats);
+        report.record("c", "c", Some("alice"), Outcome::Failed, stats);
+        report.record("d", "d", Some("bob"), Outcome::Completed, stats);
+
+        assert_eq!(report.users(), vec![Some("bob"), None, Some("alice")]);
+    }
+}
@@ -0,0 +1,360 @@
+use crate::cancel::CancellationToken;
+use crate::events::{self, Event, SkipReason};
+use crate::execute::{ExecuteArgs, Executer};
+use crate::pg_dump::{parse_dump, ImmichDump};
+use crate::prelude::*;
+use crate::progress;
+use crate::report::RunReport;
+use crate::traverse::{run_jobs, Job, Sequential};
+use crate::Arguments;
+use std::collections::{BTreeMap, HashMap};
+use std::io::BufReader;
+use std::path::PathBuf;
+
+/// Maximum number of files passed to a single `immich upload` invocation.
+const FILES_PER_UPLOAD: usize = 500;
+
+/// Rewrites original paths on the Immich server to paths in the local library.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PathMapping {
+    pub from: Box<str>,
+    pub to: Box<str>,
+}
+
+impl PathMapping {
+    /// Parse a `FROM=TO` mapping as given to `--map-prefix`.
+    pub fn parse(value: &str) -> Result<Self> {
+        match value.split_once('=') {
+            Some((from, to)) if !from.is_empty() && !to.is_empty() => Ok(Self {
+                from: from.trim_end_matches('/').into(),
+                to: to.trim_end_matches('/').into(),
+            }),
+            _ => bail!("Invalid prefix mapping: {}. Expected FROM=TO", value),
+        }
+    }
+
+    fn apply(&self, original_path: &str) -> Option<PathBuf> {
+        let rest = original_path.strip_prefix(self.from.as_ref())?;
+        if !rest.is_empty() && !rest.starts_with('/') {
+            return None;
+        }
+        Some(PathBuf::from(format!("{}{}", self.to, rest)))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RestoreAlbum {
+    pub album_name: Box<str>,
+    pub files: Vec<PathBuf>,
+}
+
+/// Albums and the local files that belong to them according to a database dump.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct RestorePlan {
+    pub albums: Vec<RestoreAlbum>,
+    /// Original paths no mapping applied to.
+    pub unmapped: Vec<String>,
+    /// Memberships pointing at assets that are deleted or absent from the dump.
+    pub unknown_assets: usize,
+}
+
+/// Map the album memberships of `dump` onto the local library.
+///
+/// With no mappings the original paths are used as they are. Otherwise the longest matching
+/// prefix wins and paths matching no mapping are reported in `unmapped`.
+pub fn restore_plan(dump: &ImmichDump, mappings: &[PathMapping]) -> RestorePlan {
+    let mut mappings: Vec<&PathMapping> = mappings.iter().collect();
+    mappings.sort_by_key(|mapping| std::cmp::Reverse(mapping.from.len()));
+
+    let album_names: HashMap<&str, &str> = dump
+        .albums
+        .iter()
+        .map(|album| (album.id.as_str(), album.name.as_str()))
+        .collect();
+
+    let mut plan = RestorePlan::default();
+    let mut albums: BTreeMap<(&str, &str), Vec<PathBuf>> = BTreeMap::new();
+
+    for (album_id, asset_id) in &dump.album_assets {
+        let Some(album_name) = album_names.get(album_id.as_str()) else {
+            continue;
+        };
+        let Some(original_path) = dump.asset_paths.get(asset_id) else {
+            plan.unknown_assets += 1;
+            continue;
+        };
+
+        let local_path = if mappings.is_empty() {
+            Some(PathBuf::from(original_path))
+        } else {
+            mappings
+                .iter()
+                .find_map(|mapping| mapping.apply(original_path))
+        };
+
+        match local_path {
+            Some(local_path) => albums
+                .entry((album_name, album_id))
+                .or_default()
+                .push(local_path),
+            None => plan.unmapped.push(original_path.clone()),
+        }
+    }
+
+    plan.albums = albums
+        .into_iter()
+        .map(|((album_name, _), mut files)| {
+            files.sort();
+            RestoreAlbum {
+                album_name: album_name.into(),
+                files,
+            }
+        })
+        .collect();
+
+    plan
+}
+
+/// A job for every batch of files of the albums recorded in a `pg_dump`. Files missing locally
+/// are reported and left out. Albums without any local file get no job; they are discovered
+/// and skipped right away.
+pub fn restore_jobs<'a>(
+    arguments: &Arguments,
+    executor: &'a Executer,
+) -> Result<Vec<Job<'a, Executer>>> {
+    info!("Reading album memberships from dump: {}", arguments.path);
+
+    let dump_file = fs::File::open(arguments.path.as_ref())
+        .with_context(|| format!("Failed to open dump: {}", arguments.path))?;
+    let dump = parse_dump(BufReader::new(dump_file))
+        .with_context(|| format!("Failed to parse dump: {}", arguments.path))?;
+
+    let plan = restore_plan(&dump, &arguments.path_mappings);
+
+    info!(
+        "Restore plan: {} albums, {} files",
+        plan.albums.len(),
+        plan.albums
+            .iter()
+            .map(|album| album.files.len())
+            .sum::<usize>()
+    );
+    if !plan.unmapped.is_empty() {
+        error!(
+            "{} files match no --map-prefix mapping and are skipped",
+            plan.unmapped.len()
+        );
+        for original_path in &plan.unmapped {
+            debug!("Unmapped: {}", original_path);
+        }
+    }
+    if plan.unknown_assets > 0 {
+        info!(
+            "{} album memberships refer to deleted or unknown assets and are skipped",
+            plan.unknown_assets
+        );
+    }
+
+    let mut jobs = Vec::new();
+    for album in &plan.albums {
+        let (present, missing): (Vec<&PathBuf>, Vec<&PathBuf>) =
+            album.files.iter().partition(|file| file.is_file());
+        for file in &missing {
+            error!(
+                "File for album \"{}\" not found: {}",
+                album.album_name,
+                file.display()
+            );
+        }
+
+        if present.is_empty() {
+            let source = format!("{} files from dump", missing.len());
+            events::emit(Event::DirDiscovered {
+                source: &source,
+                album: &album.album_name,
+                user: None,
+                files: 0,
+                bytes: 0,
+            });
+            events::emit(Event::DirSkipped {
+                source: &source,
+                album: &album.album_name,
+                user: None,
+                reason: SkipReason::FilesMissing,
+            });
+        }
+
+        let batches = present.chunks(FILES_PER_UPLOAD);
+        let batch_count = batches.len();
+        for (batch_index, batch) in batches.enumerate() {
+            let mut size = progress::Size::default();
+            for file in batch {
+                size += progress::Size {
+                    files: 1,
+                    bytes: fs::metadata(file).map_or(0, |metadata| metadata.len()),
+                };
+            }
+            jobs.push(Job {
+                group: album.album_name.clone(),
+                name: format!("batch {} of {}", batch_index + 1, batch_count).into(),
+                source: format!(
+                    "{} files from dump (batch {} of {})",
+                    batch.len(),
+                    batch_index + 1,
+                    batch_count
+                ),
+                user: None,
+                executor,
+                execute_args: ExecuteArgs {
+                    paths: batch
+                        .iter()
+                        .map(|file| file.to_string_lossy().into_owned().into_boxed_str())
+                        .collect(),
+                    album_name: album.album_name.clone(),
+                    dry_run: arguments.dry_run,
+                },
+                size,
+            });
+        }
+    }
+
+    Ok(jobs)
+}
+
+/// Recreate the albums recorded in a `pg_dump` by uploading their files into them. Files that
+/// already exist on the server are only added to the album by the Immich CLI.
+pub fn restore_albums(
+    arguments: &Arguments,
+    executor: &Executer,
+    progress: &progress::Options,
+    cancel: &CancellationToken,
+) -> Result<RunReport> {
+    let jobs = restore_jobs(arguments, executor)?;
+    run_jobs(&jobs, &Sequential::new(&jobs, progress.display), cancel)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Mode;
+    use pretty_assertions::assert_eq;
+    use rstest::rstest;
+
+    const IMMICH_V1_DUMP: &str = include_str!(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/fixtures/pg_dump/immich_v1.sql"
+    ));
+
+    #[rstest]
+    #[case(
+        "/usr/src/app/upload/library/admin=/mnt/photos",
+        "/usr/src/app/upload/library/admin",
+        "/mnt/photos"
+    )]
+    #[case("/data/=/photos/", "/data", "/photos")]
+    fn test_path_mapping_parse(#[case] value: &str, #[case] from: &str, #[case] to: &str) {
+        assert_eq!(
+            PathMapping::parse(value).unwrap(),
+            PathMapping {
+                from: from.into(),
+                to: to.into(),
+            }
+        );
+    }
+
+    #[rstest]
+    #[case("/data")]
+    #[case("=/photos")]
+    #[case("/data=")]
+    fn test_path_mapping_parse_invalid(#[case] value: &str) {
+        assert!(PathMapping::parse(value).is_err());
+    }
+
+    #[rstest]
+    #[case("/data/2024/a.jpg", Some("/photos/2024/a.jpg"))]
+    #[case("/data", Some("/photos"))]
+    #[case("/database/a.jpg", None)]
+    #[case("/other/a.jpg", None)]
+    fn test_path_mapping_apply(#[case] original_path: &str, #[case] expected: Option<&str>) {
+        let mapping = PathMapping::parse("/data=/photos").unwrap();
+        assert_eq!(mapping.apply(original_path), expected.map(PathBuf::from));
+    }
+
+    #[test]
+    fn test_restore_plan_from_fixture_dump() {
+        let dump = parse_dump(IMMICH_V1_DUMP.as_bytes()).unwrap();
+        let mappings = vec![
+            PathMapping::parse("/usr/src/app/upload=/srv/immich").unwrap(),
+            PathMapping::parse("/usr/src/app/upload/library/admin=/mnt/photos").unwrap(),
+        ];
+
+        let plan = restore_plan(&dump, &mappings);
+
+        assert_eq!(
+            plan,
+            RestorePlan {
+                albums: vec![RestoreAlbum {
+                    album_name: "Summer".into(),
+                    files: vec![
+                        "/mnt/photos/2024/summer/IMG_0001.jpg".into(),
+                        "/mnt/photos/2024/summer/IMG_0002.jpg".into(),
+                    ],
+                }],
+                unmapped: vec!["/mnt/external/scans/scan.png".into()],
+                unknown_assets: 1,
+            }
+        );
+    }
+
+    #[test]
+    fn test_restore_plan_without_mappings_keeps_original_paths() {
+        let dump = parse_dump(IMMICH_V1_DUMP.as_bytes()).unwrap();
+
+        let plan = restore_plan(&dump, &[]);
+
+        assert!(plan.unmapped.is_empty());
+        assert_eq!(
+            plan.albums
+                .iter()
+                .map(|album| (album.album_name.as_ref(), album.files.len()))
+                .collect::<Vec<_>>(),
+            vec![("Summer", 2), ("Tabs\tand \\ backslashes", 1)]
+        );
+    }
+
+    #[test]
+    fn test_restore_jobs_discovers_albums_before_skipping_them() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let dump_path = temp_dir.path().join("dump.sql");
+        fs::write(&dump_path, IMMICH_V1_DUMP).unwrap();
+        let arguments = Arguments {
+            mode: Mode::RestoreAlbums,
+            path: dump_path.to_string_lossy().into_owned().into_boxed_str(),
+            ..Default::default()
+        };
+        let executor = Executer::new();
+
+        let events = events::capture(|| {
+            assert!(restore_jobs(&arguments, &executor).unwrap().is_empty());
+        });
+
+        let order: Vec<(&str, &str)> = events
+            .iter()
+            .map(|event| {
+                (
+                    event["event"].as_str().unwrap(),
+                    event["album"].as_str().unwrap(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            order,
+            [
+                ("dir_discovered", "Summer"),
+                ("dir_skipped", "Summer"),
+                ("dir_discovered", "Tabs\tand \\ backslashes"),
+                ("dir_skipped", "Tabs\tand \\ backslashes"),
+            ]
+        );
+    }
+}
@@ -0,0 +1,286 @@
+use crate::config::SyslogSettings;
+use crate::log_rotation::utc_rfc3339;
+use crate::prelude::*;
+use crate::redact::redact;
+use std::fmt;
+use std::io;
+use std::net::{ToSocketAddrs, UdpSocket};
+use std::os::unix::net::UnixDatagram;
+use std::time::SystemTime;
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id, Record};
+use tracing::{Event, Level, Subscriber};
+use tracing_subscriber::layer;
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::Layer;
+
+const APP_NAME: &str = "immich-refresh";
+/// SD-ID of the structured data element that carries span and event fields. 32473 is the
+/// enterprise number reserved for documentation (RFC 5612), as no number is registered for us.
+const SD_ID: &str = "fields@32473";
+/// RFC 5424's placeholder for missing header fields and structured data.
+const NIL: &str = "-";
+
+enum Transport {
+    Unix(UnixDatagram),
+    Udp(UdpSocket),
+}
+
+impl Transport {
+    fn connect(address: &str) -> io::Result<Self> {
+        if address.starts_with('/') {
+            let socket = UnixDatagram::unbound()?;
+            socket.connect(address)?;
+            return Ok(Self::Unix(socket));
+        }
+
+        let target = address.to_socket_addrs()?.next().ok_or_else(|| {
+            io::Error::new(io::ErrorKind::NotFound, "address resolved to nothing")
+        })?;
+        let local = if target.is_ipv4() {
+            "0.0.0.0:0"
+        } else {
+            "[::]:0"
+        };
+        let socket = UdpSocket::bind(local)?;
+        socket.connect(target)?;
+        Ok(Self::Udp(socket))
+    }
+
+    fn send(&self, message: &[u8]) -> io::Result<usize> {
+        match self {
+            Self::Unix(socket) => socket.send(message),
+            Self::Udp(socket) => socket.send(message),
+        }
+    }
+}
+
+/// Field names and values of a span or event, in recording order.
+#[derive(Default)]
+struct Fields(Vec<(&'static str, String)>);
+
+impl Visit for Fields {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.0.push((field.name(), value.to_string()));
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.0.push((field.name(), format!("{:?}", value)));
+    }
+}
+
+/// Sends every event as an RFC 5424 message, with the fields of its spans (`run_id`, `album`,
+/// ...) as structured data.
+pub struct SyslogLayer {
+    transport: Transport,
+    facility: u8,
+    hostname: String,
+}
+
+fn hostname() -> String {
+    let mut buffer = [0u8; 256];
+    let result =
+        unsafe { libc::gethostname(buffer.as_mut_ptr() as *mut libc::c_char, buffer.len()) };
+    if result != 0 {
+        return NIL.to_string();
+    }
+    let length = buffer.iter().position(|&b| b == 0).unwrap_or(buffer.len());
+    match String::from_utf8_lossy(&buffer[..length]).into_owned() {
+        hostname if hostname.is_empty() => NIL.to_string(),
+        hostname => hostname,
+    }
+}
+
+/// Syslog severity of `level`, which the journal uses as priority too.
+pub fn severity(level: &Level) -> u8 {
+    match *level {
+        Level::ERROR => 3,
+        Level::WARN => 4,
+        Level::INFO => 6,
+        Level::DEBUG | Level::TRACE => 7,
+    }
+}
+
+/// Escape a structured data parameter value.
+fn escape_param(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        if matches!(c, '"' | '\\' | ']') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// `<PRI>1 TIMESTAMP HOSTNAME APP-NAME PROCID MSGID [SD-ID name="value"...] MSG`
+fn format_message(
+    priority: u8,
+    timestamp: &str,
+    hostname: &str,
+    pid: u32,
+    fields: &[(&str, String)],
+    message: &str,
+) -> String {
+    let structured_data = if fields.is_empty() {
+        NIL.to_string()
+    } else {
+        let params: Vec<String> = fields
+            .iter()
+            .map(|(name, value)| format!("{}=\"{}\"", name, escape_param(&redact(value))))
+            .collect();
+        format!("[{} {}]", SD_ID, params.join(" "))
+    };
+    format!(
+        "<{}>1 {} {} {} {} {} {} {}",
+        priority,
+        timestamp,
+        hostname,
+        APP_NAME,
+        pid,
+        NIL,
+        structured_data,
+        redact(message)
+    )
+}
+
+impl SyslogLayer {
+    pub fn connect(settings: &SyslogSettings) -> Result<Self> {
+        let transport = Transport::connect(&settings.address)
+            .with_context(|| format!("Failed to connect to syslog at {}", settings.address))?;
+        Ok(Self {
+            transport,
+            facility: settings.facility as u8,
+            hostname: hostname(),
+        })
+    }
+}
+
+impl<S> Layer<S> for SyslogLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: layer::Context<'_, S>) {
+        let Some(span) = ctx.span(id) else {
+            return;
+        };
+        let mut fields = Fields::default();
+        attrs.record(&mut fields);
+        span.extensions_mut().insert(fields);
+    }
+
+    fn on_record(&self, id: &Id, values: &Record<'_>, ctx: layer::Context<'_, S>) {
+        let Some(span) = ctx.span(id) else {
+            return;
+        };
+        let mut extensions = span.extensions_mut();
+        if let Some(fields) = extensions.get_mut::<Fields>() {
+            values.record(fields);
+        }
+    }
+
+    fn on_event(&self, event: &Event<'_>, ctx: layer::Context<'_, S>) {
+        let mut fields = Vec::new();
+        if let Some(scope) = ctx.event_scope(event) {
+            for span in scope.from_root() {
+                if let Some(span_fields) = span.extensions().get::<Fields>() {
+                    fields.extend(span_fields.0.iter().cloned());
+                }
+            }
+        }
+
+        let mut event_fields = Fields::default();
+        event.record(&mut event_fields);
+        let mut message = String::new();
+        for (name, value) in event_fields.0 {
+            if name == "message" {
+                message = value;
+            } else {
+                fields.push((name, value));
+            }
+        }
+
+        let formatted = format_message(
+            self.facility * 8 + severity(event.metadata().level()),
+            &utc_rfc3339(SystemTime::now()),
+            &self.hostname,
+            std::process::id(),
+            &fields,
+            &message,
+        );
+        // Nothing sensible can be done about a lost message while logging
+        let _ = self.transport.send(formatted.as_bytes());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Facility;
+    use pretty_assertions::assert_eq;
+    use tempfile::TempDir;
+    use tracing_subscriber::layer::SubscriberExt;
+    use tracing_subscriber::Registry;
+
+    #[test]
+    fn test_format_message() {
+        let fields = vec![
+            ("run_id", "20261018T210503Z-4711".to_string()),
+            ("album", "Say \"cheese\" [2024]".to_string()),
+        ];
+
+        assert_eq!(
+            format_message(
+                14,
+                "2026-10-18T21:05:03.512Z",
+                "nas",
+                4711,
+                &fields,
+                "Uploading"
+            ),
+            "<14>1 2026-10-18T21:05:03.512Z nas immich-refresh 4711 - [fields@32473 run_id=\"20261018T210503Z-4711\" album=\"Say \\\"cheese\\\" [2024\\]\"] Uploading"
+        );
+        assert_eq!(
+            format_message(11, "2026-10-18T21:05:03.512Z", "nas", 4711, &[], "Failed"),
+            "<11>1 2026-10-18T21:05:03.512Z nas immich-refresh 4711 - - Failed"
+        );
+    }
+
+    #[test]
+    fn test_sends_events_with_span_fields() {
+        let temp_dir = TempDir::new().unwrap();
+        let socket_path = temp_dir.path().join("log");
+        let receiver = UnixDatagram::bind(&socket_path).unwrap();
+        let layer = SyslogLayer::connect(&SyslogSettings {
+            address: socket_path.to_string_lossy().into(),
+            facility: Facility::Local3,
+        })
+        .unwrap();
+        let subscriber = Registry::default().with(layer);
+
+        tracing::subscriber::with_default(subscriber, || {
+            let _album = info_span!("album", album = "Summer").entered();
+            error!(attempt = 2, "Upload failed");
+        });
+
+        let mut buffer = [0u8; 1024];
+        let length = receiver.recv(&mut buffer).unwrap();
+        let message = String::from_utf8_lossy(&buffer[..length]);
+        // local3 (19) * 8 + error (3)
+        assert!(message.starts_with("<155>1 "), "{message}");
+        let expected_end = format!(
+            " immich-refresh {} - [fields@32473 album=\"Summer\" attempt=\"2\"] Upload failed",
+            std::process::id()
+        );
+        assert!(message.ends_with(&expected_end), "{message}");
+    }
+
+    #[test]
+    fn test_connect_failure() {
+        let settings = SyslogSettings {
+            address: "/nonexistent/log".into(),
+            ..SyslogSettings::default()
+        };
+        assert!(SyslogLayer::connect(&settings).is_err());
+    }
+}
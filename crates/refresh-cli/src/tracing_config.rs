@@ -1,86 +1,416 @@
+use crate::config::{self, LogFormat, LogSettings};
+use crate::history;
+use crate::journald::{JournaldLayer, JOURNAL_SOCKET};
+use crate::log_rotation::{self, RotatingFile};
 use crate::prelude::*;
+use crate::progress;
+use crate::redact::Redacting;
+use crate::syslog::SyslogLayer;
 use std::path::PathBuf;
-use tracing_subscriber::{fmt, layer::SubscriberExt, util::SubscriberInitExt, EnvFilter};
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::time::SystemTime;
+use tracing_subscriber::filter::LevelFilter;
+use tracing_subscriber::fmt::MakeWriter;
+use tracing_subscriber::{fmt, layer::SubscriberExt, util::SubscriberInitExt, EnvFilter, Layer};
 
-fn get_log_file_path() -> Result<PathBuf> {
-    let home_dir = env::var("HOME").context("Failed to get HOME environment variable")?;
+type BoxedLayer<S> = Box<dyn Layer<S> + Send + Sync>;
 
-    let log_dir = Path::new(&home_dir)
-        .join(".local")
-        .join("state")
-        .join("immich-refresh");
+const LOG_FILE_NAME: &str = "run.log";
+/// Target of this crate's events, which `log.modules` keys are relative to.
+const CRATE_TARGET: &str = "immich_refresh";
 
-    let log_file_path = log_dir.join("run.log");
+/// Where the log file goes besides stdout.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Destination {
+    /// `run.log` in the state directory.
+    StateDir,
+    File(PathBuf),
+    StdoutOnly,
+}
+
+/// Decide the log destination. `--no-log-file` wins; dry runs only write a log file that was
+/// requested on the command line or with `log.file_in_dry_run`.
+pub fn destination(
+    dry_run: bool,
+    log_file: Option<&str>,
+    no_log_file: bool,
+    settings: &LogSettings,
+) -> Destination {
+    if no_log_file || (dry_run && log_file.is_none() && !settings.file_in_dry_run) {
+        return Destination::StdoutOnly;
+    }
+    match log_file.or(settings.file.as_deref()) {
+        Some(path) => Destination::File(PathBuf::from(path)),
+        None => Destination::StateDir,
+    }
+}
+
+/// Identifies the log lines of one run: its UTC start time and process ID,
+/// e.g. `20261018T210503Z-4711`.
+pub fn run_id() -> String {
+    format!(
+        "{}-{}",
+        log_rotation::utc_timestamp(SystemTime::now()),
+        std::process::id()
+    )
+}
 
-    Ok(log_file_path)
+fn parse_level(level: &str, setting: &str) -> Result<LevelFilter> {
+    LevelFilter::from_str(level).map_err(|_| {
+        anyhow::anyhow!(
+            "Invalid {} '{}': expected off, error, warn, info, debug or trace",
+            setting,
+            level
+        )
+    })
+}
+
+/// The `EnvFilter` directives for a run. `-v` and `-vv` raise the level to debug and trace;
+/// otherwise `RUST_LOG` replaces the configured levels entirely. Modules are given relative to
+/// the crate, e.g. `execute`, or as a full target such as `hyper::client`.
+pub fn filter_directives(
+    settings: &LogSettings,
+    verbosity: i8,
+    rust_log: Option<String>,
+) -> Result<String> {
+    let level = match verbosity {
+        ..=0 => match rust_log {
+            Some(rust_log) if !rust_log.is_empty() => return Ok(rust_log),
+            _ => parse_level(settings.level.as_deref().unwrap_or("info"), "log.level")?,
+        },
+        1 => LevelFilter::DEBUG,
+        _ => LevelFilter::TRACE,
+    };
+
+    let mut directives = vec![level.to_string().to_lowercase()];
+    for (module, module_level) in &settings.modules {
+        let module_level = parse_level(module_level, &format!("level for log.modules.{}", module))?;
+        let target = if module.contains("::") {
+            module.clone()
+        } else {
+            format!("{}::{}", CRATE_TARGET, module)
+        };
+        directives.push(format!(
+            "{}={}",
+            target,
+            module_level.to_string().to_lowercase()
+        ));
+    }
+    Ok(directives.join(","))
+}
+
+/// A formatting layer writing to `writer`. JSON events carry the fields of all their spans, so
+/// every line can be attributed to its run, album and attempt.
+fn format_layer<S, W>(writer: W, format: LogFormat, ansi: bool) -> BoxedLayer<S>
+where
+    S: tracing::Subscriber + for<'a> tracing_subscriber::registry::LookupSpan<'a>,
+    W: for<'a> MakeWriter<'a> + Send + Sync + 'static,
+{
+    let layer = fmt::layer()
+        .with_writer(Redacting(writer))
+        .with_ansi(ansi)
+        .with_target(true);
+    match format {
+        LogFormat::Text => layer.compact().boxed(),
+        LogFormat::Json => layer
+            .json()
+            .flatten_event(true)
+            .with_current_span(false)
+            .with_span_list(true)
+            .boxed(),
+    }
 }
 
-pub fn configure(dry_run: bool) -> Result<()> {
-    let stdout_layer = fmt::layer()
-        .with_writer(std::io::stdout)
-        .with_target(true)
-        .compact();
-
-    let env_filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
-
-    if dry_run {
-        tracing_subscriber::registry()
-            .with(env_filter)
-            .with(stdout_layer)
-            .init();
-
-        info!("[DRY RUN] Logging to stdout only (file logging disabled)");
-    } else {
-        let log_file_path = get_log_file_path()?;
-        let log_dir = log_file_path
-            .parent()
-            .ok_or_else(|| anyhow::anyhow!("Invalid log file path"))?;
-
-        // Create the log directory if it doesn't exist
-        fs::create_dir_all(log_dir)
-            .with_context(|| format!("Failed to create log directory at {}", log_dir.display()))?;
-
-        // Test if we can write to the log file location before initializing the appender
-        std::fs::OpenOptions::new()
-            .create(true)
-            .append(true)
-            .open(&log_file_path)
-            .with_context(|| {
+/// The file of this run only, `runs/<run_id>.log` in the state directory, written next to the
+/// shared log file.
+fn open_run_log(run_id: &str) -> Result<Option<PathBuf>> {
+    let Ok(runs_dir) = history::runs_dir() else {
+        return Ok(None);
+    };
+    fs::create_dir_all(&runs_dir)
+        .with_context(|| format!("Failed to create run directory at {}", runs_dir.display()))?;
+    Ok(Some(history::run_log_path(&runs_dir, run_id)))
+}
+
+/// Set up logging to stdout, `destination` and the configured system logs at the levels from
+/// [`filter_directives`]. A negative `verbosity` (`-q`) only shows warnings and errors on stdout;
+/// the log files and system logs still get everything. Nothing is logged to stdout without
+/// `log_to_stdout`, e.g. while the dashboard has the terminal. Returns the log file of this run, which is
+/// only written along with the shared log file.
+pub fn configure(
+    dry_run: bool,
+    destination: Destination,
+    settings: &LogSettings,
+    verbosity: i8,
+    log_to_stdout: bool,
+    run_id: &str,
+) -> Result<Option<PathBuf>> {
+    let mut layers = Vec::new();
+    if log_to_stdout {
+        let stdout = format_layer(progress::Stdout, settings.format, true);
+        layers.push(if verbosity < 0 {
+            stdout.with_filter(LevelFilter::WARN).boxed()
+        } else {
+            stdout
+        });
+    }
+
+    let directives = filter_directives(settings, verbosity, env::var(EnvFilter::DEFAULT_ENV).ok())?;
+    let env_filter = EnvFilter::try_new(&directives)
+        .with_context(|| format!("Invalid log filter '{}'", directives))?;
+
+    // Without a state directory the run still goes ahead, logging to stdout only
+    let mut no_state_dir = None;
+    let log_file_path = match destination {
+        Destination::StateDir => match config::state_dir() {
+            Ok(state_dir) => Some(state_dir.join(LOG_FILE_NAME)),
+            Err(e) => {
+                no_state_dir = Some(e);
+                None
+            }
+        },
+        Destination::File(path) => Some(path),
+        Destination::StdoutOnly => None,
+    };
+
+    let mut run_log_path = None;
+    if let Some(log_file_path) = &log_file_path {
+        if let Some(log_dir) = log_file_path.parent() {
+            // Create the log directory if it doesn't exist
+            fs::create_dir_all(log_dir).with_context(|| {
+                format!("Failed to create log directory at {}", log_dir.display())
+            })?;
+        }
+
+        let log_file = RotatingFile::open(log_file_path, settings.clone()).with_context(|| {
+            format!(
+                "Failed to initialize log file at {}. Check permissions.",
+                log_file_path.display()
+            )
+        })?;
+        layers.push(format_layer(log_file, settings.format, false));
+
+        run_log_path = open_run_log(run_id)?;
+        if let Some(run_log_path) = &run_log_path {
+            let run_log = fs::File::create(run_log_path).with_context(|| {
                 format!(
-                    "Failed to initialize log file at {}. Check permissions.",
-                    log_file_path.display()
+                    "Failed to create run log file at {}",
+                    run_log_path.display()
                 )
             })?;
+            layers.push(format_layer(Mutex::new(run_log), settings.format, false));
+        }
+    }
 
-        let file_appender = tracing_appender::rolling::never(log_dir, "run.log");
-        let file_layer = fmt::layer()
-            .with_writer(file_appender)
-            .with_ansi(false)
-            .with_target(true)
-            .compact();
+    if settings.journald {
+        layers.push(JournaldLayer::connect(JOURNAL_SOCKET)?.boxed());
+    }
+    if let Some(syslog) = &settings.syslog {
+        layers.push(SyslogLayer::connect(syslog)?.boxed());
+    }
 
-        tracing_subscriber::registry()
-            .with(env_filter)
-            .with(stdout_layer)
-            .with(file_layer)
-            .init();
+    tracing_subscriber::registry()
+        .with(layers)
+        .with(env_filter)
+        .init();
 
-        info!("Logging to stdout and {}", log_file_path.display());
+    let dry_run_prefix = if dry_run { "[DRY RUN] " } else { "" };
+    let mut targets: Vec<String> = log_file_path
+        .iter()
+        .chain(&run_log_path)
+        .map(|path| path.display().to_string())
+        .collect();
+    if log_to_stdout {
+        targets.insert(0, "stdout".to_string());
+    }
+    match (targets.as_slice(), no_state_dir) {
+        ([], _) => {}
+        ([_], Some(e)) if log_to_stdout => info!(
+            "{}Logging to stdout only. {:#}; use --log-file to log to a file",
+            dry_run_prefix, e
+        ),
+        ([_], None) if log_to_stdout => info!(
+            "{}Logging to stdout only (file logging disabled)",
+            dry_run_prefix
+        ),
+        ([target], _) => info!("{}Logging to {}", dry_run_prefix, target),
+        ([first @ .., last], _) => info!(
+            "{}Logging to {} and {}",
+            dry_run_prefix,
+            first.join(", "),
+            last
+        ),
+    }
+    if settings.journald {
+        info!("Logging to journald");
+    }
+    if let Some(syslog) = &settings.syslog {
+        info!("Logging to syslog at {}", syslog.address);
     }
 
-    Ok(())
+    Ok(run_log_path)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use pretty_assertions::assert_eq;
+    use rstest::rstest;
+    use std::io;
+    use std::sync::{Arc, Mutex};
+    use tracing_subscriber::Registry;
 
     #[test]
     fn test_configure_dry_run() {
         // This test verifies that configure doesn't panic in dry-run mode
         // We can't easily test the actual tracing output without complex setup
-        let result = configure(true);
+        let result = configure(
+            true,
+            Destination::StdoutOnly,
+            &LogSettings::default(),
+            0,
+            true,
+            "run",
+        );
         // The function should succeed even if we can't write to /var/log
         assert!(result.is_ok());
     }
+
+    #[rstest]
+    #[case(false, None, false, false, Destination::StateDir)]
+    #[case(false, Some("/tmp/run.log"), false, false, Destination::File("/tmp/run.log".into()))]
+    #[case(false, None, true, false, Destination::StdoutOnly)]
+    #[case(false, Some("/tmp/run.log"), true, false, Destination::StdoutOnly)]
+    #[case(true, None, false, false, Destination::StdoutOnly)]
+    #[case(true, None, false, true, Destination::StateDir)]
+    #[case(true, Some("/tmp/run.log"), false, false, Destination::File("/tmp/run.log".into()))]
+    fn test_destination(
+        #[case] dry_run: bool,
+        #[case] log_file: Option<&str>,
+        #[case] no_log_file: bool,
+        #[case] file_in_dry_run: bool,
+        #[case] expected: Destination,
+    ) {
+        let settings = LogSettings {
+            file_in_dry_run,
+            ..LogSettings::default()
+        };
+        assert_eq!(
+            destination(dry_run, log_file, no_log_file, &settings),
+            expected
+        );
+    }
+
+    #[test]
+    fn test_destination_from_config() {
+        let settings = LogSettings {
+            file: Some("/var/log/immich-refresh.log".into()),
+            ..LogSettings::default()
+        };
+        assert_eq!(
+            destination(false, None, false, &settings),
+            Destination::File("/var/log/immich-refresh.log".into())
+        );
+        assert_eq!(
+            destination(false, Some("/tmp/run.log"), false, &settings),
+            Destination::File("/tmp/run.log".into())
+        );
+    }
+
+    fn level_settings(level: Option<&str>, modules: &[(&str, &str)]) -> LogSettings {
+        LogSettings {
+            level: level.map(Into::into),
+            modules: modules
+                .iter()
+                .map(|(module, level)| (module.to_string(), (*level).into()))
+                .collect(),
+            ..LogSettings::default()
+        }
+    }
+
+    #[rstest]
+    #[case(None, &[], 0, None, "info")]
+    #[case(Some("warn"), &[], 0, None, "warn")]
+    #[case(Some("WARN"), &[("execute", "debug")], 0, None, "warn,immich_refresh::execute=debug")]
+    #[case(None, &[("hyper::client", "error")], 0, None, "info,hyper::client=error")]
+    #[case(Some("warn"), &[("execute", "debug")], 0, Some("trace"), "trace")]
+    #[case(Some("warn"), &[], 0, Some(""), "warn")]
+    #[case(Some("warn"), &[("execute", "trace")], 1, Some("error"), "debug,immich_refresh::execute=trace")]
+    #[case(None, &[], 2, None, "trace")]
+    #[case(Some("warn"), &[], -1, None, "warn")]
+    fn test_filter_directives(
+        #[case] level: Option<&str>,
+        #[case] modules: &[(&str, &str)],
+        #[case] verbosity: i8,
+        #[case] rust_log: Option<&str>,
+        #[case] expected: &str,
+    ) {
+        assert_eq!(
+            filter_directives(
+                &level_settings(level, modules),
+                verbosity,
+                rust_log.map(Into::into)
+            )
+            .unwrap(),
+            expected
+        );
+    }
+
+    #[test]
+    fn test_filter_directives_invalid_level() {
+        let error = filter_directives(&level_settings(Some("loud"), &[]), 0, None).unwrap_err();
+        assert!(error.to_string().contains("log.level 'loud'"), "{error}");
+        assert!(
+            filter_directives(&level_settings(None, &[("execute", "chatty")]), 0, None).is_err()
+        );
+    }
+
+    #[derive(Clone, Default)]
+    struct Captured(Arc<Mutex<Vec<u8>>>);
+
+    impl io::Write for Captured {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl<'a> MakeWriter<'a> for Captured {
+        type Writer = Captured;
+
+        fn make_writer(&'a self) -> Self::Writer {
+            self.clone()
+        }
+    }
+
+    #[test]
+    fn test_json_events_carry_span_fields() {
+        let output = Captured::default();
+        let subscriber =
+            Registry::default().with(format_layer(output.clone(), LogFormat::Json, false));
+
+        tracing::subscriber::with_default(subscriber, || {
+            let _run = info_span!("run", run_id = "20261018T210503Z-4711").entered();
+            let _album =
+                info_span!("album", album = "Summer", path = "/photos/2024/summer").entered();
+            let _attempt = info_span!("attempt", attempt = 2).entered();
+            info!("stdout: Uploading 3 assets");
+        });
+
+        let output = String::from_utf8(output.0.lock().unwrap().clone()).unwrap();
+        let event: serde_json::Value = serde_json::from_str(output.trim()).unwrap();
+        assert_eq!(event["message"], "stdout: Uploading 3 assets");
+        assert_eq!(
+            event["spans"],
+            serde_json::json!([
+                {"name": "run", "run_id": "20261018T210503Z-4711"},
+                {"name": "album", "album": "Summer", "path": "/photos/2024/summer"},
+                {"name": "attempt", "attempt": 2},
+            ])
+        );
+    }
 }
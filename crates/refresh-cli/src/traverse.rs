@@ -1,33 +1,115 @@
-use crate::execute::{Execute, ExecuteArgs, ExecuteError, Executer};
+use crate::cancel::CancellationToken;
+use crate::events::{self, Event, SkipReason};
+use crate::execute::{Execute, ExecuteArgs, ExecuteError, UploadError};
 use crate::prelude::*;
+use crate::progress::{self, Progress};
+use crate::report::{Outcome, RunReport};
+use crate::users::Accounts;
 use crate::Arguments;
+use std::cell::{Cell, RefCell};
+use std::path::PathBuf;
+use std::sync::mpsc;
+use std::time::Duration;
+
+/// How often an album is started again after a failure that may be transient.
+const MAX_RETRIES: usize = 1;
+/// Time the server gets to come back before an album is retried after a network or server error.
+const SERVER_RETRY_DELAY: Duration = Duration::from_secs(30);
+
+/// What `execute_album` does after a failed upload.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Policy {
+    /// Stop the run: every remaining album would fail the same way.
+    Abort,
+    /// Start the album again, the problem may be transient.
+    Retry,
+    /// Record the failure and continue with the next album.
+    Skip,
+}
 
-pub fn traverse(arguments: &Arguments, executor: &Executer) -> Result<()> {
-    let base_path = Path::new(arguments.path.as_ref());
-
-    if !base_path.exists() {
-        bail!("Path does not exist: {}", arguments.path);
+fn policy(error: &ExecuteError) -> Policy {
+    match error {
+        ExecuteError::AuthFailed(_)
+        | ExecuteError::ImmichCliNotFound(_)
+        | ExecuteError::Tls(_)
+        | ExecuteError::ServerDiskFull(_) => Policy::Abort,
+        ExecuteError::TimedOut(_)
+        | ExecuteError::ServerUnreachable(_)
+        | ExecuteError::ServerError(_)
+        | ExecuteError::PartialSuccess(_) => Policy::Retry,
+        ExecuteError::UnsupportedFile(_) | ExecuteError::Cancelled | ExecuteError::Other(_) => {
+            Policy::Skip
+        }
     }
+}
 
-    if !base_path.is_dir() {
-        bail!("Path is not a directory: {}", arguments.path);
+fn retry_delay(error: &ExecuteError) -> Duration {
+    match error {
+        ExecuteError::ServerUnreachable(_) | ExecuteError::ServerError(_) => SERVER_RETRY_DELAY,
+        _ => Duration::ZERO,
     }
+}
 
-    info!("Traversing directory: {}", arguments.path);
+/// Sleep for `delay` unless the run is cancelled first. Returns whether the delay passed.
+fn wait_unless_cancelled(delay: Duration, cancel: &CancellationToken) -> bool {
+    if delay.is_zero() {
+        return !cancel.is_cancelled();
+    }
+    let (cancel_tx, cancel_rx) = mpsc::sync_channel(1);
+    let _cancel_guard = cancel.on_cancel(move || {
+        let _ = cancel_tx.try_send(());
+    });
+    cancel_rx.recv_timeout(delay).is_err() && !cancel.is_cancelled()
+}
 
-    // Iterate over child directories
-    let child_entries = fs::read_dir(base_path)
-        .with_context(|| format!("Failed to read directory: {}", arguments.path))?;
+/// A grandchild directory together with the album its contents belong to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AlbumPlan {
+    pub path: PathBuf,
+    pub album_name: Box<str>,
+}
 
-    for child_entry in child_entries {
-        let child_entry =
-            child_entry.with_context(|| format!("Failed to read entry in {}", arguments.path))?;
-        let child_path = child_entry.path();
+/// Determine the album name for a grandchild directory: use the grandchild name unless it's
+/// "other" (case-insensitive), in which case the child name is used.
+fn album_name_for(child_name: &str, grandchild_name: &str) -> Box<str> {
+    if grandchild_name.eq_ignore_ascii_case("other") {
+        child_name.into()
+    } else {
+        grandchild_name.into()
+    }
+}
 
-        if !child_path.is_dir() {
-            continue;
+/// Sorted list of the directories directly below `dir`.
+fn sub_directories(dir: &Path) -> Result<Vec<PathBuf>> {
+    let entries = fs::read_dir(dir)
+        .with_context(|| format!("Failed to read directory: {}", dir.display()))?;
+
+    let mut directories = Vec::new();
+    for entry in entries {
+        let entry = entry.with_context(|| format!("Failed to read entry in {}", dir.display()))?;
+        let path = entry.path();
+        if path.is_dir() {
+            directories.push(path);
         }
+    }
+    directories.sort();
+
+    Ok(directories)
+}
+
+/// Map every grandchild directory below `base_path` to the album it should end up in.
+pub fn plan_albums(base_path: &Path) -> Result<Vec<AlbumPlan>> {
+    if !base_path.exists() {
+        bail!("Path does not exist: {}", base_path.display());
+    }
 
+    if !base_path.is_dir() {
+        bail!("Path is not a directory: {}", base_path.display());
+    }
+
+    let mut plans = Vec::new();
+
+    for child_path in sub_directories(base_path)? {
         let child_name = child_path
             .file_name()
             .and_then(|n| n.to_str())
@@ -35,22 +117,7 @@ pub fn traverse(arguments: &Arguments, executor: &Executer) -> Result<()> {
 
         debug!("Found child directory: {}", child_name);
 
-        // Iterate over grandchild directories
-        let grandchild_entries = fs::read_dir(&child_path)
-            .with_context(|| format!("Failed to read child directory: {}", child_path.display()))?;
-
-        for grandchild_entry in grandchild_entries {
-            // Check for signals before processing each directory
-            executor.check_signal();
-
-            let grandchild_entry = grandchild_entry
-                .with_context(|| format!("Failed to read entry in {}", child_path.display()))?;
-            let grandchild_path = grandchild_entry.path();
-
-            if !grandchild_path.is_dir() {
-                continue;
-            }
-
+        for grandchild_path in sub_directories(&child_path)? {
             let grandchild_name = grandchild_path
                 .file_name()
                 .and_then(|n| n.to_str())
@@ -58,51 +125,336 @@ pub fn traverse(arguments: &Arguments, executor: &Executer) -> Result<()> {
 
             debug!("Found grandchild directory: {}", grandchild_name);
 
-            // Determine album name: use grandchild name unless it's "other" (case-insensitive)
-            let album_name = if grandchild_name.eq_ignore_ascii_case("other") {
-                child_name.to_string()
-            } else {
-                grandchild_name.to_string()
-            };
+            let album_name = album_name_for(child_name, grandchild_name);
+            plans.push(AlbumPlan {
+                path: grandchild_path,
+                album_name,
+            });
+        }
+    }
 
-            let execute_args = ExecuteArgs {
-                path: grandchild_path
-                    .to_string_lossy()
-                    .into_owned()
-                    .into_boxed_str(),
-                album_name: album_name.into_boxed_str(),
-                dry_run: arguments.dry_run,
-            };
+    Ok(plans)
+}
 
-            if let Err(e) = executor.execute(&execute_args) {
-                match e {
-                    ExecuteError::AuthFailed(ref msg) => {
-                        error!("Authentication failed: {}", msg);
-                        bail!("Aborting due to authentication failure");
-                    }
-                    ExecuteError::ImmichCliNotFound(ref path) => {
-                        error!("Immich CLI not found at {}", path);
-                        bail!("Aborting because Immich CLI is not installed");
-                    }
-                    ExecuteError::Other(ref err) => {
-                        error!(
-                            "Failed to execute for directory {}: {}",
-                            grandchild_name, err
-                        );
-                        // Continue processing other directories
-                    }
+/// Run the executor for a single album and record the outcome in `report`. Failures are
+/// handled according to their `Policy`: the run is aborted on errors that would affect every
+/// remaining album, and transient errors are retried. `source` identifies the album source in
+/// messages, `user` the account it is uploaded for.
+pub fn execute_album(
+    executor: &impl Execute,
+    execute_args: &ExecuteArgs,
+    source: &str,
+    user: Option<&str>,
+    cancel: &CancellationToken,
+    report: &mut RunReport,
+) -> Result<()> {
+    // Restore batches upload many files at once and are identified by their description
+    let path = match execute_args.paths.as_slice() {
+        [path] => path.as_ref(),
+        _ => source,
+    };
+    let _album =
+        info_span!("album", album = %execute_args.album_name, path = %path, user).entered();
+    let album = execute_args.album_name.as_ref();
+    let run_attempt = |attempt: usize| {
+        let _attempt = info_span!("attempt", attempt).entered();
+        events::emit(Event::UploadStarted {
+            source,
+            album,
+            user,
+            attempt,
+        });
+        match executor.execute(execute_args, cancel) {
+            Ok(stats) => (Ok(()), stats),
+            Err(UploadError { error, stats }) => (Err(error), stats),
+        }
+    };
+
+    // Files uploaded by a failed attempt count as well
+    let mut attempts = 1;
+    let (mut result, mut stats) = run_attempt(attempts);
+    for attempt in 1..=MAX_RETRIES {
+        let Err(e) = &result else {
+            break;
+        };
+        if policy(e) != Policy::Retry {
+            break;
+        }
+        let delay = retry_delay(e);
+        info!(
+            "{} failed ({}), retrying in {}s (retry {} of {})",
+            source,
+            e,
+            delay.as_secs(),
+            attempt,
+            MAX_RETRIES
+        );
+        events::emit(Event::Retry {
+            source,
+            album,
+            user,
+            attempt: attempt + 1,
+            delay_secs: delay.as_secs(),
+            error: e.to_string(),
+        });
+        if !wait_unless_cancelled(delay, cancel) {
+            break;
+        }
+        attempts = attempt + 1;
+        let retry_stats;
+        (result, retry_stats) = run_attempt(attempts);
+        stats = stats.with_retry(&retry_stats);
+    }
+
+    let outcome = match &result {
+        Ok(()) => Outcome::Completed,
+        Err(ExecuteError::Cancelled) => Outcome::Interrupted,
+        Err(ExecuteError::TimedOut(_)) => Outcome::TimedOut,
+        Err(_) => Outcome::Failed,
+    };
+    report.record(source, album, user, outcome, stats);
+    events::emit(Event::UploadFinished {
+        source,
+        album,
+        user,
+        attempt: attempts,
+        outcome,
+        stats,
+        error: result.as_ref().err().map(ToString::to_string),
+    });
+
+    match result {
+        Ok(()) => {}
+        Err(ExecuteError::Cancelled) => info!("Interrupted {}", source),
+        Err(e) if policy(&e) == Policy::Abort => {
+            error!("{}", e);
+            bail!("Aborting: {}", e);
+        }
+        // Continue processing other directories
+        Err(e) => error!("Failed to execute for {}: {}", source, e),
+    }
+
+    Ok(())
+}
+
+/// One upload of a run: an album directory, or a batch of files of a restored album.
+pub struct Job<'a, E> {
+    /// Where the job appears in the dashboard's plan, e.g. its child directory.
+    pub group: Box<str>,
+    pub name: Box<str>,
+    /// Identifies the job in messages and the report.
+    pub source: String,
+    /// The user of the config file the job uploads for.
+    pub user: Option<&'a str>,
+    pub executor: &'a E,
+    pub execute_args: ExecuteArgs,
+    pub size: progress::Size,
+}
+
+/// Decides which job runs next and follows the jobs as they run.
+pub trait Schedule {
+    /// The job to run next, or `None` to end the run.
+    fn next(&self, cancel: &CancellationToken) -> Option<usize>;
+    /// `cancel` stops only this job.
+    fn started(&self, index: usize, cancel: &CancellationToken);
+    fn finished(&self, index: usize, outcome: Outcome);
+    /// Called once the last job finished, before the summary is logged.
+    fn end(&self) {}
+}
+
+/// Every job once, in order, until the run is cancelled, with progress shown as it goes.
+pub struct Sequential {
+    next: Cell<usize>,
+    album_names: Vec<Box<str>>,
+    sizes: Vec<progress::Size>,
+    progress: RefCell<Option<Progress>>,
+}
+
+impl Sequential {
+    pub fn new<E>(jobs: &[Job<'_, E>], display: progress::Display) -> Self {
+        let sizes: Vec<progress::Size> = jobs.iter().map(|job| job.size).collect();
+        Self {
+            next: Cell::new(0),
+            album_names: jobs
+                .iter()
+                .map(|job| job.execute_args.album_name.clone())
+                .collect(),
+            progress: RefCell::new(Some(Progress::start(&sizes, display))),
+            sizes,
+        }
+    }
+}
+
+impl Schedule for Sequential {
+    fn next(&self, cancel: &CancellationToken) -> Option<usize> {
+        let index = self.next.get();
+        // Stop scheduling new directories once cancelled
+        if cancel.is_cancelled() || index >= self.sizes.len() {
+            return None;
+        }
+        self.next.set(index + 1);
+        Some(index)
+    }
+
+    fn started(&self, index: usize, _cancel: &CancellationToken) {
+        if let Some(progress) = &*self.progress.borrow() {
+            progress.album_started(&self.album_names[index]);
+        }
+    }
+
+    fn finished(&self, index: usize, outcome: Outcome) {
+        if let Some(progress) = &*self.progress.borrow() {
+            progress.album_finished(self.sizes[index], outcome == Outcome::Completed);
+        }
+    }
+
+    fn end(&self) {
+        self.progress.take();
+    }
+}
+
+/// Run the jobs `schedule` picks and report on them. A job that runs again replaces its earlier
+/// entry in the report. Jobs that never ran count as not started.
+pub fn run_jobs<E: Execute>(
+    jobs: &[Job<'_, E>],
+    schedule: &impl Schedule,
+    cancel: &CancellationToken,
+) -> Result<RunReport> {
+    let mut report = RunReport::default();
+    // Position of every job's entry in the report
+    let mut entries: Vec<Option<usize>> = vec![None; jobs.len()];
+    let mut result = Ok(());
+
+    for job in jobs {
+        events::emit(Event::DirDiscovered {
+            source: &job.source,
+            album: &job.execute_args.album_name,
+            user: job.user,
+            files: job.size.files,
+            bytes: job.size.bytes,
+        });
+    }
+
+    while let Some(index) = schedule.next(cancel) {
+        let job = &jobs[index];
+        if let Some(previous) = entries[index].take() {
+            report.directories.remove(previous);
+            for entry in entries.iter_mut().flatten() {
+                if *entry > previous {
+                    *entry -= 1;
                 }
             }
         }
+
+        let (job_cancel, _cancel_guard) = cancel.child();
+        schedule.started(index, &job_cancel);
+        result = execute_album(
+            job.executor,
+            &job.execute_args,
+            &job.source,
+            job.user,
+            &job_cancel,
+            &mut report,
+        );
+        entries[index] = Some(report.directories.len() - 1);
+        let outcome = report.directories[report.directories.len() - 1].outcome;
+        schedule.finished(index, outcome);
+        if result.is_err() {
+            break;
+        }
     }
 
-    Ok(())
+    schedule.end();
+    for (job, _) in jobs
+        .iter()
+        .zip(&entries)
+        .filter(|(_, entry)| entry.is_none())
+    {
+        events::emit(Event::DirSkipped {
+            source: &job.source,
+            album: &job.execute_args.album_name,
+            user: job.user,
+            reason: SkipReason::NotStarted,
+        });
+    }
+    report.not_started = entries.iter().filter(|entry| entry.is_none()).count();
+    report.log_summary();
+    result.map(|()| report)
+}
+
+/// A job for every album below the base path, routed to its user and measured for progress.
+pub fn album_jobs<'a, E>(
+    arguments: &Arguments,
+    accounts: &'a Accounts<E>,
+    scan: progress::ScanOptions,
+) -> Result<Vec<Job<'a, E>>> {
+    info!("Traversing directory: {}", arguments.path);
+
+    let base_path = Path::new(arguments.path.as_ref());
+    let plans = plan_albums(base_path)?;
+    // Route every album before the first upload so a directory claimed by two users stops the
+    // run right away
+    let routes = plans
+        .iter()
+        .map(|plan| accounts.for_album(base_path, &plan.path))
+        .collect::<Result<Vec<_>>>()?;
+
+    let jobs = plans
+        .into_iter()
+        .zip(routes)
+        .map(|(plan, (user, executor))| {
+            let source = match user {
+                Some(user) => format!("{} (user {})", plan.path.display(), user),
+                None => plan.path.display().to_string(),
+            };
+            let relative = plan.path.strip_prefix(base_path).unwrap_or(&plan.path);
+            let mut components = relative.iter().map(|c| c.to_string_lossy());
+            let group = components.next().unwrap_or_default();
+            let mut name = components.next().unwrap_or_default().into_owned();
+            if name != plan.album_name.as_ref() {
+                name = format!("{} -> {}", name, plan.album_name);
+            }
+            if let Some(user) = user {
+                name = format!("{} (user {})", name, user);
+            }
+            Job {
+                group: group.into(),
+                name: name.into(),
+                source,
+                user,
+                executor,
+                execute_args: ExecuteArgs {
+                    paths: vec![plan.path.to_string_lossy().into_owned().into_boxed_str()],
+                    album_name: plan.album_name,
+                    dry_run: arguments.dry_run,
+                },
+                // Measure everything up front so progress knows what's left
+                size: progress::measure(&plan.path, scan),
+            }
+        })
+        .collect();
+    Ok(jobs)
+}
+
+/// Upload every album below the base path, showing progress through them as `progress` says.
+pub fn traverse(
+    arguments: &Arguments,
+    accounts: &Accounts<impl Execute>,
+    progress: &progress::Options,
+    cancel: &CancellationToken,
+) -> Result<RunReport> {
+    let jobs = album_jobs(arguments, accounts, progress.scan)?;
+    run_jobs(&jobs, &Sequential::new(&jobs, progress.display), cancel)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::execute::Executer;
+    use crate::execute::{Executer, MockExecute};
+    use crate::upload_stats::UploadStats;
+    use crate::users::UserRoute;
+    use pretty_assertions::assert_eq;
+    use rstest::rstest;
     use std::fs;
     use tempfile::TempDir;
 
@@ -122,27 +474,39 @@ mod tests {
         // Create a file (should be ignored)
         fs::write(base_path.join("file.txt"), "test").unwrap();
 
-        let executor = Executer::new();
+        let executor = Accounts::single(Executer::new());
 
         let arguments = Arguments {
             path: base_path.to_string_lossy().into_owned().into_boxed_str(),
             dry_run: true, // Use dry run to avoid executing actual commands
+            ..Default::default()
         };
 
-        let result = traverse(&arguments, &executor);
+        let result = traverse(
+            &arguments,
+            &executor,
+            &progress::Options::default(),
+            &CancellationToken::new(),
+        );
         assert!(result.is_ok());
     }
 
     #[test]
     fn test_traverse_nonexistent_path() {
-        let executor = Executer::new();
+        let executor = Accounts::single(Executer::new());
 
         let arguments = Arguments {
             path: "/nonexistent/path".into(),
             dry_run: true,
+            ..Default::default()
         };
 
-        let result = traverse(&arguments, &executor);
+        let result = traverse(
+            &arguments,
+            &executor,
+            &progress::Options::default(),
+            &CancellationToken::new(),
+        );
         assert!(result.is_err());
     }
 
@@ -152,14 +516,20 @@ mod tests {
         let file_path = temp_dir.path().join("file.txt");
         fs::write(&file_path, "test").unwrap();
 
-        let executor = Executer::new();
+        let executor = Accounts::single(Executer::new());
 
         let arguments = Arguments {
             path: file_path.to_string_lossy().into_owned().into_boxed_str(),
             dry_run: true,
+            ..Default::default()
         };
 
-        let result = traverse(&arguments, &executor);
+        let result = traverse(
+            &arguments,
+            &executor,
+            &progress::Options::default(),
+            &CancellationToken::new(),
+        );
         assert!(result.is_err());
     }
 
@@ -167,7 +537,7 @@ mod tests {
     fn test_traverse_empty_directory() {
         let temp_dir = TempDir::new().unwrap();
 
-        let executor = Executer::new();
+        let executor = Accounts::single(Executer::new());
 
         let arguments = Arguments {
             path: temp_dir
@@ -176,9 +546,15 @@ mod tests {
                 .into_owned()
                 .into_boxed_str(),
             dry_run: true,
+            ..Default::default()
         };
 
-        let result = traverse(&arguments, &executor);
+        let result = traverse(
+            &arguments,
+            &executor,
+            &progress::Options::default(),
+            &CancellationToken::new(),
+        );
         assert!(result.is_ok());
     }
 
@@ -193,14 +569,355 @@ mod tests {
         fs::create_dir(base_path.join("child1/Other")).unwrap();
         fs::create_dir(base_path.join("child1/grandchildA")).unwrap();
 
-        let executor = Executer::new();
+        let executor = Accounts::single(Executer::new());
 
         let arguments = Arguments {
             path: base_path.to_string_lossy().into_owned().into_boxed_str(),
             dry_run: true,
+            ..Default::default()
         };
 
-        let result = traverse(&arguments, &executor);
+        let result = traverse(
+            &arguments,
+            &executor,
+            &progress::Options::default(),
+            &CancellationToken::new(),
+        );
         assert!(result.is_ok());
     }
+
+    #[test]
+    fn test_plan_albums_maps_grandchildren_to_albums() {
+        let temp_dir = TempDir::new().unwrap();
+        let base_path = temp_dir.path();
+
+        fs::create_dir_all(base_path.join("2024/summer")).unwrap();
+        fs::create_dir_all(base_path.join("2024/other")).unwrap();
+        fs::create_dir_all(base_path.join("vacation/Other")).unwrap();
+        fs::write(base_path.join("2024/photo.jpg"), "test").unwrap();
+
+        let plans = plan_albums(base_path).unwrap();
+
+        assert_eq!(
+            plans,
+            vec![
+                AlbumPlan {
+                    path: base_path.join("2024/other"),
+                    album_name: "2024".into(),
+                },
+                AlbumPlan {
+                    path: base_path.join("2024/summer"),
+                    album_name: "summer".into(),
+                },
+                AlbumPlan {
+                    path: base_path.join("vacation/Other"),
+                    album_name: "vacation".into(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_traverse_cancelled_schedules_nothing() {
+        let temp_dir = TempDir::new().unwrap();
+        let base_path = temp_dir.path();
+        fs::create_dir_all(base_path.join("child1/grandchildA")).unwrap();
+        fs::create_dir_all(base_path.join("child1/grandchildB")).unwrap();
+
+        let executor = Accounts::single(Executer::new());
+        let cancel = CancellationToken::new();
+        cancel.cancel(libc::SIGTERM);
+
+        let arguments = Arguments {
+            path: base_path.to_string_lossy().into_owned().into_boxed_str(),
+            dry_run: true,
+            ..Default::default()
+        };
+
+        let report = traverse(
+            &arguments,
+            &executor,
+            &progress::Options::default(),
+            &cancel,
+        )
+        .unwrap();
+        assert_eq!(
+            report,
+            RunReport {
+                directories: vec![],
+                not_started: 2,
+            }
+        );
+    }
+
+    #[test]
+    fn test_traverse_records_completed_directories() {
+        let temp_dir = TempDir::new().unwrap();
+        let base_path = temp_dir.path();
+        fs::create_dir_all(base_path.join("child1/grandchildA")).unwrap();
+        fs::create_dir_all(base_path.join("child1/other")).unwrap();
+
+        let executor = Accounts::single(Executer::new());
+
+        let arguments = Arguments {
+            path: base_path.to_string_lossy().into_owned().into_boxed_str(),
+            dry_run: true,
+            ..Default::default()
+        };
+
+        let report = traverse(
+            &arguments,
+            &executor,
+            &progress::Options::default(),
+            &CancellationToken::new(),
+        )
+        .unwrap();
+        assert_eq!(report.count(Outcome::Completed), 2);
+        assert_eq!(report.directories[1].album_name.as_ref(), "child1");
+    }
+
+    #[test]
+    fn test_traverse_routes_albums_to_users() {
+        let temp_dir = TempDir::new().unwrap();
+        let base_path = temp_dir.path();
+        fs::create_dir_all(base_path.join("alice/summer")).unwrap();
+        fs::create_dir_all(base_path.join("alice/winter")).unwrap();
+        fs::create_dir_all(base_path.join("shared/family")).unwrap();
+
+        let executor = |calls: usize| {
+            let mut executor = MockExecute::new();
+            executor
+                .expect_execute()
+                .times(calls)
+                .returning(|_, _| Ok(UploadStats::default()));
+            executor
+        };
+        let accounts = Accounts {
+            default: executor(1),
+            users: vec![(
+                UserRoute {
+                    name: "alice".into(),
+                    patterns: vec!["alice".into()],
+                },
+                executor(2),
+            )],
+        };
+        let arguments = Arguments {
+            path: base_path.to_string_lossy().into_owned().into_boxed_str(),
+            ..Default::default()
+        };
+
+        let report = traverse(
+            &arguments,
+            &accounts,
+            &progress::Options::default(),
+            &CancellationToken::new(),
+        )
+        .unwrap();
+
+        let users: Vec<Option<&str>> = report
+            .directories
+            .iter()
+            .map(|directory| directory.user.as_deref())
+            .collect();
+        assert_eq!(users, vec![Some("alice"), Some("alice"), None]);
+    }
+
+    fn album_args() -> ExecuteArgs {
+        ExecuteArgs {
+            paths: vec!["/base/2024/summer".into()],
+            album_name: "summer".into(),
+            dry_run: false,
+        }
+    }
+
+    #[rstest]
+    #[case::retry_succeeds(vec![false], 2, Outcome::Completed)]
+    #[case::retry_times_out(vec![true], 2, Outcome::TimedOut)]
+    fn test_execute_album_retries_timeouts(
+        #[case] retry_times_out: Vec<bool>,
+        #[case] expected_calls: usize,
+        #[case] expected_outcome: Outcome,
+    ) {
+        let mut executor = MockExecute::new();
+        let mut results = vec![true];
+        results.extend(retry_times_out);
+        let mut results = results.into_iter();
+        executor
+            .expect_execute()
+            .times(expected_calls)
+            .returning(move |_, _| match results.next() {
+                Some(true) => {
+                    Err(ExecuteError::TimedOut("produced no output for 5m".into()).into())
+                }
+                _ => Ok(UploadStats {
+                    new: 3,
+                    uploaded: 3,
+                    ..Default::default()
+                }),
+            });
+
+        let mut report = RunReport::default();
+        execute_album(
+            &executor,
+            &album_args(),
+            "/base/2024/summer",
+            None,
+            &CancellationToken::new(),
+            &mut report,
+        )
+        .unwrap();
+
+        assert_eq!(report.count(expected_outcome), 1);
+        assert_eq!(report.directories.len(), 1);
+        let expected_uploaded = if expected_outcome == Outcome::Completed {
+            3
+        } else {
+            0
+        };
+        assert_eq!(report.directories[0].stats.uploaded, expected_uploaded);
+    }
+
+    #[rstest]
+    #[case::auth(ExecuteError::AuthFailed("Invalid API key".into()), Policy::Abort)]
+    #[case::not_installed(ExecuteError::ImmichCliNotFound("immich".into()), Policy::Abort)]
+    #[case::tls(ExecuteError::Tls("CERT_HAS_EXPIRED".into()), Policy::Abort)]
+    #[case::disk_full(ExecuteError::ServerDiskFull("ENOSPC".into()), Policy::Abort)]
+    #[case::unreachable(ExecuteError::ServerUnreachable("ECONNREFUSED".into()), Policy::Retry)]
+    #[case::server_error(ExecuteError::ServerError("502".into()), Policy::Retry)]
+    #[case::partial(ExecuteError::PartialSuccess("2 of 12".into()), Policy::Retry)]
+    #[case::timed_out(ExecuteError::TimedOut("no output".into()), Policy::Retry)]
+    #[case::unsupported(ExecuteError::UnsupportedFile("psd".into()), Policy::Skip)]
+    #[case::other(ExecuteError::Other(anyhow::anyhow!("exit code 1")), Policy::Skip)]
+    fn test_policy(#[case] error: ExecuteError, #[case] expected: Policy) {
+        assert_eq!(policy(&error), expected);
+    }
+
+    #[rstest]
+    #[case::auth(ExecuteError::AuthFailed("Invalid API key".into()))]
+    #[case::disk_full(ExecuteError::ServerDiskFull("ENOSPC".into()))]
+    fn test_execute_album_aborts(#[case] error: ExecuteError) {
+        let mut executor = MockExecute::new();
+        let mut error = Some(error);
+        executor
+            .expect_execute()
+            .times(1)
+            .returning(move |_, _| Err(error.take().unwrap().into()));
+
+        let mut report = RunReport::default();
+        let result = execute_album(
+            &executor,
+            &album_args(),
+            "/base/2024/summer",
+            None,
+            &CancellationToken::new(),
+            &mut report,
+        );
+
+        assert!(result.is_err());
+        assert_eq!(report.count(Outcome::Failed), 1);
+    }
+
+    #[test]
+    fn test_execute_album_does_not_retry_when_cancelled() {
+        let mut executor = MockExecute::new();
+        executor
+            .expect_execute()
+            .times(1)
+            .returning(|_, _| Err(ExecuteError::ServerUnreachable("ECONNREFUSED".into()).into()));
+        let cancel = CancellationToken::new();
+        cancel.cancel(libc::SIGINT);
+
+        let mut report = RunReport::default();
+        execute_album(
+            &executor,
+            &album_args(),
+            "/base/2024/summer",
+            None,
+            &cancel,
+            &mut report,
+        )
+        .unwrap();
+
+        assert_eq!(report.count(Outcome::Failed), 1);
+    }
+
+    #[test]
+    fn test_execute_album_does_not_retry_failures() {
+        let mut executor = MockExecute::new();
+        executor.expect_execute().times(1).returning(|_, _| {
+            Err(UploadError {
+                error: ExecuteError::UnsupportedFile("1 of 3 files".into()),
+                stats: UploadStats {
+                    new: 3,
+                    uploaded: 2,
+                    failed: 1,
+                    ..Default::default()
+                },
+            })
+        });
+
+        let mut report = RunReport::default();
+        execute_album(
+            &executor,
+            &album_args(),
+            "/base/2024/summer",
+            None,
+            &CancellationToken::new(),
+            &mut report,
+        )
+        .unwrap();
+
+        assert_eq!(report.count(Outcome::Failed), 1);
+        // The files uploaded before the failure still count
+        assert_eq!(report.directories[0].stats.uploaded, 2);
+    }
+
+    #[test]
+    fn test_execute_album_counts_uploads_of_every_attempt() {
+        let mut executor = MockExecute::new();
+        let mut attempts = 0;
+        executor.expect_execute().times(2).returning(move |_, _| {
+            attempts += 1;
+            if attempts == 1 {
+                Err(UploadError {
+                    error: ExecuteError::PartialSuccess("2 of 12 files failed to upload".into()),
+                    stats: UploadStats {
+                        new: 12,
+                        uploaded: 10,
+                        failed: 2,
+                        ..Default::default()
+                    },
+                })
+            } else {
+                Ok(UploadStats {
+                    new: 2,
+                    duplicates: 10,
+                    uploaded: 2,
+                    ..Default::default()
+                })
+            }
+        });
+
+        let mut report = RunReport::default();
+        execute_album(
+            &executor,
+            &album_args(),
+            "/base/2024/summer",
+            None,
+            &CancellationToken::new(),
+            &mut report,
+        )
+        .unwrap();
+
+        assert_eq!(report.count(Outcome::Completed), 1);
+        assert_eq!(
+            report.directories[0].stats,
+            UploadStats {
+                new: 12,
+                uploaded: 12,
+                ..Default::default()
+            }
+        );
+    }
 }
@@ -0,0 +1,239 @@
+use serde::{Deserialize, Serialize};
+
+/// What an `immich upload` run reported about the files of a directory.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UploadStats {
+    /// Files not on the server yet.
+    pub new: usize,
+    /// Files the server already had.
+    pub duplicates: usize,
+    pub uploaded: usize,
+    pub failed: usize,
+    /// Size of the uploaded files, when the CLI reported it.
+    pub uploaded_bytes: Option<u64>,
+}
+
+impl UploadStats {
+    /// All files the CLI found.
+    pub fn found(&self) -> usize {
+        self.new + self.duplicates
+    }
+
+    /// Size of the uploads of `self` and `other` together, known if each either reported its
+    /// size or uploaded nothing.
+    pub fn uploaded_bytes_with(&self, other: &Self) -> Option<u64> {
+        match (self.uploaded_bytes, other.uploaded_bytes) {
+            (Some(a), Some(b)) => Some(a + b),
+            (a, None) if other.uploaded == 0 => a,
+            (None, b) if self.uploaded == 0 => b,
+            _ => None,
+        }
+    }
+
+    /// The statistics of a directory after `retry` followed the attempt that reported `self`.
+    /// Files uploaded by either attempt count. The files found are those of the first attempt
+    /// that found any, as a retry sees the uploaded ones as duplicates; the failed ones are
+    /// those of the last attempt that reported anything.
+    pub fn with_retry(&self, retry: &Self) -> Self {
+        let (new, duplicates) = if self.found() > 0 {
+            (self.new, self.duplicates)
+        } else {
+            (retry.new, retry.duplicates)
+        };
+        let retry_reported = retry.found() > 0 || retry.uploaded > 0 || retry.failed > 0;
+        Self {
+            new,
+            duplicates,
+            uploaded: self.uploaded + retry.uploaded,
+            failed: if retry_reported {
+                retry.failed
+            } else {
+                self.failed
+            },
+            uploaded_bytes: self.uploaded_bytes_with(retry),
+        }
+    }
+
+    /// Update the statistics from one line of CLI output. Lines that carry no counts are ignored.
+    ///
+    /// Understood formats:
+    /// - `Found 40 new files and 2 duplicates` (`Found 1 new file and 1 duplicate`)
+    /// - `Found 12 assets` (older CLIs, no duplicate detection)
+    /// - `Successfully uploaded 40 new assets (83.2 MB)` (`12 assets (31.5 MiB)`)
+    /// - `Failed to upload 2 assets:`
+    pub fn parse_line(&mut self, line: &str) {
+        // Progress bars redraw themselves with carriage returns; only the last state counts
+        let line = line.rsplit('\r').next().unwrap_or(line).trim();
+        let words: Vec<&str> = line.split_whitespace().collect();
+
+        match words.as_slice() {
+            ["Found", new, "new", "file" | "files", "and", duplicates, "duplicate" | "duplicates", ..] => {
+                if let (Ok(new), Ok(duplicates)) = (new.parse(), duplicates.parse()) {
+                    self.new = new;
+                    self.duplicates = duplicates;
+                }
+            }
+            ["Found", found, "asset" | "assets" | "file" | "files"] => {
+                if let Ok(found) = found.parse() {
+                    self.new = found;
+                }
+            }
+            ["Successfully", "uploaded", uploaded, rest @ ..] => {
+                if let Ok(uploaded) = uploaded.parse() {
+                    self.uploaded = uploaded;
+                    self.uploaded_bytes = parse_size(rest);
+                }
+            }
+            ["Failed", "to", "upload", failed, ..] => {
+                if let Ok(failed) = failed.parse() {
+                    self.failed = failed;
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// The `(83.2 MB)` at the end of an upload summary in bytes.
+fn parse_size(words: &[&str]) -> Option<u64> {
+    let [.., value, unit] = words else {
+        return None;
+    };
+    let value: f64 = value.strip_prefix('(')?.parse().ok()?;
+    let multiplier: u64 = match unit.strip_suffix(')')? {
+        "B" => 1,
+        "KB" | "kB" => 1_000,
+        "MB" => 1_000_000,
+        "GB" => 1_000_000_000,
+        "TB" => 1_000_000_000_000,
+        "KiB" => 1 << 10,
+        "MiB" => 1 << 20,
+        "GiB" => 1 << 30,
+        "TiB" => 1 << 40,
+        _ => return None,
+    };
+    Some((value * multiplier as f64).round() as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use rstest::rstest;
+
+    /// Parse every line of a run's output.
+    fn from_output<'a>(lines: impl IntoIterator<Item = &'a str>) -> UploadStats {
+        let mut stats = UploadStats::default();
+        for line in lines {
+            stats.parse_line(line);
+        }
+        stats
+    }
+
+    fn fixture(name: &str) -> String {
+        let path = format!(
+            "{}/fixtures/cli_output/{}.txt",
+            env!("CARGO_MANIFEST_DIR"),
+            name
+        );
+        std::fs::read_to_string(&path).unwrap_or_else(|e| panic!("Failed to read {path}: {e}"))
+    }
+
+    #[rstest]
+    #[case("success", 40, 2, 40, 0, Some(83_200_000))]
+    #[case("partial_success", 12, 0, 10, 2, Some(24_800_000))]
+    #[case("unsupported_file", 3, 0, 2, 1, Some(5_100_000))]
+    #[case("all_duplicates", 0, 42, 0, 0, None)]
+    #[case("legacy_upload", 12, 0, 12, 0, Some(33_030_144))]
+    #[case("progress_carriage_returns", 1, 1, 1, 0, Some(2_400_000))]
+    #[case("auth_not_logged_in", 0, 0, 0, 0, None)]
+    fn test_upload_stats_from_fixture(
+        #[case] name: &str,
+        #[case] new: usize,
+        #[case] duplicates: usize,
+        #[case] uploaded: usize,
+        #[case] failed: usize,
+        #[case] uploaded_bytes: Option<u64>,
+    ) {
+        let output = fixture(name);
+
+        let stats = from_output(output.lines());
+
+        assert_eq!(
+            stats,
+            UploadStats {
+                new,
+                duplicates,
+                uploaded,
+                failed,
+                uploaded_bytes,
+            }
+        );
+    }
+
+    #[rstest]
+    #[case(&["assets", "(1.5", "GiB)"], Some(1_610_612_736))]
+    #[case(&["assets", "(512", "B)"], Some(512))]
+    #[case(&["assets"], None)]
+    #[case(&["assets", "(many", "MB)"], None)]
+    #[case(&["assets", "(1", "parsecs)"], None)]
+    fn test_parse_size(#[case] words: &[&str], #[case] expected: Option<u64>) {
+        assert_eq!(parse_size(words), expected);
+    }
+
+    #[test]
+    fn test_with_retry_after_partial_success() {
+        let first = from_output(fixture("partial_success").lines());
+        let retry = from_output([
+            "Found 2 new files and 10 duplicates",
+            "Successfully uploaded 2 new assets (4.2 MB)",
+        ]);
+
+        assert_eq!(
+            first.with_retry(&retry),
+            UploadStats {
+                new: 12,
+                duplicates: 0,
+                uploaded: 12,
+                failed: 0,
+                uploaded_bytes: Some(29_000_000),
+            }
+        );
+    }
+
+    #[test]
+    fn test_with_retry_that_reported_nothing() {
+        let first = from_output(fixture("partial_success").lines());
+
+        assert_eq!(first.with_retry(&UploadStats::default()), first);
+    }
+
+    #[rstest]
+    #[case(Some(10), 1, Some(5), 1, Some(15))]
+    #[case(Some(10), 1, None, 0, Some(10))]
+    #[case(None, 0, Some(5), 1, Some(5))]
+    #[case(Some(10), 1, None, 1, None)]
+    fn test_uploaded_bytes_with(
+        #[case] bytes: Option<u64>,
+        #[case] uploaded: usize,
+        #[case] other_bytes: Option<u64>,
+        #[case] other_uploaded: usize,
+        #[case] expected: Option<u64>,
+    ) {
+        let stats = |uploaded_bytes, uploaded| UploadStats {
+            uploaded,
+            uploaded_bytes,
+            ..UploadStats::default()
+        };
+        assert_eq!(
+            stats(bytes, uploaded).uploaded_bytes_with(&stats(other_bytes, other_uploaded)),
+            expected
+        );
+    }
+
+    #[test]
+    fn test_found_counts_new_and_duplicates() {
+        let stats = from_output(["Found 3 new files and 4 duplicates"]);
+        assert_eq!(stats.found(), 7);
+    }
+}
@@ -0,0 +1,160 @@
+use crate::prelude::*;
+
+/// The directories that belong to one Immich account.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UserRoute {
+    pub name: Box<str>,
+    /// Patterns without a `/` match the child directory (`alice`), patterns with one the child
+    /// and grandchild (`shared/alice-*`). `*` matches any run of characters, `?` a single one.
+    pub patterns: Vec<Box<str>>,
+}
+
+impl UserRoute {
+    /// Whether the album directory `relative`, given relative to the base path, belongs to this
+    /// user.
+    pub fn matches(&self, relative: &Path) -> bool {
+        let mut components = relative.iter().map(|c| c.to_string_lossy());
+        let Some(child) = components.next() else {
+            return false;
+        };
+        let full = relative.to_string_lossy();
+
+        self.patterns.iter().any(|pattern| {
+            if pattern.contains('/') {
+                glob_match(pattern, &full)
+            } else {
+                glob_match(pattern, &child)
+            }
+        })
+    }
+}
+
+/// The executor for every Immich account of a run. Albums no user claims use `default`.
+pub struct Accounts<E> {
+    pub default: E,
+    pub users: Vec<(UserRoute, E)>,
+}
+
+impl<E> Accounts<E> {
+    pub fn single(default: E) -> Self {
+        Self {
+            default,
+            users: Vec::new(),
+        }
+    }
+
+    /// The user `album_path` belongs to and the executor to upload it with. Fails when more than
+    /// one user claims the directory.
+    pub fn for_album(&self, base_path: &Path, album_path: &Path) -> Result<(Option<&str>, &E)> {
+        let relative = album_path.strip_prefix(base_path).unwrap_or(album_path);
+        let mut matches = self
+            .users
+            .iter()
+            .filter(|(route, _)| route.matches(relative));
+
+        match (matches.next(), matches.next()) {
+            (None, _) => Ok((None, &self.default)),
+            (Some((route, executor)), None) => Ok((Some(&route.name), executor)),
+            (Some((first, _)), Some((second, _))) => bail!(
+                "{} matches the directories of both users {} and {}",
+                album_path.display(),
+                first.name,
+                second.name
+            ),
+        }
+    }
+}
+
+/// Match `text` against a pattern of literal characters, `*` and `?`.
+fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    let (mut p, mut t) = (0, 0);
+    // Position after the last `*` and the text position it has consumed up to
+    let mut backtrack: Option<(usize, usize)> = None;
+
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                p += 1;
+                backtrack = Some((p, t));
+            }
+            Some(&c) if c == '?' || c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match backtrack {
+                Some((star_p, star_t)) => {
+                    p = star_p;
+                    t = star_t + 1;
+                    backtrack = Some((star_p, star_t + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use rstest::rstest;
+
+    #[rstest]
+    #[case("alice", "alice", true)]
+    #[case("alice", "alice2", false)]
+    #[case("alice*", "alice-phone", true)]
+    #[case("*-bob", "phone-bob", true)]
+    #[case("a?ice", "alice", true)]
+    #[case("*", "", true)]
+    #[case("shared/*bob*", "shared/2024 bob trip", true)]
+    #[case("shared/*bob*", "shared/alice", false)]
+    fn test_glob_match(#[case] pattern: &str, #[case] text: &str, #[case] expected: bool) {
+        assert_eq!(glob_match(pattern, text), expected);
+    }
+
+    fn accounts() -> Accounts<&'static str> {
+        let route = |name: &str, patterns: &[&str]| UserRoute {
+            name: name.into(),
+            patterns: patterns.iter().map(|p| (*p).into()).collect(),
+        };
+        Accounts {
+            default: "default",
+            users: vec![
+                (route("alice", &["alice", "shared/alice-*"]), "alice"),
+                (route("bob", &["bob", "shared/*bob*"]), "bob"),
+            ],
+        }
+    }
+
+    #[rstest]
+    #[case("/photos/alice/summer", Some("alice"))]
+    #[case("/photos/shared/alice-birthday", Some("alice"))]
+    #[case("/photos/bob/other", Some("bob"))]
+    #[case("/photos/shared/family", None)]
+    #[case("/photos/2024/summer", None)]
+    fn test_for_album(#[case] album_path: &str, #[case] expected: Option<&str>) {
+        let accounts = accounts();
+
+        let (user, executor) = accounts
+            .for_album(Path::new("/photos"), Path::new(album_path))
+            .unwrap();
+
+        assert_eq!(user, expected);
+        assert_eq!(*executor, expected.unwrap_or("default"));
+    }
+
+    #[test]
+    fn test_for_album_refuses_ambiguous_directories() {
+        let accounts = accounts();
+        assert!(accounts
+            .for_album(
+                Path::new("/photos"),
+                Path::new("/photos/shared/alice-and-bob")
+            )
+            .is_err());
+    }
+}